@@ -0,0 +1,65 @@
+//! Measure the time and the number of heap allocations it takes to convert the
+//! test files.
+//!
+//! Run with `cargo bench`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Counts the allocations made through the system allocator.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const ITERATIONS: u32 = 50;
+
+fn main() {
+    let mut paths: Vec<_> = std::fs::read_dir("tests")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "svg"))
+        .collect();
+    paths.sort();
+
+    println!("{:<24} {:>12} {:>14}", "file", "time", "allocations");
+    for path in paths {
+        let svg = std::fs::read_to_string(&path).unwrap();
+        let opt = usvg::Options {
+            resources_dir: path.parent().map(Into::into),
+            ..usvg::Options::default()
+        };
+        let tree = usvg::Tree::from_str(&svg, &opt.to_ref()).unwrap();
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        for _ in 0 .. ITERATIONS {
+            svg2pdf::convert_tree(&tree, svg2pdf::Options::default());
+        }
+
+        let time = start.elapsed() / ITERATIONS;
+        let allocations =
+            (ALLOCATIONS.load(Ordering::Relaxed) - before) / ITERATIONS as usize;
+        let name = path.file_name().unwrap().to_string_lossy();
+        println!("{:<24} {:>12.2?} {:>14}", name, time, allocations);
+    }
+}
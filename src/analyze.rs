@@ -0,0 +1,238 @@
+//! Report which SVG features a tree uses before converting it.
+
+use std::collections::BTreeMap;
+
+use usvg::{ImageKind, NodeKind, Paint, SpreadMethod, Tree};
+
+/// An SVG feature that svg2pdf distinguishes in its [`FeatureReport`].
+///
+/// Text does not show up here because usvg converts it to paths while parsing.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Feature {
+    /// Filled or stroked paths. Paths in definitions like clip paths and
+    /// patterns are not counted.
+    Path,
+    /// Groups with an opacity below one.
+    GroupOpacity,
+    /// Linear gradients used as fills or strokes.
+    LinearGradient,
+    /// Radial gradients used as fills or strokes.
+    RadialGradient,
+    /// Gradients with a `gradientTransform`. The transform is ignored.
+    GradientTransform,
+    /// Gradients with a `spreadMethod` of `reflect` or `repeat`.
+    SpreadMethod,
+    /// Patterns used as fills or strokes.
+    Pattern,
+    /// Clip paths.
+    ClipPath,
+    /// Clip paths with a `transform` or `clipPathUnits="objectBoundingBox"`.
+    /// The transform and units are ignored.
+    ClipPathTransform,
+    /// Masks.
+    Mask,
+    /// Filters. The filtered element is drawn without the filter.
+    ///
+    /// Only reported with the `filter` feature. Without it, usvg ignores
+    /// `filter` attributes while parsing, so they never reach the tree.
+    Filter,
+    /// Embedded JPEG images. Requires the `jpeg` feature, skipped otherwise.
+    JpegImage,
+    /// Embedded PNG images. Requires the `png` feature, skipped otherwise.
+    PngImage,
+    /// Embedded GIF images. Requires the `gif` feature, skipped otherwise.
+    GifImage,
+    /// Embedded SVG images, which are converted to vector graphics as well.
+    SvgImage,
+}
+
+impl Feature {
+    /// How svg2pdf handles the feature.
+    pub fn support(self) -> Support {
+        match self {
+            Self::Path
+            | Self::GroupOpacity
+            | Self::LinearGradient
+            | Self::RadialGradient
+            | Self::Pattern
+            | Self::ClipPath
+            | Self::Mask
+            | Self::SpreadMethod
+            | Self::SvgImage => Support::Full,
+            Self::JpegImage if cfg!(feature = "jpeg") => Support::Full,
+            Self::PngImage if cfg!(feature = "png") => Support::Full,
+            Self::GifImage if cfg!(feature = "gif") => Support::Full,
+            Self::GradientTransform | Self::ClipPathTransform => Support::Partial,
+            Self::Filter | Self::JpegImage | Self::PngImage | Self::GifImage => {
+                Support::Unsupported
+            }
+        }
+    }
+}
+
+/// How well svg2pdf converts a feature.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Support {
+    /// The feature is converted faithfully.
+    Full,
+    /// The feature is converted, but some of its aspects are lost.
+    Partial,
+    /// The feature is not converted at all.
+    Unsupported,
+}
+
+/// Which features a tree uses and how often, as returned by [`analyze`].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct FeatureReport {
+    /// How often each of the used features occurs.
+    counts: BTreeMap<Feature, usize>,
+}
+
+impl FeatureReport {
+    /// How often the tree uses a feature.
+    pub fn count(&self, feature: Feature) -> usize {
+        self.counts.get(&feature).copied().unwrap_or(0)
+    }
+
+    /// Iterate over the used features, how often they occur, and how svg2pdf
+    /// handles them.
+    pub fn iter(&self) -> impl Iterator<Item = (Feature, usize, Support)> + '_ {
+        self.counts
+            .iter()
+            .map(|(&feature, &count)| (feature, count, feature.support()))
+    }
+
+    /// Whether all used features will be converted faithfully.
+    pub fn is_fully_supported(&self) -> bool {
+        self.iter().all(|(_, _, support)| support == Support::Full)
+    }
+
+    /// Record a use of a feature.
+    fn add(&mut self, feature: Feature) {
+        *self.counts.entry(feature).or_default() += 1;
+    }
+}
+
+/// Report which features a [`usvg` tree](Tree) uses and how svg2pdf would
+/// handle them.
+///
+/// This allows applications to pre-flight uploaded files and warn about lossy
+/// conversions before converting them.
+///
+/// ## Example
+/// ```rust
+/// use svg2pdf::{Feature, Support};
+///
+/// let svg = std::fs::read_to_string("tests/mask.svg").unwrap();
+/// let tree = usvg::Tree::from_str(&svg, &usvg::Options::default().to_ref()).unwrap();
+///
+/// let report = svg2pdf::analyze(&tree);
+/// assert!(report.count(Feature::Mask) > 0);
+///
+/// for (feature, count, support) in report.iter() {
+///     if support != Support::Full {
+///         println!("{:?} is used {} times but not fully supported", feature, count);
+///     }
+/// }
+/// ```
+pub fn analyze(tree: &Tree) -> FeatureReport {
+    let mut report = FeatureReport::default();
+
+    // The contents of clip paths, masks and patterns are only drawn through
+    // the elements that reference them.
+    for node in tree.root().descendants().filter(|node| !tree.is_in_defs(node)) {
+        match *node.borrow() {
+            NodeKind::Path(ref path) => {
+                report.add(Feature::Path);
+
+                let fill = path.fill.as_ref().map(|fill| &fill.paint);
+                let stroke = path.stroke.as_ref().map(|stroke| &stroke.paint);
+                for paint in fill.into_iter().chain(stroke) {
+                    if let Paint::Link(id) = paint {
+                        analyze_paint(tree, id, &mut report);
+                    }
+                }
+            }
+            NodeKind::Group(ref group) => {
+                if group.opacity.value() != 1.0 {
+                    report.add(Feature::GroupOpacity);
+                }
+
+                if let Some(id) = &group.clip_path {
+                    analyze_clip_path(tree, id, &mut report);
+                }
+
+                if group.mask.is_some() {
+                    report.add(Feature::Mask);
+                }
+
+                for _ in &group.filter {
+                    report.add(Feature::Filter);
+                }
+            }
+            NodeKind::Image(ref image) => report.add(match image.kind {
+                ImageKind::JPEG(_) => Feature::JpegImage,
+                ImageKind::PNG(_) => Feature::PngImage,
+                ImageKind::GIF(_) => Feature::GifImage,
+                ImageKind::SVG(_) => Feature::SvgImage,
+            }),
+            _ => {}
+        }
+    }
+
+    report
+}
+
+/// Record the features of a paint server.
+fn analyze_paint(tree: &Tree, id: &str, report: &mut FeatureReport) {
+    let node = match tree.defs_by_id(id) {
+        Some(node) => node,
+        None => return,
+    };
+
+    let base = match *node.borrow() {
+        NodeKind::LinearGradient(ref lg) => {
+            report.add(Feature::LinearGradient);
+            lg.base.clone()
+        }
+        NodeKind::RadialGradient(ref rg) => {
+            report.add(Feature::RadialGradient);
+            rg.base.clone()
+        }
+        NodeKind::Pattern(_) => {
+            report.add(Feature::Pattern);
+            return;
+        }
+        _ => return,
+    };
+
+    if !base.transform.is_default() {
+        report.add(Feature::GradientTransform);
+    }
+
+    if base.spread_method != SpreadMethod::Pad {
+        report.add(Feature::SpreadMethod);
+    }
+}
+
+/// Record the features of a clip path and the clip paths it is clipped by.
+fn analyze_clip_path(tree: &Tree, id: &str, report: &mut FeatureReport) {
+    let node = match tree.defs_by_id(id) {
+        Some(node) => node,
+        None => return,
+    };
+
+    if let NodeKind::ClipPath(ref clip_path) = *node.borrow() {
+        report.add(Feature::ClipPath);
+
+        if !clip_path.transform.is_default()
+            || clip_path.units == usvg::Units::ObjectBoundingBox
+        {
+            report.add(Feature::ClipPathTransform);
+        }
+
+        if let Some(id) = &clip_path.clip_path {
+            analyze_clip_path(tree, id, report);
+        }
+    };
+}
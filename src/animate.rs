@@ -0,0 +1,483 @@
+//! Resolve SMIL animations to their state at a point in time.
+//!
+//! usvg ignores animation elements and always yields the initial state of a
+//! document. To convert a different frame, the animated attributes are
+//! rewritten in the source string before it is parsed.
+
+use std::ops::Range;
+
+use roxmltree::{Document, Node};
+
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+/// Attributes whose values are colors and are interpolated as such.
+const COLOR_ATTRIBUTES: &[&str] = &[
+    "fill",
+    "stroke",
+    "stop-color",
+    "flood-color",
+    "lighting-color",
+    "color",
+];
+
+/// Rewrite an SVG source string such that its animated attributes take on
+/// their values at `time` seconds after the document began.
+///
+/// This supports `set`, `animate` and `animateTransform` elements with clock
+/// values as begin times. Spline and paced interpolation are approximated
+/// linearly, CSS animations are not supported. Returns `None` if the source is
+/// not valid XML or has no animation elements.
+pub(crate) fn snapshot(src: &str, time: f64) -> Option<String> {
+    if !src.contains("set") && !src.contains("animate") {
+        return None;
+    }
+
+    let doc = Document::parse(src).ok()?;
+
+    // The animated values per target element and attribute. Later animations
+    // build on the results of earlier ones.
+    let mut animated: Vec<(Node, &str, String)> = vec![];
+
+    for anim in doc.descendants().filter(|node| node.is_element()) {
+        let kind = anim.tag_name().name();
+        if !matches!(kind, "set" | "animate" | "animateTransform") {
+            continue;
+        }
+
+        let target = match target(anim) {
+            Some(target) => target,
+            None => continue,
+        };
+
+        let name = match anim.attribute("attributeName") {
+            Some(name) => name,
+            None => continue,
+        };
+
+        let index = animated
+            .iter()
+            .position(|(node, attr, _)| *node == target && *attr == name);
+
+        let base = match index {
+            Some(i) => Some(animated[i].2.clone()),
+            None => base_value(target, name),
+        };
+
+        if let Some(value) = sample(anim, kind, name, base.as_deref(), time) {
+            match index {
+                Some(i) => animated[i].2 = value,
+                None => animated.push((target, name, value)),
+            }
+        }
+    }
+
+    let mut edits: Vec<(Range<usize>, String)> = vec![];
+    let mut targets: Vec<Node> = vec![];
+    for &(node, ..) in &animated {
+        if !targets.contains(&node) {
+            targets.push(node);
+        }
+    }
+
+    for node in targets {
+        let values = animated
+            .iter()
+            .filter(|(target, ..)| *target == node)
+            .map(|(_, name, value)| (*name, value));
+
+        let mut styled = String::new();
+        for (name, value) in values {
+            if style_value(node, name).is_some() {
+                styled.push_str(&format!(";{}:{}", name, value));
+            } else if let Some(attr) =
+                node.attributes().iter().find(|attr| attr.name() == name)
+            {
+                edits.push((attr.value_range(), escape(value)));
+            } else {
+                let pos = name_end(src, node.range().start);
+                edits.push((pos .. pos, format!(" {}=\"{}\"", name, escape(value))));
+            }
+        }
+
+        if let Some(style) = node.attributes().iter().find(|attr| attr.name() == "style")
+        {
+            if !styled.is_empty() {
+                let end = style.value_range().end;
+                edits.push((end .. end, escape(&styled)));
+            }
+        }
+    }
+
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+
+    let mut out = src.to_string();
+    for (range, text) in edits {
+        out.replace_range(range, &text);
+    }
+
+    Some(out)
+}
+
+/// The element an animation applies to.
+fn target<'a, 'input>(anim: Node<'a, 'input>) -> Option<Node<'a, 'input>> {
+    let href = anim.attribute((XLINK_NS, "href")).or_else(|| anim.attribute("href"));
+
+    match href {
+        Some(href) => {
+            let id = href.strip_prefix('#')?;
+            anim.document()
+                .root()
+                .descendants()
+                .find(|node| node.attribute("id") == Some(id))
+        }
+        None => anim.parent_element(),
+    }
+}
+
+/// The value of an attribute or property before it is animated.
+fn base_value(node: Node, name: &str) -> Option<String> {
+    style_value(node, name)
+        .or_else(|| node.attribute(name))
+        .map(ToString::to_string)
+}
+
+/// The value of a property in the `style` attribute of an element.
+fn style_value<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attribute("style")?.split(';').rev().find_map(|decl| {
+        let (key, value) = decl.split_once(':')?;
+        (key.trim() == name).then(|| value.trim())
+    })
+}
+
+/// The position right after the tag name of an element's start tag.
+pub(crate) fn name_end(src: &str, start: usize) -> usize {
+    let rest = &src[start + 1 ..];
+    let len = rest
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .unwrap_or(rest.len());
+    start + 1 + len
+}
+
+/// Escape a value for use in a quoted attribute.
+pub(crate) fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// The time in seconds at which the last animation of an SVG source string
+/// ends or, for repeating animations, completes its first iteration.
+///
+/// Returns `None` if the source is not valid XML or has no animations that
+/// begin at a fixed time.
+pub(crate) fn duration(src: &str) -> Option<f64> {
+    let doc = Document::parse(src).ok()?;
+    doc.descendants()
+        .filter(|node| node.is_element())
+        .filter_map(|anim| {
+            let kind = anim.tag_name().name();
+            if !matches!(kind, "set" | "animate" | "animateTransform") {
+                return None;
+            }
+
+            let (begin, dur, active) = timing(anim, kind)?;
+            let end = if active.is_finite() { active } else { dur.unwrap_or(0.0) };
+            Some(begin + end)
+        })
+        .reduce(f64::max)
+}
+
+/// The begin time, simple duration and active duration of an animation.
+fn timing(anim: Node, kind: &str) -> Option<(f64, Option<f64>, f64)> {
+    let begin = anim
+        .attribute("begin")
+        .unwrap_or("0s")
+        .split(';')
+        .filter_map(clock_value)
+        .reduce(f64::min)?;
+
+    let dur = anim.attribute("dur").and_then(clock_value).filter(|&dur| dur > 0.0);
+    let repeat_count = anim.attribute("repeatCount").map(|count| match count.trim() {
+        "indefinite" => f64::INFINITY,
+        count => count.parse().unwrap_or(1.0),
+    });
+
+    let repeat_dur = anim
+        .attribute("repeatDur")
+        .map(|dur| clock_value(dur).unwrap_or(f64::INFINITY));
+
+    let mut active = match (dur, repeat_count, repeat_dur) {
+        (Some(dur), None, None) => dur,
+        (Some(dur), count, repeat) => {
+            (dur * count.unwrap_or(f64::INFINITY)).min(repeat.unwrap_or(f64::INFINITY))
+        }
+        (None, ..) if kind == "set" => f64::INFINITY,
+        (None, ..) => return None,
+    };
+
+    if let Some(end) = anim
+        .attribute("end")
+        .and_then(|end| end.split(';').filter_map(clock_value).reduce(f64::min))
+    {
+        active = active.min(end - begin);
+    }
+
+    Some((begin, dur, active))
+}
+
+/// Compute the value of an animated attribute at a point in time. Returns
+/// `None` if the animation has no effect at that time.
+fn sample(
+    anim: Node,
+    kind: &str,
+    name: &str,
+    base: Option<&str>,
+    time: f64,
+) -> Option<String> {
+    let (begin, dur, active) = timing(anim, kind)?;
+    if time < begin {
+        return None;
+    }
+
+    let elapsed = time - begin;
+    let progress = if elapsed < active {
+        dur.map_or(0.0, |dur| (elapsed % dur) / dur)
+    } else if anim.attribute("fill") == Some("freeze") {
+        match dur {
+            Some(dur) if active % dur != 0.0 => (active % dur) / dur,
+            _ => 1.0,
+        }
+    } else {
+        return None;
+    };
+
+    if kind == "set" {
+        return anim.attribute("to").map(ToString::to_string);
+    }
+
+    let values: Vec<String> = if let Some(values) = anim.attribute("values") {
+        values
+            .split(';')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(ToString::to_string)
+            .collect()
+    } else {
+        let from = anim.attribute("from").map(ToString::to_string);
+        let to = anim.attribute("to").map(ToString::to_string).or_else(|| {
+            let by = anim.attribute("by")?.trim().parse::<f64>().ok()?;
+            let from = from.as_deref().or(base)?.trim().parse::<f64>().ok()?;
+            Some(format_number(from + by))
+        });
+
+        match (from.or_else(|| base.map(ToString::to_string)), to) {
+            (Some(from), Some(to)) => vec![from, to],
+            (None, Some(to)) => vec![to],
+            _ => return None,
+        }
+    };
+
+    let key_times: Option<Vec<f64>> = anim.attribute("keyTimes").map(|times| {
+        times.split(';').filter_map(|time| time.trim().parse().ok()).collect()
+    });
+
+    let key_times = key_times.filter(|times| times.len() == values.len());
+    let discrete = anim.attribute("calcMode") == Some("discrete");
+    let value =
+        interpolate_values(&values, key_times.as_deref(), progress, discrete, name);
+
+    let value = if kind == "animateTransform" {
+        let ty = anim.attribute("type").unwrap_or("translate");
+        format!("{}({})", ty, value)
+    } else {
+        value
+    };
+
+    match (anim.attribute("additive"), base) {
+        (Some("sum"), Some(base)) if kind == "animateTransform" => {
+            Some(format!("{} {}", base, value))
+        }
+        (Some("sum"), Some(base)) => match (base.parse::<f64>(), value.parse::<f64>()) {
+            (Ok(base), Ok(value)) => Some(format_number(base + value)),
+            _ => Some(value),
+        },
+        _ => Some(value),
+    }
+}
+
+/// Pick or interpolate the value of an animation at a progress between zero
+/// and one within the current iteration.
+fn interpolate_values(
+    values: &[String],
+    key_times: Option<&[f64]>,
+    progress: f64,
+    discrete: bool,
+    name: &str,
+) -> String {
+    let n = values.len();
+    if n == 1 || progress >= 1.0 {
+        return values[n - 1].clone();
+    }
+
+    if discrete {
+        let index = match key_times {
+            Some(times) => times.iter().rposition(|&t| t <= progress).unwrap_or(0),
+            None => ((progress * n as f64) as usize).min(n - 1),
+        };
+        return values[index].clone();
+    }
+
+    let (index, t) = match key_times {
+        Some(times) => {
+            let index = times.windows(2).position(|w| progress < w[1]).unwrap_or(n - 2);
+            let span = times[index + 1] - times[index];
+            let t = if span > 0.0 {
+                (progress - times[index]) / span
+            } else {
+                1.0
+            };
+            (index, t)
+        }
+        None => {
+            let scaled = progress * (n - 1) as f64;
+            let index = (scaled as usize).min(n - 2);
+            (index, scaled - index as f64)
+        }
+    };
+
+    interpolate(&values[index], &values[index + 1], t.clamp(0.0, 1.0), name)
+}
+
+/// Interpolate between two values of an attribute. Values that cannot be
+/// interpolated switch halfway.
+fn interpolate(a: &str, b: &str, t: f64, name: &str) -> String {
+    let discrete = || if t < 0.5 { a.to_string() } else { b.to_string() };
+
+    if COLOR_ATTRIBUTES.contains(&name) {
+        return match (a.parse::<svgtypes::Color>(), b.parse::<svgtypes::Color>()) {
+            (Ok(x), Ok(y)) => {
+                let mix = |x: u8, y: u8| (x as f64 + (y as f64 - x as f64) * t).round();
+                format!(
+                    "rgb({},{},{})",
+                    mix(x.red, y.red),
+                    mix(x.green, y.green),
+                    mix(x.blue, y.blue)
+                )
+            }
+            _ => discrete(),
+        };
+    }
+
+    let (x, template) = split_numbers(a);
+    let (y, other) = split_numbers(b);
+    if x.is_empty() || x.len() != y.len() || template != other {
+        return discrete();
+    }
+
+    // Only separators and units may surround the numbers.
+    if !template
+        .iter()
+        .flat_map(|piece| piece.chars())
+        .all(|c| c.is_whitespace() || c.is_ascii_alphabetic() || c == ',' || c == '%')
+    {
+        return discrete();
+    }
+
+    let mut out = template[0].clone();
+    for ((x, y), piece) in x.iter().zip(&y).zip(&template[1 ..]) {
+        out.push_str(&format_number(x + (y - x) * t));
+        out.push_str(piece);
+    }
+
+    out
+}
+
+/// Split a value into its numbers and the text around them. The text has one
+/// more element than there are numbers.
+fn split_numbers(value: &str) -> (Vec<f64>, Vec<String>) {
+    let bytes = value.as_bytes();
+    let mut numbers = vec![];
+    let mut template = vec![String::new()];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        if matches!(bytes[i], b'+' | b'-') {
+            i += 1;
+        }
+
+        let digits = i;
+        while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+            i += 1;
+        }
+
+        if i > digits && bytes[digits .. i].iter().any(u8::is_ascii_digit) {
+            if i < bytes.len() && matches!(bytes[i], b'e' | b'E') {
+                let mut j = i + 1;
+                if j < bytes.len() && matches!(bytes[j], b'+' | b'-') {
+                    j += 1;
+                }
+                if j < bytes.len() && bytes[j].is_ascii_digit() {
+                    i = j;
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+
+            if let Ok(number) = value[start .. i].parse() {
+                numbers.push(number);
+                template.push(String::new());
+                continue;
+            }
+        }
+
+        i = start + 1;
+        while !value.is_char_boundary(i) {
+            i += 1;
+        }
+        template.last_mut().unwrap().push_str(&value[start .. i]);
+    }
+
+    (numbers, template)
+}
+
+/// Parse an SMIL clock value like `2s`, `150ms` or `00:01:30` into seconds.
+fn clock_value(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if value.contains(':') {
+        return value.split(':').try_fold(0.0, |acc, part| {
+            Some(acc * 60.0 + part.parse::<f64>().ok()?)
+        });
+    }
+
+    let (number, factor) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(s) = value.strip_suffix('s') {
+        (s, 1.0)
+    } else if let Some(min) = value.strip_suffix("min") {
+        (min, 60.0)
+    } else if let Some(h) = value.strip_suffix('h') {
+        (h, 3600.0)
+    } else {
+        (value, 1.0)
+    };
+
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|seconds| seconds.is_finite())
+        .map(|seconds| seconds * factor)
+}
+
+/// Format a number without superfluous digits.
+fn format_number(number: f64) -> String {
+    let rounded = (number * 1e6).round() / 1e6;
+    if rounded == 0.0 {
+        "0".into()
+    } else {
+        rounded.to_string()
+    }
+}
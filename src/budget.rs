@@ -0,0 +1,123 @@
+//! Warn about conversions whose output exceeds size thresholds.
+
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::Arc;
+
+/// Thresholds for the size of a converted document.
+///
+/// Services can use them to flag pathological SVGs that produce unwieldy
+/// PDFs. Each threshold that the output exceeds results in a
+/// [`BudgetWarning`], which is passed to the [handler](Self::handler) or
+/// logged.
+#[derive(Debug, Clone, Default)]
+pub struct Budget {
+    /// The maximum number of indirect objects.
+    pub objects: Option<usize>,
+    /// The maximum number of image and Form XObjects.
+    pub xobjects: Option<usize>,
+    /// The maximum number of bytes in content and image streams before
+    /// compression.
+    pub stream_bytes: Option<usize>,
+    /// The maximum number of pages.
+    pub pages: Option<usize>,
+    /// Receives the warnings. If this is `None`, they are logged instead.
+    pub handler: Option<BudgetHandler>,
+}
+
+/// A measure of the size of a document.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Measure {
+    /// The number of indirect objects.
+    Objects,
+    /// The number of image and Form XObjects.
+    XObjects,
+    /// The number of bytes in content and image streams before compression.
+    StreamBytes,
+    /// The number of pages.
+    Pages,
+}
+
+/// A threshold of a [`Budget`] that the output exceeds.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BudgetWarning {
+    /// What was measured.
+    pub measure: Measure,
+    /// The size of the output.
+    pub value: usize,
+    /// The threshold it exceeds.
+    pub limit: usize,
+}
+
+impl Display for BudgetWarning {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let what = match self.measure {
+            Measure::Objects => "objects",
+            Measure::XObjects => "XObjects",
+            Measure::StreamBytes => "bytes of uncompressed streams",
+            Measure::Pages => "pages",
+        };
+
+        write!(
+            f,
+            "The output has {} {}, more than the budget of {}.",
+            self.value, what, self.limit,
+        )
+    }
+}
+
+/// Receives the [warnings](BudgetWarning) of a [`Budget`].
+#[derive(Clone)]
+pub struct BudgetHandler(Arc<HandlerFn>);
+
+/// The callback of a [`BudgetHandler`].
+type HandlerFn = dyn Fn(&BudgetWarning) + Send + Sync;
+
+impl BudgetHandler {
+    /// Create a handler from a callback.
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&BudgetWarning) + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+}
+
+impl Debug for BudgetHandler {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.pad("BudgetHandler(..)")
+    }
+}
+
+/// The size of a written document.
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct Usage {
+    pub objects: usize,
+    pub xobjects: usize,
+    pub stream_bytes: usize,
+    pub pages: usize,
+}
+
+impl Budget {
+    /// Report the thresholds that a document exceeds.
+    pub(crate) fn check(&self, usage: &Usage) {
+        let measures = [
+            (Measure::Objects, usage.objects, self.objects),
+            (Measure::XObjects, usage.xobjects, self.xobjects),
+            (Measure::StreamBytes, usage.stream_bytes, self.stream_bytes),
+            (Measure::Pages, usage.pages, self.pages),
+        ];
+
+        for (measure, value, limit) in measures {
+            let limit = match limit {
+                Some(limit) if value > limit => limit,
+                _ => continue,
+            };
+
+            let warning = BudgetWarning { measure, value, limit };
+            match &self.handler {
+                Some(handler) => handler.0(&warning),
+                None => log::warn!("{}", warning),
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Flatten clip paths into the geometry that they clip.
+//!
+//! Some viewers and plotters do not support clipping paths. Instead of setting
+//! one, the paths in a clipped group can be intersected with the clip region,
+//! which results in self-contained fills. This works for clip paths made up of
+//! a single convex shape, like the rectangles that crop most maps and
+//! diagrams, and for groups whose content consists of fills.
+
+use std::f64::consts::PI;
+
+use pdf_writer::Content;
+use usvg::{NodeKind, PathData, PathSegment, Transform};
+
+use crate::outline::{flatten, length, lerp, sub, Point, TOLERANCE};
+use crate::scale::CoordToPdf;
+use crate::Context;
+
+/// Compute the regions that the clip path of a group consists of, as
+/// counter-clockwise convex polygons in PDF coordinates.
+///
+/// Returns `None` if the group has no clip path or it cannot be flattened,
+/// in which case it is applied as a clipping path.
+pub(crate) fn clip_regions(
+    node: &usvg::Node,
+    group: &usvg::Group,
+    ctx: &mut Context,
+) -> Option<Vec<Vec<Point>>> {
+    let id = group.clip_path.as_ref()?;
+
+    // Strokes cannot be intersected, but their outlines can.
+    let fills = node.descendants().skip(1).all(|child| match *child.borrow() {
+        NodeKind::Path(ref path) => path.stroke.is_none() || ctx.outline_strokes,
+        NodeKind::Group(_) => true,
+        _ => false,
+    });
+
+    let ts = group.transform;
+    let old = ctx.c.transform([ts.a, ts.b, ts.c, ts.d, ts.e, ts.f]);
+    let regions = fills.then(|| convex_regions(id, ctx)).flatten();
+    ctx.c.transform(old);
+
+    if regions.is_none() {
+        log::warn!("Could not flatten clip path '{}', applying it instead.", id);
+    }
+
+    regions
+}
+
+/// Collect the shapes of a clip path and the clip paths it is clipped by,
+/// provided that each consists of a single convex shape.
+fn convex_regions(id: &str, ctx: &Context) -> Option<Vec<Vec<Point>>> {
+    let mut regions = vec![];
+    let mut next = Some(id.to_string());
+
+    while let Some(node) = next.take().and_then(|id| ctx.tree.defs_by_id(&id)) {
+        if let NodeKind::ClipPath(ref clip_path) = *node.borrow() {
+            next = clip_path.clip_path.clone();
+        }
+
+        let mut children = node.children();
+        let region = match (children.next(), children.next()) {
+            (Some(child), None) => match *child.borrow() {
+                NodeKind::Path(ref path) => {
+                    convex_ring(&path.data, path.transform, &ctx.c)?
+                }
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        regions.push(region);
+    }
+
+    Some(regions)
+}
+
+/// Flatten a path into a counter-clockwise polygon if it consists of a single
+/// convex shape.
+fn convex_ring(
+    data: &PathData,
+    transform: Transform,
+    c: &CoordToPdf,
+) -> Option<Vec<Point>> {
+    let mut lines = flatten(&to_pdf(data, transform, c), TOLERANCE);
+    if lines.len() != 1 {
+        return None;
+    }
+
+    let (mut ring, _) = lines.pop()?;
+    if area(&ring) < 0.0 {
+        ring.reverse();
+    }
+
+    // A polygon is convex if it only turns left and does so only once, which
+    // rules out stars. Rounded coordinates may bend straight edges slightly
+    // the wrong way.
+    let n = ring.len();
+    let mut turning = 0.0;
+    for i in 0 .. n {
+        let a = sub(ring[(i + 1) % n], ring[i]);
+        let b = sub(ring[(i + 2) % n], ring[(i + 1) % n]);
+        if cross(a, b) < -1e-3 * length(a) * length(b) {
+            return None;
+        }
+
+        turning += cross(a, b).atan2(a.0 * b.0 + a.1 * b.1);
+    }
+
+    (n >= 3 && area(&ring) > 1e-9 && turning < 3.0 * PI).then_some(ring)
+}
+
+/// Intersect a path with convex regions. The resulting polygons are in PDF
+/// coordinates and have the same winding numbers as the path within the
+/// regions, so they are filled with the path's fill rule.
+pub(crate) fn clip(
+    data: &PathData,
+    transform: Transform,
+    c: &CoordToPdf,
+    regions: &[Vec<Point>],
+) -> Vec<Vec<Point>> {
+    flatten(&to_pdf(data, transform, c), TOLERANCE)
+        .into_iter()
+        .filter_map(|(ring, _)| {
+            regions.iter().try_fold(ring, |ring, region| {
+                let ring = clip_ring(&ring, region);
+                (ring.len() >= 3).then_some(ring)
+            })
+        })
+        .collect()
+}
+
+/// Write polygons to a content stream.
+pub(crate) fn draw_rings(rings: &[Vec<Point>], content: &mut Content) {
+    for ring in rings {
+        let (x, y) = ring[0];
+        content.move_to(x as f32, y as f32);
+        for &(x, y) in &ring[1 ..] {
+            content.line_to(x as f32, y as f32);
+        }
+        content.close_path();
+    }
+}
+
+/// Clip a polygon to a convex, counter-clockwise one with the
+/// Sutherland-Hodgman algorithm.
+fn clip_ring(ring: &[Point], region: &[Point]) -> Vec<Point> {
+    let mut out = ring.to_vec();
+
+    for (i, &a) in region.iter().enumerate() {
+        let b = region[(i + 1) % region.len()];
+        let side = |p: Point| cross(sub(b, a), sub(p, a));
+        let input = std::mem::take(&mut out);
+
+        for (j, &p) in input.iter().enumerate() {
+            let q = input[(j + 1) % input.len()];
+            let (sp, sq) = (side(p), side(q));
+            if sp >= 0.0 {
+                out.push(p);
+            }
+
+            if (sp >= 0.0) != (sq >= 0.0) {
+                out.push(lerp(p, q, sp / (sp - sq)));
+            }
+        }
+
+        if out.is_empty() {
+            break;
+        }
+    }
+
+    out
+}
+
+/// Transform a path into PDF coordinates.
+fn to_pdf(data: &PathData, transform: Transform, c: &CoordToPdf) -> PathData {
+    let point = |x, y| c.precise_point(transform.apply(x, y));
+
+    PathData(
+        data.0
+            .iter()
+            .map(|&segment| match segment {
+                PathSegment::MoveTo { x, y } => {
+                    let (x, y) = point(x, y);
+                    PathSegment::MoveTo { x, y }
+                }
+                PathSegment::LineTo { x, y } => {
+                    let (x, y) = point(x, y);
+                    PathSegment::LineTo { x, y }
+                }
+                PathSegment::CurveTo { x1, y1, x2, y2, x, y } => {
+                    let (x1, y1) = point(x1, y1);
+                    let (x2, y2) = point(x2, y2);
+                    let (x, y) = point(x, y);
+                    PathSegment::CurveTo { x1, y1, x2, y2, x, y }
+                }
+                PathSegment::ClosePath => PathSegment::ClosePath,
+            })
+            .collect(),
+    )
+}
+
+/// The signed area of a polygon, positive if it is counter-clockwise.
+fn area(ring: &[Point]) -> f64 {
+    let n = ring.len();
+    (0 .. n).map(|i| cross(ring[i], ring[(i + 1) % n])).sum::<f64>() / 2.0
+}
+
+fn cross(a: Point, b: Point) -> f64 {
+    a.0 * b.1 - a.1 * b.0
+}
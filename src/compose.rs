@@ -0,0 +1,177 @@
+//! Lay out multiple SVGs on a single page.
+
+use pdf_writer::{Content, Filter, Finish, PdfWriter, Rect, Ref};
+use usvg::Tree;
+
+use crate::defer::ResourceName;
+use crate::reduce::{has_raster_images, reduce, Reductions};
+use crate::{
+    deflate, fit_user_unit, tree_to_xobject, write_document_info, Options, MAX_PAGE_SIZE,
+};
+
+/// An SVG to be placed on a [composed page](convert_composition).
+#[derive(Clone)]
+pub struct Placement<'a> {
+    /// The parsed SVG file.
+    pub tree: &'a Tree,
+    /// The area of the page in points that the SVG is fitted into, measured
+    /// from the bottom-left corner of the page. The SVG is scaled according to
+    /// its `preserveAspectRatio` attribute or [`Options::aspect`].
+    pub rect: Rect,
+}
+
+/// Convert multiple [`usvg` trees](Tree) into a single PDF page with the given
+/// size in points, placing each of them in its area.
+///
+/// This is the building block for label sheets, imposition and dashboards.
+/// Each distinct tree and size is converted into a Form XObject once, so
+/// repeated placements share their resources. Later placements are drawn on
+/// top of earlier ones. Pages larger than PDF viewers support are scaled down
+/// and declare a larger user space unit.
+///
+/// [`Options::viewport`] and [`Options::page_size`] are ignored, and so are the
+/// options that do not apply to [`convert_tree_into`](crate::convert_tree_into)
+/// because the trees are converted into Form XObjects the same way:
+/// `page_rounding`, `content_chunk_size`, `keep_hidden` and `annotate_issues`.
+/// Of the options for the document as a whole, `target_size` and
+/// `strip_metadata` are honored, while `bleed`, `tagged`, `provenance`,
+/// `object_streams` and `budget` are not supported for composed pages and have
+/// no effect. The other options apply to each tree.
+///
+/// ## Example
+/// ```rust
+/// use pdf_writer::Rect;
+/// use svg2pdf::Placement;
+///
+/// let svg = std::fs::read_to_string("tests/example.svg").unwrap();
+/// let tree = usvg::Tree::from_str(&svg, &usvg::Options::default().to_ref()).unwrap();
+///
+/// // Two rows of three labels on an A4 page.
+/// let mut placements = vec![];
+/// for row in 0 .. 2 {
+///     for col in 0 .. 3 {
+///         let (x, y) = (36.0 + col as f32 * 180.0, 600.0 - row as f32 * 200.0);
+///         let rect = Rect::new(x, y, x + 160.0, y + 160.0);
+///         placements.push(Placement { tree: &tree, rect });
+///     }
+/// }
+///
+/// let options = svg2pdf::Options::default();
+/// let pdf = svg2pdf::convert_composition((595.0, 842.0), &placements, options);
+/// # assert!(pdf.starts_with(b"%PDF"));
+/// ```
+pub fn convert_composition(
+    size: (f32, f32),
+    placements: &[Placement],
+    options: Options,
+) -> Vec<u8> {
+    if let Some(user_unit) = fit_user_unit(size.0.max(size.1)) {
+        log::warn!(
+            "Page exceeds the maximum page size of {} pt and was scaled down by a factor of {}.",
+            MAX_PAGE_SIZE,
+            user_unit,
+        );
+    }
+
+    let images = placements.iter().any(|placement| has_raster_images(placement.tree));
+    let (pdf, _) = reduce(images, options.target_size, None, |reductions| {
+        write_composition(size, placements, &options, reductions)
+    });
+
+    pdf
+}
+
+/// Write a composed page with the given measures to reduce its size in effect.
+fn write_composition(
+    size: (f32, f32),
+    placements: &[Placement],
+    options: &Options,
+    reductions: &Reductions,
+) -> Vec<u8> {
+    let mut writer = PdfWriter::new();
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let page_id = Ref::new(3);
+    let content_id = Ref::new(4);
+    let mut next_id = Ref::new(5);
+    let user_unit = fit_user_unit(size.0.max(size.1));
+    let unit = user_unit.unwrap_or(1.0);
+
+    writer.catalog(catalog_id).pages(page_tree_id);
+    writer.pages(page_tree_id).kids([page_id]).count(1);
+
+    // The XObjects that have been written, identified by their tree and the
+    // size of their area.
+    let mut xobjects: Vec<(&Tree, f32, f32, Ref)> = vec![];
+    let mut content = Content::new();
+
+    for placement in placements {
+        let rect = placement.rect;
+        let (width, height) = (rect.x2 - rect.x1, rect.y2 - rect.y1);
+        if width <= 0.0 || height <= 0.0 {
+            continue;
+        }
+
+        let index = match xobjects.iter().position(|&(tree, w, h, _)| {
+            std::ptr::eq(tree, placement.tree) && w == width && h == height
+        }) {
+            Some(index) => index,
+            None => {
+                let px = |pt: f32| pt as f64 * options.dpi / 72.0;
+                let opt = Options {
+                    viewport: Some((px(width), px(height))),
+                    page_size: None,
+                    ..options.clone()
+                };
+
+                let id = next_id;
+                next_id =
+                    tree_to_xobject(placement.tree, opt, reductions, &mut writer, id);
+                xobjects.push((placement.tree, width, height, id));
+                xobjects.len() - 1
+            }
+        };
+
+        content.save_state();
+        content.transform([
+            width / unit,
+            0.0,
+            0.0,
+            height / unit,
+            rect.x1 / unit,
+            rect.y1 / unit,
+        ]);
+        content.x_object(ResourceName::numbered("xo", index as u32).as_name());
+        content.restore_state();
+    }
+
+    let mut page = writer.page(page_id);
+    page.media_box(Rect::new(0.0, 0.0, size.0 / unit, size.1 / unit));
+    page.parent(page_tree_id);
+    if let Some(user_unit) = user_unit {
+        page.user_unit(user_unit);
+    }
+    page.contents(content_id);
+
+    let mut resources = page.resources();
+    let mut dict = resources.x_objects();
+    for (i, &(.., id)) in xobjects.iter().enumerate() {
+        dict.pair(ResourceName::numbered("xo", i as u32).as_name(), id);
+    }
+
+    dict.finish();
+    resources.finish();
+    page.finish();
+
+    let content = content.finish();
+    let compressed = deflate(&content, options);
+    let mut stream = writer.stream(content_id, compressed.as_deref().unwrap_or(&content));
+    if compressed.is_some() {
+        stream.filter(Filter::FlateDecode);
+    }
+
+    stream.finish();
+
+    write_document_info(&mut writer, next_id, options);
+    writer.finish()
+}
@@ -0,0 +1,92 @@
+//! Carry selected `data-*` attributes into marked content.
+//!
+//! usvg discards attributes it does not understand, so they are collected from
+//! the source string before it is parsed and looked up by element id during the
+//! conversion. usvg also removes most groups, so the attributes of containers
+//! are attached to the graphics elements within them. Graphics elements without
+//! an id are given one.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use roxmltree::Document;
+
+use crate::animate::name_end;
+
+/// Elements that produce content themselves rather than grouping other
+/// elements.
+pub(crate) const GRAPHICS_ELEMENTS: &[&str] = &[
+    "path", "rect", "circle", "ellipse", "line", "polyline", "polygon", "image", "text",
+];
+
+/// The selected `data-*` attributes of elements, keyed by element id.
+pub(crate) type DataAttributes = HashMap<String, Vec<(String, String)>>;
+
+/// Collect the attributes with the given names of each graphics element and its
+/// ancestors from an SVG source string. Attributes of closer elements take
+/// precedence.
+///
+/// Returns the source string with ids added to graphics elements that have
+/// such attributes but no id, if there are any, along with the attributes.
+pub(crate) fn collect(src: &str, names: &[String]) -> (Option<String>, DataAttributes) {
+    let mut data = DataAttributes::new();
+    let doc = match Document::parse(src) {
+        Ok(doc) => doc,
+        Err(_) => return (None, data),
+    };
+
+    let mut edits: Vec<(Range<usize>, String)> = vec![];
+    let mut next = 1;
+    let graphics = doc
+        .descendants()
+        .filter(|node| GRAPHICS_ELEMENTS.contains(&node.tag_name().name()));
+
+    for node in graphics {
+        let mut attrs: Vec<(String, String)> = vec![];
+        for attr in node.ancestors().flat_map(|node| node.attributes()) {
+            if attr.namespace().is_none()
+                && attr.name().starts_with("data-")
+                && names.iter().any(|name| name == attr.name())
+                && !attrs.iter().any(|(name, _)| name == attr.name())
+            {
+                attrs.push((attr.name().to_string(), attr.value().to_string()));
+            }
+        }
+
+        if attrs.is_empty() {
+            continue;
+        }
+
+        let id = match node.attribute("id") {
+            Some(id) => id.to_string(),
+            None => {
+                let id = loop {
+                    let id = format!("svg2pdf-data-{}", next);
+                    next += 1;
+                    if !doc.descendants().any(|node| node.attribute("id") == Some(&id)) {
+                        break id;
+                    }
+                };
+
+                let pos = name_end(src, node.range().start);
+                edits.push((pos .. pos, format!(" id=\"{}\"", id)));
+                id
+            }
+        };
+
+        data.insert(id, attrs);
+    }
+
+    if edits.is_empty() {
+        return (None, data);
+    }
+
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+
+    let mut out = src.to_string();
+    for (range, text) in edits {
+        out.replace_range(range, &text);
+    }
+
+    (Some(out), data)
+}
@@ -5,14 +5,53 @@
 //! and functions to ultimately populate them to the file.
 
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::rc::Rc;
 
 use pdf_writer::types::{MaskType, ShadingType};
-use pdf_writer::writers::{ExtGraphicsState, Resources, ShadingPattern};
+use pdf_writer::writers::{ExtGraphicsState, Resources, Shading, ShadingPattern};
 use pdf_writer::{Finish, Name, PdfWriter, Rect, Ref};
 use usvg::{NodeKind, Tree};
 
 use super::{content_stream, form_xobject, Context, CoordToPdf};
-use crate::render::Gradient;
+use crate::render::{write_shading, Gradient, Spread};
+
+/// The name of a resource in a `Resources` dictionary, e.g. `gs3`.
+///
+/// Sequential names are stored inline, so allocating, queueing and writing them
+/// does not touch the heap. Names derived from element ids are shared with the
+/// set of taken names in the [`Context`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ResourceName {
+    /// A short prefix followed by a number.
+    Inline { buf: [u8; 14], len: u8 },
+    /// A name derived from an element id if debug names are enabled.
+    Shared(Rc<str>),
+}
+
+impl ResourceName {
+    /// Create a sequential name such as `xo3`. The prefix must be at most four
+    /// bytes long.
+    pub fn numbered(prefix: &str, num: u32) -> Self {
+        let mut buf = [0; 14];
+        let remaining = {
+            let mut cursor = &mut buf[..];
+            write!(cursor, "{}{}", prefix, num).unwrap();
+            cursor.len()
+        };
+
+        Self::Inline { buf, len: (buf.len() - remaining) as u8 }
+    }
+
+    /// Borrow the resource name for writing.
+    pub fn as_name(&self) -> Name<'_> {
+        match self {
+            Self::Inline { buf, len } => Name(&buf[.. *len as usize]),
+            Self::Shared(name) => Name(name.as_bytes()),
+        }
+    }
+}
 
 /// A gradient to be written.
 ///
@@ -22,15 +61,20 @@ pub struct PendingGradient {
     /// The unique SVG id of the pattern which is used to fetch the associated
     /// pattern function.
     pub id: String,
-    /// The number allocated by [`Context::alloc_pattern`] for reference in
+    /// The name allocated by [`Context::alloc_pattern`] for reference in
     /// content streams as e.g. `p4`.
-    pub num: u32,
+    pub name: ResourceName,
     /// How the gradient shading is distributed in its area.
     pub shading_type: ShadingType,
     /// The coordinates of where to apply the gradient within the content
     /// stream's bounding box. Note that the last two components are zero for
     /// radial gradients.
     pub coords: [f32; 6],
+    /// How often the gradient is repeated if it is not padded.
+    pub spread: Option<Spread>,
+    /// Whether the gradient is painted with the shading operator instead of
+    /// serving as a pattern.
+    pub direct: bool,
 }
 
 impl PendingGradient {
@@ -38,13 +82,17 @@ impl PendingGradient {
     pub(crate) fn from_gradient(
         pattern: Gradient,
         bbox: usvg::Rect,
-        num: u32,
+        area: Rect,
+        name: ResourceName,
         c: &CoordToPdf,
     ) -> Self {
+        let coords = pattern.transformed_coords(c, bbox);
         Self {
-            coords: pattern.transformed_coords(c, bbox),
+            spread: pattern.spread(coords, area),
+            direct: false,
+            coords,
             id: pattern.id,
-            num,
+            name,
             shading_type: pattern.shading_type,
         }
     }
@@ -55,9 +103,9 @@ impl PendingGradient {
 /// Currently, svg2pdf mostly uses graphics state dictionaries to encode
 /// transparency data.
 pub struct PendingGS {
-    /// The number allocated by [`Context::alloc_gs`] for reference in
+    /// The name allocated by [`Context::alloc_gs`] for reference in
     /// content streams as e.g. `gs3`.
-    num: u32,
+    name: ResourceName,
     /// The opacity of strokes within the current drawing state.
     stroke_opacity: Option<f32>,
     /// The opacity of fill operations within the current drawing state.
@@ -70,11 +118,20 @@ pub struct PendingGS {
     soft_mask: Option<Ref>,
 }
 
+impl Hash for PendingGS {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.stroke_opacity.map(f32::to_bits).hash(state);
+        self.fill_opacity.map(f32::to_bits).hash(state);
+        self.soft_mask.hash(state);
+    }
+}
+
 impl PendingGS {
     /// Create a new, empty pending graphics state.
-    fn new(num: u32) -> Self {
+    fn new(name: ResourceName) -> Self {
         Self {
-            num,
+            name,
             stroke_opacity: None,
             fill_opacity: None,
             soft_mask: None,
@@ -83,8 +140,8 @@ impl PendingGS {
 
     /// Create a pending graphics state which will set a luminosity Soft Mask
     /// with the referenced Form XObject.
-    pub fn soft_mask(smask: Ref, num: u32) -> Self {
-        let mut res = Self::new(num);
+    pub fn soft_mask(smask: Ref, name: ResourceName) -> Self {
+        let mut res = Self::new(name);
         res.soft_mask = Some(smask);
         res
     }
@@ -94,9 +151,9 @@ impl PendingGS {
     pub fn opacity(
         stroke_opacity: Option<f32>,
         fill_opacity: Option<f32>,
-        num: u32,
+        name: ResourceName,
     ) -> Self {
-        let mut res = Self::new(num);
+        let mut res = Self::new(name);
         res.stroke_opacity = stroke_opacity;
         res.fill_opacity = fill_opacity;
         res
@@ -104,8 +161,8 @@ impl PendingGS {
 
     /// Create a pending graphics state which will set the fill opacity for its
     /// drawing state.
-    pub fn fill_opacity(opacity: f32, num: u32) -> Self {
-        Self::opacity(None, Some(opacity), num)
+    pub fn fill_opacity(opacity: f32, name: ResourceName) -> Self {
+        Self::opacity(None, Some(opacity), name)
     }
 }
 
@@ -141,45 +198,56 @@ pub struct PendingGroup {
 /// therefore the `function_map` retains their references.
 pub fn write_gradients(
     pending_gradients: &[PendingGradient],
-    pending_patterns: &[(u32, Ref)],
+    pending_patterns: &[(ResourceName, Ref)],
     function_map: &HashMap<String, (Ref, Option<Ref>)>,
     resources: &mut Resources,
 ) {
-    if pending_gradients.is_empty() && pending_patterns.is_empty() {
-        return;
-    }
+    let (direct, patterned): (Vec<_>, Vec<_>) =
+        pending_gradients.iter().partition(|pending| pending.direct);
+
+    if !patterned.is_empty() || !pending_patterns.is_empty() {
+        let mut patterns = resources.patterns();
+        for pending in patterned {
+            let mut pattern =
+                patterns.insert(pending.name.as_name()).start::<ShadingPattern>();
+            write_gradient(&mut pattern.shading(), pending, function_map);
+        }
 
-    let mut patterns = resources.patterns();
-
-    for pending in pending_gradients.iter() {
-        let name = format!("p{}", pending.num);
-        let mut pattern =
-            patterns.insert(Name(name.as_bytes())).start::<ShadingPattern>();
-
-        // The object has already been outfitted with an alpha soft mask, so we
-        // can disregard the alpha function option.
-        let func = function_map[&pending.id].0;
-
-        let mut shading = pattern.shading();
-        shading.shading_type(pending.shading_type);
-        shading.color_space().srgb();
-        shading.function(func);
-        shading.coords(pending.coords.into_iter().take(
-            if pending.shading_type == ShadingType::Axial {
-                4
-            } else {
-                6
-            },
-        ));
-        shading.extend([true, true]);
+        for (name, ref_id) in pending_patterns {
+            patterns.pair(name.as_name(), *ref_id);
+        }
     }
 
-    for (num, ref_id) in pending_patterns {
-        let name = format!("p{}", num);
-        patterns.pair(Name(name.as_bytes()), *ref_id);
+    if !direct.is_empty() {
+        let mut shadings = resources.shadings();
+        for pending in direct {
+            let mut shading = shadings.insert(pending.name.as_name()).start::<Shading>();
+            write_gradient(&mut shading, pending, function_map);
+        }
     }
 }
 
+/// Write the shading dictionary of a pending gradient.
+fn write_gradient(
+    shading: &mut Shading,
+    pending: &PendingGradient,
+    function_map: &HashMap<String, (Ref, Option<Ref>)>,
+) {
+    // The object has already been outfitted with an alpha soft mask, so we
+    // can disregard the alpha function option.
+    let func = function_map[&pending.id].0;
+
+    shading.shading_type(pending.shading_type);
+    shading.color_space().srgb();
+    write_shading(
+        shading,
+        pending.shading_type,
+        func,
+        pending.coords,
+        pending.spread,
+    );
+}
+
 /// Writes all pending graphics states into a `Resources` dictionary.
 pub fn write_graphics(pending_graphics: &[PendingGS], resources: &mut Resources) {
     if pending_graphics.is_empty() {
@@ -193,9 +261,7 @@ pub fn write_graphics(pending_graphics: &[PendingGS], resources: &mut Resources)
     // objects instead.
     let mut states = resources.ext_g_states();
     for gs in pending_graphics {
-        let mut state = states
-            .insert(Name(format!("gs{}", gs.num).as_bytes()))
-            .start::<ExtGraphicsState>();
+        let mut state = states.insert(gs.name.as_name()).start::<ExtGraphicsState>();
 
         if let Some(stroke_opacity) = gs.stroke_opacity {
             state.stroking_alpha(stroke_opacity);
@@ -213,15 +279,17 @@ pub fn write_graphics(pending_graphics: &[PendingGS], resources: &mut Resources)
 
 /// Register indirect XObjects with the `Resources` dictionary such that they
 /// can be used as `xo123` in content streams.
-pub fn write_xobjects(pending_xobjects: &[(u32, Ref)], resources: &mut Resources) {
+pub fn write_xobjects(
+    pending_xobjects: &[(ResourceName, Ref)],
+    resources: &mut Resources,
+) {
     if pending_xobjects.is_empty() {
         return;
     }
 
     let mut xobjects = resources.x_objects();
-    for (num, ref_id) in pending_xobjects {
-        let name = format!("xo{}", num);
-        xobjects.pair(Name(name.as_bytes()), *ref_id);
+    for (name, ref_id) in pending_xobjects {
+        xobjects.pair(name.as_name(), *ref_id);
     }
 }
 
@@ -238,6 +306,7 @@ pub(crate) fn write_masks(tree: &Tree, writer: &mut PdfWriter, ctx: &mut Context
 
             let content = content_stream(&mask_node, writer, ctx);
 
+            ctx.xobjects += 1;
             let mut group =
                 form_xobject(writer, gp.reference, &content, gp.bbox, ctx.compress, true);
 
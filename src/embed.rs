@@ -0,0 +1,161 @@
+//! Render trees and their nodes into content streams of the caller.
+
+use pdf_writer::writers::Resources;
+use pdf_writer::{Content, PdfWriter, Rect, Ref};
+use usvg::{Node, Tree};
+
+use crate::defer::write_masks;
+use crate::reduce::Reductions;
+use crate::{
+    preregister, render_content, render_element, tree_context, Context, Options,
+};
+
+/// The operators of a [rendered tree](render_tree) or [node](render_node)
+/// along with the resources they use.
+///
+/// This is the low-level counterpart to [`convert_tree_into`](crate::convert_tree_into).
+/// Instead of being wrapped in a Form XObject, the operators can be added to
+/// the content stream of a page or Form XObject that the caller writes, in
+/// between its own operators.
+pub struct Rendered<'a> {
+    content: Vec<u8>,
+    ctx: Context<'a>,
+}
+
+impl Rendered<'_> {
+    /// The uncompressed operators.
+    ///
+    /// They draw into the [bounding box](Self::bbox) and may change the
+    /// graphics state, so they should be surrounded by the `q` and `Q`
+    /// operators. A transformation set up before them places the graphic. As
+    /// content streams do not end with a newline, a separator is needed before
+    /// further operators.
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// The area in points that the operators draw into. Its bottom-left corner
+    /// is at the origin.
+    pub fn bbox(&self) -> Rect {
+        self.ctx.bbox
+    }
+
+    /// The next indirect reference id that is not used by the written objects.
+    pub fn next_id(&self) -> Ref {
+        Ref::new(self.ctx.next_id)
+    }
+
+    /// Register the resources that the operators use with the resource
+    /// dictionary of the page or Form XObject whose content stream they are
+    /// added to.
+    ///
+    /// This writes the `ColorSpace`, `ExtGState`, `Pattern`, `Shading`,
+    /// `XObject` and `ProcSet` entries of the dictionary, which therefore must
+    /// not be written a second time.
+    pub fn write_resources(mut self, resources: &mut Resources) {
+        self.ctx.pop(resources);
+    }
+}
+
+/// Render a [`usvg` tree](Tree) into operators for a content stream of the
+/// caller.
+///
+/// The objects that the operators need, like gradients and images, are written
+/// right away, using consecutive IDs starting at `id`. The returned value
+/// provides the operators, the resources to register and the next free ID.
+/// Other than with [`convert_tree_into`](crate::convert_tree_into), the size
+/// of the graphic is given by [`Options::viewport`] and [`Options::dpi`]
+/// instead of being normalized to one point.
+///
+/// ## Example
+/// ```rust
+/// use pdf_writer::{Finish, PdfWriter, Ref};
+///
+/// let svg = std::fs::read_to_string("tests/example.svg").unwrap();
+/// let tree = usvg::Tree::from_str(&svg, &usvg::Options::default().to_ref()).unwrap();
+///
+/// let mut writer = PdfWriter::new();
+/// let (catalog_id, page_tree_id, page_id, content_id) =
+///     (Ref::new(1), Ref::new(2), Ref::new(3), Ref::new(4));
+/// writer.catalog(catalog_id).pages(page_tree_id);
+/// writer.pages(page_tree_id).kids([page_id]).count(1);
+///
+/// let rendered =
+///     svg2pdf::render_tree(&tree, svg2pdf::Options::default(), &mut writer, Ref::new(5));
+///
+/// // Draw the graphic twice, side by side.
+/// let width = rendered.bbox().x2;
+/// let mut content = vec![];
+/// for x in [0.0, width] {
+///     content.extend(format!("q 1 0 0 1 {} 0 cm\n", x).as_bytes());
+///     content.extend(rendered.content());
+///     content.extend(b"\nQ\n");
+/// }
+///
+/// let mut page = writer.page(page_id);
+/// let mut media_box = rendered.bbox();
+/// media_box.x2 *= 2.0;
+/// page.media_box(media_box);
+/// page.parent(page_tree_id);
+/// page.contents(content_id);
+/// rendered.write_resources(&mut page.resources());
+/// page.finish();
+///
+/// writer.stream(content_id, &content);
+/// let pdf = writer.finish();
+/// # assert!(pdf.starts_with(b"%PDF"));
+/// ```
+pub fn render_tree<'a>(
+    tree: &'a Tree,
+    options: Options,
+    writer: &mut PdfWriter,
+    id: Ref,
+) -> Rendered<'a> {
+    render(tree, options, writer, id, |writer, ctx| {
+        render_content(&tree.root(), writer, ctx)
+    })
+}
+
+/// Render a single node of a [`usvg` tree](Tree) and its descendants into
+/// operators for a content stream of the caller.
+///
+/// This works like [`render_tree`], but only draws the given path, group or
+/// image. The node must be part of the tree, which provides the size and the
+/// definitions the node refers to. Its operators are the same as in the
+/// conversion of the whole tree, so the graphic is drawn where the node would
+/// be in the full tree.
+pub fn render_node<'a>(
+    tree: &'a Tree,
+    node: &Node,
+    options: Options,
+    writer: &mut PdfWriter,
+    id: Ref,
+) -> Rendered<'a> {
+    render(tree, options, writer, id, |writer, ctx| {
+        let mut content = Content::new();
+        render_element(node, writer, &mut content, ctx);
+        content.finish()
+    })
+}
+
+/// Set up the context for a tree, write the operators with `f` and the
+/// objects they need.
+fn render<'a, F>(
+    tree: &'a Tree,
+    options: Options,
+    writer: &mut PdfWriter,
+    id: Ref,
+    f: F,
+) -> Rendered<'a>
+where
+    F: FnOnce(&mut PdfWriter, &mut Context<'a>) -> Vec<u8>,
+{
+    let mut ctx = tree_context(tree, &options, &Reductions::default(), id);
+    preregister(tree, writer, &mut ctx);
+
+    ctx.push();
+    let content = f(writer, &mut ctx);
+    write_masks(tree, writer, &mut ctx);
+
+    Rendered { content, ctx }
+}
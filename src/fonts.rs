@@ -0,0 +1,205 @@
+//! Report which fonts the text of an SVG would be set in.
+
+use usvg::fontdb::{Database, Family, Query, Stretch, Style, Weight, ID};
+
+/// How a run of text with the same font properties would be set, as returned
+/// by [`fonts_used`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontUsage {
+    /// The font families the text asks for, in order of preference.
+    pub families: Vec<String>,
+    /// The requested font weight.
+    pub weight: u16,
+    /// The requested font style.
+    pub style: Style,
+    /// The requested font stretch.
+    pub stretch: Stretch,
+    /// The requested families the database has no face for.
+    pub unavailable: Vec<String>,
+    /// The face from the database the text would be set in, if any matches.
+    pub face: Option<ID>,
+    /// The family name of the chosen face.
+    pub family: Option<String>,
+    /// How many distinct glyphs of the chosen face the text uses.
+    pub glyphs: usize,
+    /// The characters the chosen face has no glyphs for, sorted and without
+    /// duplicates. Contains all characters if no face matches.
+    pub missing: Vec<char>,
+}
+
+/// Report which faces from a font database the text of an SVG source string
+/// would be set in, and which characters they do not cover.
+///
+/// This allows applications to warn users about missing fonts before the
+/// conversion or to bundle the required fonts dynamically. Because usvg
+/// converts text to paths while parsing, this works on the source string
+/// rather than a [`Tree`](usvg::Tree). Font properties set in `<style>`
+/// elements are not considered.
+///
+/// Requires the `text` feature. Returns an error if the SVG string is
+/// malformed.
+///
+/// ## Example
+/// ```rust
+/// let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+///   <text x="10" y="50" font-family="Inter, sans-serif">Hello</text>
+/// </svg>"#;
+///
+/// let fontdb = usvg::fontdb::Database::new();
+/// let usages = svg2pdf::fonts_used(svg, &fontdb).unwrap();
+/// assert_eq!(usages[0].families, ["Inter", "sans-serif"]);
+/// assert_eq!(usages[0].face, None);
+/// ```
+pub fn fonts_used(src: &str, fontdb: &Database) -> Result<Vec<FontUsage>, usvg::Error> {
+    let doc = roxmltree::Document::parse(src)?;
+
+    // Group the text by the font properties it is set in.
+    let mut runs: Vec<(FontUsage, String)> = vec![];
+    for node in doc.descendants().filter(|node| node.is_text()) {
+        let parent = match node.parent_element() {
+            Some(parent) => parent,
+            None => continue,
+        };
+
+        if !parent.ancestors().any(|node| node.tag_name().name() == "text") {
+            continue;
+        }
+
+        let usage = requested(parent);
+        let text = node.text().unwrap_or_default();
+        match runs.iter_mut().find(|(other, _)| *other == usage) {
+            Some((_, run)) => run.push_str(text),
+            None => runs.push((usage, text.to_string())),
+        }
+    }
+
+    let mut usages = vec![];
+    for (mut usage, text) in runs {
+        let mut chars: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+        chars.sort_unstable();
+        chars.dedup();
+
+        let families: Vec<Family> =
+            usage.families.iter().map(|name| family(name)).collect();
+
+        let query = Query {
+            families: &families,
+            weight: Weight(usage.weight),
+            stretch: usage.stretch,
+            style: usage.style,
+        };
+
+        for (family, name) in families.iter().zip(&usage.families) {
+            let query = Query { families: &[*family], ..Query::default() };
+            if fontdb.query(&query).is_none() {
+                usage.unavailable.push(name.clone());
+            }
+        }
+
+        usage.face = fontdb.query(&query);
+        usage.missing = chars.clone();
+
+        if let Some(id) = usage.face {
+            usage.family = fontdb.face(id).map(|face| face.family.clone());
+            fontdb.with_face_data(id, |data, index| {
+                let face = match ttf_parser::Face::parse(data, index) {
+                    Ok(face) => face,
+                    Err(_) => return,
+                };
+
+                let mut glyphs: Vec<u16> = vec![];
+                usage.missing.clear();
+                for &c in &chars {
+                    match face.glyph_index(c) {
+                        Some(glyph) => glyphs.push(glyph.0),
+                        None => usage.missing.push(c),
+                    }
+                }
+
+                glyphs.sort_unstable();
+                glyphs.dedup();
+                usage.glyphs = glyphs.len();
+            });
+        }
+
+        usages.push(usage);
+    }
+
+    Ok(usages)
+}
+
+/// Resolve the inherited font properties of an element.
+fn requested(node: roxmltree::Node) -> FontUsage {
+    let families = property(node, "font-family")
+        .unwrap_or("Times New Roman")
+        .split(',')
+        .map(|family| family.trim().trim_matches(|c| c == '"' || c == '\'').to_string())
+        .filter(|family| !family.is_empty())
+        .collect();
+
+    let weight = match property(node, "font-weight") {
+        Some("bold" | "bolder") => 700,
+        Some("lighter") => 300,
+        Some(weight) => weight.parse().unwrap_or(400),
+        None => 400,
+    };
+
+    let style = match property(node, "font-style") {
+        Some("italic") => Style::Italic,
+        Some("oblique") => Style::Oblique,
+        _ => Style::Normal,
+    };
+
+    let stretch = match property(node, "font-stretch") {
+        Some("ultra-condensed") => Stretch::UltraCondensed,
+        Some("extra-condensed") => Stretch::ExtraCondensed,
+        Some("condensed" | "narrower") => Stretch::Condensed,
+        Some("semi-condensed") => Stretch::SemiCondensed,
+        Some("semi-expanded") => Stretch::SemiExpanded,
+        Some("expanded" | "wider") => Stretch::Expanded,
+        Some("extra-expanded") => Stretch::ExtraExpanded,
+        Some("ultra-expanded") => Stretch::UltraExpanded,
+        _ => Stretch::Normal,
+    };
+
+    FontUsage {
+        families,
+        weight,
+        style,
+        stretch,
+        unavailable: vec![],
+        face: None,
+        family: None,
+        glyphs: 0,
+        missing: vec![],
+    }
+}
+
+/// Find the value of a property on an element or its closest ancestor that
+/// sets it, either in its `style` attribute or as a presentation attribute.
+fn property<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.ancestors().filter(|node| node.is_element()).find_map(|node| {
+        let styled = node.attribute("style").and_then(|style| {
+            style.split(';').rev().find_map(|decl| {
+                let (key, value) = decl.split_once(':')?;
+                (key.trim() == name).then(|| value.trim())
+            })
+        });
+
+        styled
+            .or_else(|| node.attribute(name))
+            .filter(|&value| value != "inherit")
+    })
+}
+
+/// Map a CSS font family name to a font database family.
+fn family(name: &str) -> Family<'_> {
+    match name {
+        "serif" => Family::Serif,
+        "sans-serif" => Family::SansSerif,
+        "cursive" => Family::Cursive,
+        "fantasy" => Family::Fantasy,
+        "monospace" => Family::Monospace,
+        name => Family::Name(name),
+    }
+}
@@ -0,0 +1,147 @@
+//! Replace `<foreignObject>` elements with raster images of their content.
+//!
+//! usvg drops foreign objects while parsing, so embedded HTML, e.g. from
+//! diagram tools, would otherwise be lost. A renderer provided by the user
+//! rasterizes their content and the elements are rewritten into `<image>`
+//! elements in the source string before it is parsed.
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+use roxmltree::{Document, Node};
+use svgtypes::{Length, LengthUnit};
+
+use crate::animate::escape;
+
+/// Attributes that are carried over from a foreign object to its image.
+const KEPT_ATTRIBUTES: &[&str] = &["id", "transform", "opacity", "clip-path", "mask"];
+
+/// A `<foreignObject>` element whose content is to be rasterized.
+#[derive(Debug, Copy, Clone)]
+pub struct ForeignObject<'a> {
+    /// The source text of the element's content, typically XHTML.
+    pub content: &'a str,
+    /// The width of the element in SVG pixels.
+    pub width: f64,
+    /// The height of the element in SVG pixels.
+    pub height: f64,
+}
+
+/// Rasterizes the content of `<foreignObject>` elements.
+///
+/// The callback returns an encoded PNG, JPEG or GIF image or an SVG file,
+/// which is stretched to fill the area of the element. The resolution is up
+/// to the callback, e.g. a multiple of the element's size in pixels for print.
+/// If it returns `None`, the element is dropped as it would be without a
+/// renderer.
+#[derive(Clone)]
+pub struct ForeignObjectRenderer(Arc<RenderFn>);
+
+/// The callback of a [`ForeignObjectRenderer`].
+type RenderFn = dyn Fn(&ForeignObject) -> Option<Vec<u8>> + Send + Sync;
+
+impl ForeignObjectRenderer {
+    /// Create a renderer from a callback.
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&ForeignObject) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+}
+
+impl Debug for ForeignObjectRenderer {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.pad("ForeignObjectRenderer(..)")
+    }
+}
+
+/// Rewrite an SVG source string such that its foreign objects are replaced by
+/// images that the `renderer` produced for them.
+///
+/// Returns `None` if the source is not valid XML or has no foreign objects.
+pub(crate) fn rasterize(src: &str, renderer: &ForeignObjectRenderer) -> Option<String> {
+    if !src.contains("foreignObject") {
+        return None;
+    }
+
+    let doc = Document::parse(src).ok()?;
+    let mut edits = vec![];
+
+    for node in doc.descendants().filter(|node| node.has_tag_name("foreignObject")) {
+        // Foreign objects in foreign objects are part of the outer content.
+        if node.ancestors().skip(1).any(|a| a.has_tag_name("foreignObject")) {
+            continue;
+        }
+
+        let image = image(src, node, renderer).unwrap_or_default();
+        edits.push((node.range(), image));
+    }
+
+    if edits.is_empty() {
+        return None;
+    }
+
+    let mut out = src.to_string();
+    for (range, text) in edits.into_iter().rev() {
+        out.replace_range(range, &text);
+    }
+
+    Some(out)
+}
+
+/// Render a foreign object and write the `<image>` element that replaces it.
+fn image(src: &str, node: Node, renderer: &ForeignObjectRenderer) -> Option<String> {
+    let length = |name| match node.attribute(name) {
+        Some(value) => pixels(value),
+        None => Some(0.0),
+    };
+
+    let (x, y) = (length("x")?, length("y")?);
+    let (width, height) = (length("width")?, length("height")?);
+    if width <= 0.0 || height <= 0.0 {
+        return None;
+    }
+
+    let content = match (node.first_child(), node.last_child()) {
+        (Some(first), Some(last)) => src[first.range().start .. last.range().end].trim(),
+        _ => return None,
+    };
+
+    let data = renderer.0(&ForeignObject { content, width, height })?;
+
+    let mut image = format!(
+        "<image xmlns:xlink=\"http://www.w3.org/1999/xlink\" x=\"{}\" y=\"{}\" \
+         width=\"{}\" height=\"{}\" preserveAspectRatio=\"none\"",
+        x, y, width, height,
+    );
+
+    for &name in KEPT_ATTRIBUTES {
+        if let Some(value) = node.attribute(name) {
+            image.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+        }
+    }
+
+    // The MIME type lets usvg detect the format from the data itself.
+    image.push_str(" xlink:href=\"data:text/plain;base64,");
+    image.push_str(&base64::encode(data));
+    image.push_str("\"/>");
+    Some(image)
+}
+
+/// Parse a length in SVG pixels. Other units are not supported.
+fn pixels(value: &str) -> Option<f64> {
+    match value.parse::<Length>() {
+        Ok(Length {
+            number,
+            unit: LengthUnit::None | LengthUnit::Px,
+        }) => Some(number),
+        _ => {
+            log::warn!(
+                "Skipped foreign object with unsupported length '{}'.",
+                value
+            );
+            None
+        }
+    }
+}
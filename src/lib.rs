@@ -31,26 +31,95 @@ std::fs::write("target/example.pdf", pdf).unwrap();
 - Respecting the `keepAspectRatio` attribute
 - Raster images and nested SVGs
 
-Filters are not currently supported and embedded raster images are not color
-managed. Instead, they use PDF's `DeviceRGB` color space.
+Filters are not currently supported. Embedded raster images are only color
+managed if they carry an ICC profile. Otherwise, they use PDF's device color
+spaces. Use [`analyze`] to find out which features a file uses before
+converting it.
+
+Raster images are decoded with the `png`, `jpeg` and `gif` features, which are
+enabled by default. Embedders that do not need all formats can disable some of
+them to shrink their binaries. Images in a disabled format are skipped with a
+warning through the `log` crate.
+
+Streams are compressed with the `compress` feature, which is enabled by
+default as well. Without it, all streams are written uncompressed, which
+suits builds that post-process the PDF with another tool anyway.
 */
 
-use std::collections::HashMap;
+#[cfg(feature = "compress")]
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "async")]
+use std::sync::Arc;
+use std::time::Instant;
 
-use pdf_writer::types::ProcSet;
+#[cfg(feature = "compress")]
+use miniz_oxide::deflate::core::{compress, CompressorOxide, TDEFLFlush, TDEFLStatus};
+#[cfg(feature = "compress")]
+use miniz_oxide::DataFormat;
+use pdf_writer::types::{AnnotationType, ProcSet};
 use pdf_writer::writers::{ColorSpace, ExponentialFunction, FormXObject, Resources};
 use pdf_writer::{Content, Filter, Finish, Name, PdfWriter, Rect, Ref, TextStr, Writer};
-use usvg::{NodeExt, NodeKind, Opacity, Stop, Tree};
+use usvg::{Align, AspectRatio, NodeExt, NodeKind, Stop, Tree, ViewBox, Visibility};
 
+mod analyze;
+mod animate;
+mod budget;
+mod clip;
+mod compose;
+mod data;
 mod defer;
+mod embed;
+#[cfg(feature = "text")]
+mod fonts;
+mod foreign;
+mod library;
+mod limits;
+mod marks;
+mod outline;
+mod portfolio;
+mod profile;
+mod provenance;
+mod reduce;
 mod render;
 mod scale;
+mod style;
+mod switch;
+mod tags;
+mod xref;
 
+pub use analyze::{analyze, Feature, FeatureReport, Support};
+pub use budget::{Budget, BudgetHandler, BudgetWarning, Measure};
+pub use compose::{convert_composition, Placement};
+pub use embed::{render_node, render_tree, Rendered};
+#[cfg(feature = "text")]
+pub use fonts::{fonts_used, FontUsage};
+pub use foreign::{ForeignObject, ForeignObjectRenderer};
+pub use library::{convert_library, LibraryItem};
+pub use portfolio::{convert_portfolio, PortfolioFile};
+pub use profile::Timings;
+pub use provenance::Provenance;
+pub use reduce::{ImagePolicy, Reduction};
+
+use budget::Usage;
+use data::DataAttributes;
 use defer::*;
+use outline::Point;
+use reduce::{has_raster_images, reduce, Reductions};
 use render::*;
 use scale::*;
+use tags::{Labels, StructTree};
 
 const SRGB: Name = Name(b"srgb");
+const HIDDEN: Name = Name(b"hidden");
+
+/// The largest width and height of a page in user space units that PDF
+/// viewers have to support.
+const MAX_PAGE_SIZE: f32 = 14400.0;
 
 /// Set size and scaling preferences for the conversion.
 #[derive(Debug, Clone)]
@@ -72,9 +141,31 @@ pub struct Options {
     ///
     /// _Default:_ `None`.
     pub viewport: Option<(f64, f64)>,
+    /// Specific dimensions of the page in points. If this is `Some`, it takes
+    /// precedence over `viewport` and the SVG is fitted into a viewport of this
+    /// size, converted to pixels according to `dpi`. The page then has exactly
+    /// this size unless it is trimmed or gets a bleed.
+    ///
+    /// _Default:_ `None`.
+    pub page_size: Option<(f32, f32)>,
+    /// Whether to round the dimensions of pages up to whole units.
+    ///
+    /// Some viewers show hairline white edges along pages whose size is a
+    /// fraction of a point. If this is `Some`, the width and height of each
+    /// page are rounded up to whole points or whole pixels at `dpi` and the
+    /// content is stretched by less than one unit to fill the page. This does
+    /// not apply to [`convert_tree_into`].
+    ///
+    /// _Default:_ `None`.
+    pub page_rounding: Option<PageRounding>,
     /// Override the scaling mode of the SVG within its viewport. Look
     /// [here][aspect] to learn about the different possible modes.
     ///
+    /// With the `defer` keyword, the SVG's own `preserveAspectRatio` takes
+    /// precedence, and this mode only applies to SVGs without one. Since usvg
+    /// doesn't keep whether the attribute was given, an SVG that specifies the
+    /// initial `xMidYMid meet` counts as having none.
+    ///
     /// _Default:_ `None`.
     ///
     /// [aspect]: https://developer.mozilla.org/en-US/docs/Web/SVG/Attribute/preserveAspectRatio
@@ -94,35 +185,500 @@ pub struct Options {
     /// Whether the content streams should be compressed.
     ///
     /// The smaller PDFs generated by this are generally more practical but it
-    /// increases runtime a bit.
+    /// increases runtime a bit. Without the `compress` feature, this has no
+    /// effect and raster images are written uncompressed as well.
     ///
     /// _Default:_ `true`.
     pub compress: bool,
+    /// Whether the content streams of tiling patterns should be compressed
+    /// along with the other content streams.
+    ///
+    /// Some older raster image processors mishandle compressed pattern
+    /// streams. If this is `false`, patterns and the groups drawn within them
+    /// are written uncompressed, while page contents and raster images stay
+    /// compressed. This has no effect if `compress` is `false`.
+    ///
+    /// _Default:_ `true`.
+    pub compress_patterns: bool,
+    /// Whether large streams should be compressed on multiple threads.
+    ///
+    /// Compression can dominate the runtime for huge SVGs with megabytes of
+    /// content or large raster images. If this is `true`, streams of at least
+    /// a megabyte are split into segments that are compressed in parallel. The
+    /// output is slightly larger because matches cannot span segments.
+    ///
+    /// _Default:_ `false`.
+    pub parallel_compression: bool,
+    /// How strongly streams are compressed.
+    ///
+    /// Higher levels result in smaller files but take longer, which is most
+    /// noticeable for SVGs with large raster images.
+    ///
+    /// _Default:_ [`CompressionLevel::Default`].
+    pub compression: CompressionLevel,
+    /// Whether to store objects in compressed object streams.
+    ///
+    /// PDF 1.5 allows to store the objects that are not streams themselves,
+    /// like page and resource dictionaries, in object streams that are
+    /// compressed as a whole, with a compressed cross-reference stream instead
+    /// of the cross-reference table. This noticeably shrinks documents with
+    /// many small objects, but needs another pass over the finished file and
+    /// the result can't be read by viewers that only support PDF 1.4. This
+    /// applies only to standalone documents, not to [`convert_tree_into`].
+    ///
+    /// _Default:_ `false`.
+    pub object_streams: bool,
+    /// The maximum size in bytes of each of the page's content streams before
+    /// compression.
+    ///
+    /// Very detailed SVGs like maps can result in content streams of tens of
+    /// megabytes which some viewers struggle with. If this is `Some`, larger
+    /// page contents are split into multiple streams between operators. A
+    /// single operator that exceeds the size on its own still ends up in one
+    /// stream. This does not apply to [`convert_tree_into`] as Form XObjects
+    /// can only have a single content stream.
+    ///
+    /// _Default:_ `None`.
+    pub content_chunk_size: Option<usize>,
+    /// Whether elements hidden through the `visibility` property should be
+    /// kept in a layer (an optional content group) that is hidden by default.
+    ///
+    /// Viewers with support for layers then allow the user to reveal them,
+    /// which is useful for alternate states authored in the SVG. Elements with
+    /// `display="none"` are removed by usvg while parsing and are always
+    /// dropped. This does not apply to [`convert_tree_into`] as layers must be
+    /// registered with the document catalog.
+    ///
+    /// _Default:_ `false`.
+    pub keep_hidden: bool,
+    /// Whether to write a structure tree that makes the document accessible,
+    /// as required by PDF/UA.
+    ///
+    /// Elements with an `aria-label` attribute or a `<title>` or `<desc>`
+    /// child become figures with that alternative text, and text elements
+    /// become spans with their characters as the actual text. All other content
+    /// is marked as an artifact. The alternative text is only available to
+    /// functions that parse an SVG string.
+    ///
+    /// _Default:_ `false`.
+    pub tagged: bool,
+    /// Whether images referenced by a file path may be loaded from disk when
+    /// parsing an SVG string.
+    ///
+    /// Disable this when converting untrusted files. Such images are then
+    /// skipped with a warning while images in data URLs are still decoded with
+    /// usvg's default resolver. This has no effect on functions that take an
+    /// already parsed [`Tree`].
+    ///
+    /// _Default:_ `true`.
+    pub load_external: bool,
+    /// Whether XObject and pattern resources should be named after the ids of
+    /// the SVG elements they originate from (e.g. `/xo_chart3`) instead of
+    /// being numbered sequentially.
+    ///
+    /// This makes the generated files easier to inspect and post-process.
+    /// Elements without an id still get sequential names.
+    ///
+    /// _Default:_ `false`.
+    pub debug_names: bool,
+    /// Whether to mark elements that were skipped or drawn incompletely with
+    /// text annotations describing why.
+    ///
+    /// This makes it easy to audit visually what the conversion changed, like
+    /// images in a disabled format and filters, which are not supported.
+    /// Filters are only noticed with the `filter` feature. The same warnings
+    /// are logged through the `log` crate either way. This does not apply to
+    /// [`convert_tree_into`] as Form XObjects can't have annotations.
+    ///
+    /// _Default:_ `false`.
+    pub annotate_issues: bool,
+    /// The point in time in seconds at which to take a snapshot of an
+    /// animated SVG.
+    ///
+    /// If this is `Some`, attributes animated with SMIL's `set`, `animate` and
+    /// `animateTransform` elements take on their values at that time instead
+    /// of their initial values. Only animations that begin at a fixed time are
+    /// considered and CSS animations are not supported. This only applies to
+    /// functions that parse an SVG string.
+    ///
+    /// _Default:_ `None`.
+    pub animation_time: Option<f64>,
+    /// The names of `data-*` attributes (e.g. `data-series`) that should be
+    /// carried into the PDF.
+    ///
+    /// The content generated for each shape, image and text element is wrapped
+    /// in a marked-content sequence tagged `/Data` whose property list maps the
+    /// names of these attributes on the element and its ancestors to their
+    /// values. This allows downstream tools to correlate the content of the PDF
+    /// with application data. This only applies to functions that parse an SVG
+    /// string.
+    ///
+    /// _Default:_ empty.
+    pub data_attributes: Vec<String>,
+    /// The preferred languages for choosing among the children of `<switch>`
+    /// elements, most preferred first (e.g. `de-CH`, `de`, `en`).
+    ///
+    /// Children whose `systemLanguage` matches a language earlier in the list
+    /// win over those that come first in the document. A language also
+    /// matches more specific tags, like `de` does for `de-CH`. If this is empty
+    /// but [`supported_features`](Self::supported_features) is not, the
+    /// languages of the [`usvg::Options`] are used. This only applies to
+    /// functions that parse an SVG string.
+    ///
+    /// _Default:_ empty.
+    pub languages: Vec<String>,
+    /// Additional feature strings and extension URIs that children of
+    /// `<switch>` elements may require through `requiredFeatures` and
+    /// `requiredExtensions`.
+    ///
+    /// Such children are otherwise skipped, falling back to the next
+    /// applicable child. This only applies to functions that parse an SVG
+    /// string.
+    ///
+    /// _Default:_ empty.
+    pub supported_features: Vec<String>,
+    /// The size in bytes the output should not exceed.
+    ///
+    /// If this is `Some` and the output is larger, the conversion is repeated
+    /// with increasingly aggressive measures until it fits: Raster images are
+    /// downsampled and recompressed as JPEG and coordinates are rounded. This
+    /// is useful for attachment and upload size limits.
+    ///
+    /// Each of the up to six measures converts the whole document again, so a
+    /// target that can't be met takes up to seven times as long as a single
+    /// conversion. The measures for images are skipped if there are no raster
+    /// images. The measures taken are returned by [`convert_tree_sized`] and
+    /// reported through the `log` crate along with a warning if the target
+    /// could not be met. This does not apply to [`convert_tree_into`].
+    ///
+    /// _Default:_ `None`.
+    pub target_size: Option<usize>,
+    /// How to downsample and recompress raster images regardless of the size
+    /// of the output.
+    ///
+    /// The resolution of an image is measured at the size it is displayed
+    /// with in the SVG. Nested SVG images are measured within their own
+    /// viewport. If a [target size](Self::target_size) is set as well, the
+    /// stricter of the two measures applies.
+    ///
+    /// _Default:_ `None`.
+    pub image_policy: Option<ImagePolicy>,
+    /// Whether the output should be trimmed to the drawn content and, if so,
+    /// how much padding in nominal SVG pixels to keep around it.
+    ///
+    /// Many exported SVGs have a large empty canvas around a small drawing. If
+    /// this is `Some`, the page or XObject is sized to the tight bounding box
+    /// of the visible shapes, including their strokes, and images plus the
+    /// padding on each side, keeping the scale of the content. Content outside
+    /// of the viewport stays cut off and SVGs without visible content are not
+    /// trimmed.
+    ///
+    /// _Default:_ `None`.
+    pub trim_to_content: Option<f64>,
+    /// Where to place the SVG within a [viewport](Self::viewport) that is
+    /// larger than it.
+    ///
+    /// By default, the SVG is scaled to fill the viewport as far as its aspect
+    /// ratio allows. If this is `Some`, the SVG instead keeps its native size
+    /// and is placed at the given position, e.g. to put a small logo at the
+    /// center of an A4 page without computing a transform. SVGs larger than
+    /// the viewport are scaled down to fit. This has no effect if neither a
+    /// viewport nor a page size is set.
+    ///
+    /// _Default:_ `None`.
+    pub align: Option<Alignment>,
+    /// The bleed in points to add around each page along with crop and
+    /// registration marks, for print deliverables.
+    ///
+    /// If this is `Some`, the page as converted becomes the TrimBox, the
+    /// BleedBox extends it by the bleed on each side and the MediaBox leaves
+    /// room for the marks around it. The marks are drawn in the registration
+    /// color outside of the BleedBox. Artwork that reaches beyond the viewport
+    /// of the SVG shows in the bleed. This does not apply to
+    /// [`convert_tree_into`].
+    ///
+    /// _Default:_ `None`.
+    pub bleed: Option<f32>,
+    /// A CSS style sheet to apply to the SVG, e.g. to recolor or re-theme it
+    /// for print without editing its source.
+    ///
+    /// The rules are added after the SVG's own style sheets, so they win over
+    /// rules with the same specificity as well as presentation attributes,
+    /// but not over `style` attributes. The selectors and properties supported
+    /// by usvg's CSS engine apply. This only applies to functions that parse
+    /// an SVG string.
+    ///
+    /// _Default:_ `None`.
+    pub style_sheet: Option<String>,
+    /// A renderer for the content of `<foreignObject>` elements, like the HTML
+    /// labels of diagrams.
+    ///
+    /// usvg does not support foreign objects and drops them. If this is
+    /// `Some`, their content is passed to the renderer and the resulting
+    /// raster image is embedded in their place. This only applies to SVG
+    /// source strings as parsed trees do not contain foreign objects.
+    ///
+    /// _Default:_ `None`.
+    pub foreign_objects: Option<ForeignObjectRenderer>,
+    /// Whether strokes should be converted into filled outlines.
+    ///
+    /// Some RIPs and cutting plotters handle fills more reliably than stroked
+    /// paths with dashes, joins and caps. If this is `true`, the outline of
+    /// each stroke is computed and filled instead. Curves and round joins and
+    /// caps are approximated by lines within a twentieth of a point, which
+    /// makes the output larger.
+    ///
+    /// _Default:_ `false`.
+    pub outline_strokes: bool,
+    /// Whether clip paths should be intersected with the geometry they clip.
+    ///
+    /// Some viewers and plotters do not support clipping paths. If this is
+    /// `true`, the paths of a clipped group are cut to the clip region instead
+    /// of setting a clipping path. This requires the clip path to consist of a
+    /// single convex shape and the group to only contain fills, or strokes if
+    /// [`outline_strokes`](Self::outline_strokes) is enabled. Other clip
+    /// paths are applied as usual. Curves in the clipped paths are
+    /// approximated by lines within a twentieth of a point.
+    ///
+    /// _Default:_ `false`.
+    pub flatten_clip_paths: bool,
+    /// Whether the opacity of groups should be applied to their children
+    /// even if they overlap.
+    ///
+    /// A group with a constant opacity is normally painted into a
+    /// transparency group first, so that its children don't show through
+    /// each other. If the children don't overlap, their opacity is multiplied
+    /// by that of the group instead, which avoids the transparency group. If
+    /// this is `true`, this is also done for overlapping children, trading
+    /// accuracy for simpler output. Groups with a mask are always painted
+    /// into a transparency group.
+    ///
+    /// _Default:_ `false`.
+    pub distribute_opacity: bool,
+    /// Where the SVG came from, to be recorded in the XMP metadata of the
+    /// document.
+    ///
+    /// If this is `Some`, the document gets a metadata stream with a custom
+    /// schema that holds the svg2pdf version, these options and the given
+    /// source information. This does not apply to [`convert_tree_into`] as
+    /// metadata belongs to the document.
+    ///
+    /// _Default:_ `None`.
+    pub provenance: Option<Provenance>,
+    /// Thresholds for the size of the document, beyond which warnings are
+    /// emitted.
+    ///
+    /// This does not apply to [`convert_tree_into`] as the caller writes the
+    /// document.
+    ///
+    /// _Default:_ `None`.
+    pub budget: Option<Budget>,
+    /// The maximum number of pixels of a raster image.
+    ///
+    /// Image files only store their width and height in a header, so a small
+    /// file can decode to gigabytes. Larger images are skipped with a warning
+    /// before they are decoded.
+    ///
+    /// _Default:_ `Some(16384 * 16384)`.
+    pub max_image_pixels: Option<u64>,
+    /// The maximum size in bytes that a gzip-compressed SVG image in a data
+    /// URL may decompress to.
+    ///
+    /// Larger images are removed with a warning before the SVG is parsed, and
+    /// so are SVG images in data URLs that contain such images. This only
+    /// applies to functions that parse an SVG string. Compressed `.svgz` files
+    /// that images reference by path are not guarded, so set
+    /// [`load_external`](Self::load_external) to `false` for untrusted input.
+    /// Without the `compress` feature, all compressed SVG images are removed if
+    /// a limit is set because their size can't be measured.
+    ///
+    /// _Default:_ `Some(64 * 1024 * 1024)`.
+    pub max_svg_image_size: Option<usize>,
+    /// Whether to omit all metadata from the document, for publication
+    /// workflows with strict metadata policies.
+    ///
+    /// If this is `true`, neither a document information dictionary naming
+    /// svg2pdf as the producer nor XMP metadata is written, even if
+    /// [`provenance`](Self::provenance) is set. svg2pdf never writes dates or
+    /// document IDs, so the output then only depends on the SVG and the
+    /// options.
+    ///
+    /// _Default:_ `false`.
+    pub strip_metadata: bool,
+}
+
+/// The position of an SVG within a larger viewport.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Alignment {
+    /// The top-left corner.
+    TopLeft,
+    /// The center of the top edge.
+    Top,
+    /// The top-right corner.
+    TopRight,
+    /// The center of the left edge.
+    Left,
+    /// The center.
+    Center,
+    /// The center of the right edge.
+    Right,
+    /// The bottom-left corner.
+    BottomLeft,
+    /// The center of the bottom edge.
+    Bottom,
+    /// The bottom-right corner.
+    BottomRight,
+}
+
+impl Alignment {
+    /// The fraction of the free horizontal and vertical space to the left of
+    /// and above the SVG.
+    fn fractions(self) -> (f64, f64) {
+        match self {
+            Self::TopLeft => (0.0, 0.0),
+            Self::Top => (0.5, 0.0),
+            Self::TopRight => (1.0, 0.0),
+            Self::Left => (0.0, 0.5),
+            Self::Center => (0.5, 0.5),
+            Self::Right => (1.0, 0.5),
+            Self::BottomLeft => (0.0, 1.0),
+            Self::Bottom => (0.5, 1.0),
+            Self::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+/// The unit to round the dimensions of pages up to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum PageRounding {
+    /// Whole PostScript points.
+    Points,
+    /// Whole pixels at the DPI of the conversion.
+    Pixels,
+}
+
+/// How strongly streams are compressed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub enum CompressionLevel {
+    /// Compress quickly at the cost of larger files.
+    Fast,
+    /// Balance the size of the files with the time it takes to compress them.
+    #[default]
+    Default,
+    /// Compress as well as possible, which takes considerably longer.
+    Best,
+    /// A DEFLATE level between 0 (no compression) and 10.
+    Level(u8),
+}
+
+impl CompressionLevel {
+    /// The DEFLATE level for content streams and other objects.
+    fn content(self) -> u8 {
+        match self {
+            Self::Fast => 1,
+            Self::Default => COMPRESSION_LEVEL,
+            Self::Best => 10,
+            Self::Level(level) => level.min(10),
+        }
+    }
+
+    /// The DEFLATE level for raster images and ICC profiles, which are
+    /// compressed a bit harder by default.
+    #[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
+    fn image(self) -> u8 {
+        match self {
+            Self::Default => 8,
+            _ => self.content(),
+        }
+    }
+}
+
+impl Options {
+    /// The dimensions in nominal SVG pixels that the SVG is forced to fill, if
+    /// any.
+    fn viewport(&self) -> Option<(f64, f64)> {
+        let px = |pt: f32| pt as f64 * self.dpi / 72.0;
+        self.page_size
+            .map(|(width, height)| (px(width), px(height)))
+            .or(self.viewport)
+    }
 }
 
 impl Default for Options {
     fn default() -> Self {
         Options {
             viewport: None,
+            page_size: None,
+            page_rounding: None,
             aspect: None,
             dpi: 72.0,
             compress: true,
+            compress_patterns: true,
+            parallel_compression: false,
+            compression: CompressionLevel::Default,
+            object_streams: false,
+            content_chunk_size: None,
+            keep_hidden: false,
+            tagged: false,
+            load_external: true,
+            debug_names: false,
+            annotate_issues: false,
+            animation_time: None,
+            data_attributes: vec![],
+            languages: vec![],
+            supported_features: vec![],
+            target_size: None,
+            image_policy: None,
+            trim_to_content: None,
+            align: None,
+            bleed: None,
+            style_sheet: None,
+            foreign_objects: None,
+            outline_strokes: false,
+            flatten_clip_paths: false,
+            distribute_opacity: false,
+            provenance: None,
+            budget: None,
+            max_image_pixels: Some(16384 * 16384),
+            max_svg_image_size: Some(64 * 1024 * 1024),
+            strip_metadata: false,
         }
     }
 }
 
+/// A hasher that records everything that is fed into it, so that cache keys
+/// can be compared exactly instead of by their hash.
+#[derive(Default)]
+struct KeyRecorder(Vec<u8>);
+
+impl Hasher for KeyRecorder {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        unreachable!("recorded keys are not hashed")
+    }
+}
+
+/// The uncompressed data of a stream along with its compressed data.
+type Deflated = (Rc<[u8]>, Rc<[u8]>);
+
 /// Data is needed during the preparation of the file.
 struct Context<'a> {
     /// The SVG tree.
     tree: &'a Tree,
     /// The bounding box of the PDF page.
-    bbox: &'a Rect,
+    bbox: Rect,
     /// Converter to the PDF coordinate system.
     c: CoordToPdf,
     /// References for functions for gradient color and alpha values.
     function_map: HashMap<String, (Ref, Option<Ref>)>,
     /// The next indirect reference id.
     next_id: i32,
+    /// Allocates the indirect reference ids instead of `next_id` if the caller
+    /// manages them.
+    allocator: Option<&'a mut dyn FnMut() -> Ref>,
     /// The next pattern id, to be used as e.g. `p1`.
     next_pattern: u32,
     /// The next graphics state id, to be used as e.g. `gs2`.
@@ -134,34 +690,106 @@ struct Context<'a> {
     /// Patterns which have been used but not yet written to the file.
     pending_gradients: Vec<PendingGradient>,
     /// Patterns which have been used but not yet written to the file.
-    pending_patterns: Vec<(u32, Ref)>,
+    pending_patterns: Vec<(ResourceName, Ref)>,
     /// Graphics states which have been used but not yet written to the file.
     pending_graphics: Vec<PendingGS>,
     /// XObjects that have been both written as indirect objects and referenced
     /// but still need to be registered with the `Resources` dictionary.
-    pending_xobjects: Vec<(u32, Ref)>,
+    pending_xobjects: Vec<(ResourceName, Ref)>,
     /// IDs of nodes which need to be written to the root of the document as a
     /// transparency group along with their metadata.
     pending_groups: HashMap<String, PendingGroup>,
+    /// How often the hidden layer has been used but not yet registered with
+    /// the `Resources` dictionary.
+    pending_hidden: usize,
     /// This array stores the lengths of the pending vectors and allows to push
     /// each of their elements onto the closes `Resources` dictionary.
-    checkpoints: Vec<[usize; 4]>,
+    checkpoints: Vec<[usize; 5]>,
+    /// The resource name counters of the enclosing `Resources` dictionaries.
+    /// Names restart in each dictionary, so that identical content results in
+    /// identical streams.
+    counters: Vec<[u32; 4]>,
     /// The mask that needs to be applied at the start of a path drawing
     /// operation.
     initial_mask: Option<String>,
     /// Whether the content streas should be compressed.
     compress: bool,
+    /// Whether the content streams of patterns should be compressed.
+    compress_patterns: bool,
+    /// Whether large streams should be compressed on multiple threads.
+    parallel_compression: bool,
+    /// How strongly streams are compressed.
+    compression: CompressionLevel,
+    /// Whether strokes are converted into filled outlines.
+    outline_strokes: bool,
+    /// Whether clip paths are intersected with the geometry they clip.
+    flatten_clip_paths: bool,
+    /// Whether the opacity of groups is applied to overlapping children.
+    distribute_opacity: bool,
+    /// The opacity of the groups around the elements being written that is
+    /// applied to each of them instead of to a transparency group.
+    opacity: f32,
+    /// The number of clip paths of groups drawn directly into the current
+    /// content stream that enclose the elements being written.
+    inline_clips: usize,
+    /// The flattened clip regions of the groups being written, in PDF
+    /// coordinates.
+    clip_regions: Vec<Vec<Point>>,
+    /// The path being written, intersected with the clip regions.
+    clipped_path: Option<Vec<Vec<Point>>>,
+    /// A flag that is set when the result of the conversion is no longer
+    /// needed.
+    cancel: Option<&'a AtomicBool>,
+    /// The optional content group for hidden elements if they are kept.
+    hidden_layer: Option<Ref>,
+    /// Resource names derived from element ids that are already taken, if
+    /// debug names are enabled.
+    debug_names: Option<HashSet<Rc<str>>>,
+    /// What usvg discarded about the elements of the tree.
+    source: Option<&'a SourceData>,
+    /// The labelled element of each marked-content id of the page, if the
+    /// page is tagged.
+    tags: Option<Vec<usize>>,
+    /// The bounding boxes of the elements that were skipped or drawn
+    /// incompletely along with the reason, if issues are annotated.
+    issues: Option<Vec<(usvg::Rect, String)>>,
+    /// Image XObjects that have already been written, keyed by their data or,
+    /// for nested SVG images, their tree and size. Raster images also store
+    /// their width and height in pixels.
+    images: HashMap<ImageKey, (Ref, Option<(u32, u32)>)>,
+    /// Form XObjects and patterns that have already been written, keyed by
+    /// their content, resources and geometry, and ICC profiles, keyed by their
+    /// data.
+    forms: HashMap<Box<[u8]>, Ref>,
+    /// The measures taken to reduce the size of the output.
+    reductions: Reductions,
+    /// How raster images are reduced regardless of the size of the output.
+    image_policy: Option<ImagePolicy>,
+    /// Compressed streams that occurred more than once, like repeated masks,
+    /// along with their uncompressed data to compare against, keyed by a hash
+    /// of the latter. Of streams that occurred once, only the hash is kept.
+    deflated: HashMap<u64, Option<Deflated>>,
+    /// The number of bytes in the content and image streams written so far,
+    /// before compression.
+    stream_bytes: usize,
+    /// The number of XObjects written, for the budget.
+    xobjects: usize,
+    /// The time spent encoding images and compressing content streams so far.
+    timings: Timings,
+    /// The maximum number of pixels of a raster image to decode.
+    max_image_pixels: Option<u64>,
 }
 
 impl<'a> Context<'a> {
     /// Create a new context.
-    fn new(tree: &'a Tree, compress: bool, bbox: &'a Rect, c: CoordToPdf) -> Self {
+    fn new(tree: &'a Tree, compress: bool, bbox: Rect, c: CoordToPdf) -> Self {
         Self {
             tree,
             bbox,
             c,
             function_map: HashMap::new(),
             next_id: 1,
+            allocator: None,
             next_pattern: 0,
             next_graphic: 0,
             next_xobject: 0,
@@ -171,9 +799,36 @@ impl<'a> Context<'a> {
             pending_graphics: vec![],
             pending_xobjects: vec![],
             pending_groups: HashMap::new(),
+            pending_hidden: 0,
             checkpoints: vec![],
+            counters: vec![],
             initial_mask: None,
-            compress,
+            compress: compress && cfg!(feature = "compress"),
+            compress_patterns: true,
+            parallel_compression: false,
+            compression: CompressionLevel::Default,
+            outline_strokes: false,
+            flatten_clip_paths: false,
+            distribute_opacity: false,
+            opacity: 1.0,
+            inline_clips: 0,
+            clip_regions: vec![],
+            clipped_path: None,
+            cancel: None,
+            hidden_layer: None,
+            debug_names: None,
+            source: None,
+            tags: None,
+            issues: None,
+            images: HashMap::new(),
+            forms: HashMap::new(),
+            reductions: Reductions::default(),
+            image_policy: None,
+            deflated: HashMap::new(),
+            stream_bytes: 0,
+            xobjects: 0,
+            timings: Timings::default(),
+            max_image_pixels: None,
         }
     }
 
@@ -184,7 +839,19 @@ impl<'a> Context<'a> {
             self.pending_patterns.len(),
             self.pending_graphics.len(),
             self.pending_xobjects.len(),
+            self.pending_hidden,
+        ]);
+
+        self.counters.push([
+            self.next_pattern,
+            self.next_graphic,
+            self.next_xobject,
+            self.next_shading,
         ]);
+        self.next_pattern = 0;
+        self.next_graphic = 0;
+        self.next_xobject = 0;
+        self.next_shading = 0;
     }
 
     /// Pop a context frame and write all pending objects onto an `Resources`
@@ -193,7 +860,9 @@ impl<'a> Context<'a> {
         resources.color_spaces().insert(SRGB).start::<ColorSpace>().srgb();
         resources.proc_sets([ProcSet::Pdf, ProcSet::ImageColor, ProcSet::ImageGrayscale]);
 
-        let [gradients, patterns, graphics, xobjects] = self.checkpoints.pop().unwrap();
+        let [gradients, patterns, graphics, xobjects, hidden] =
+            self.checkpoints.pop().unwrap();
+        self.restore_counters();
 
         let pending_gradients = self.pending_gradients.split_off(gradients);
         let pending_patterns = self.pending_patterns.split_off(patterns);
@@ -209,156 +878,1150 @@ impl<'a> Context<'a> {
 
         let pending_xobjects = self.pending_xobjects.split_off(xobjects);
         write_xobjects(&pending_xobjects, resources);
+
+        if self.pending_hidden > hidden {
+            if let Some(layer) = self.hidden_layer {
+                resources.insert(Name(b"Properties")).dict().pair(HIDDEN, layer);
+            }
+            self.pending_hidden = hidden;
+        }
+    }
+
+    /// Pop a context frame without writing its pending objects because an
+    /// identical object that uses them was already written.
+    fn discard(&mut self) {
+        let [gradients, patterns, graphics, xobjects, hidden] =
+            self.checkpoints.pop().unwrap();
+        self.restore_counters();
+
+        self.pending_gradients.truncate(gradients);
+        self.pending_patterns.truncate(patterns);
+        self.pending_graphics.truncate(graphics);
+        self.pending_xobjects.truncate(xobjects);
+        self.pending_hidden = self.pending_hidden.min(hidden);
+    }
+
+    /// Continue the resource names of the enclosing `Resources` dictionary.
+    fn restore_counters(&mut self) {
+        let [pattern, graphic, xobject, shading] = self.counters.pop().unwrap();
+        self.next_pattern = pattern;
+        self.next_graphic = graphic;
+        self.next_xobject = xobject;
+        self.next_shading = shading;
+    }
+
+    /// Record the content stream of a Form XObject or pattern along with the
+    /// pending objects of the current frame and further properties, to find
+    /// identical objects in [`forms`](Self::forms).
+    fn form_key(&self, content: &[u8], properties: impl Hash) -> Box<[u8]> {
+        let [gradients, patterns, graphics, xobjects, hidden] =
+            *self.checkpoints.last().unwrap();
+
+        let mut hasher = KeyRecorder::default();
+        content.hash(&mut hasher);
+        properties.hash(&mut hasher);
+
+        // Gradients with the same id on different pages may have different
+        // stops, so their functions identify them.
+        for gradient in &self.pending_gradients[gradients ..] {
+            self.function_map.get(&gradient.id).hash(&mut hasher);
+            gradient.name.hash(&mut hasher);
+            gradient.shading_type.hash(&mut hasher);
+            gradient.coords.map(f32::to_bits).hash(&mut hasher);
+            gradient.spread.hash(&mut hasher);
+        }
+
+        self.pending_patterns[patterns ..].hash(&mut hasher);
+        for gs in &self.pending_graphics[graphics ..] {
+            gs.hash(&mut hasher);
+        }
+
+        self.pending_xobjects[xobjects ..].hash(&mut hasher);
+        (self.pending_hidden > hidden).hash(&mut hasher);
+        hasher.0.into_boxed_slice()
+    }
+
+    /// Start a marked-content sequence that places the following operators in
+    /// the hidden layer. Returns `false` and writes nothing if hidden elements
+    /// are not kept.
+    fn begin_hidden(&mut self, content: &mut Content) -> bool {
+        if self.hidden_layer.is_none() {
+            return false;
+        }
+
+        content
+            .begin_marked_content_with_properties(Name(b"OC"))
+            .properties_named(HIDDEN);
+        self.pending_hidden += 1;
+        true
+    }
+
+    /// Remember that a node was skipped or drawn incompletely, to mark it on
+    /// the page if issues are annotated.
+    fn note_issue(&mut self, node: &usvg::Node, message: String) {
+        let (issues, bbox) = match (self.issues.as_mut(), node.calculate_bbox()) {
+            (Some(issues), Some(bbox)) => (issues, bbox),
+            _ => return,
+        };
+
+        // Lines still get an area that can be clicked.
+        let (width, height) = (bbox.width().max(1.0), bbox.height().max(1.0));
+        if let Some(rect) = usvg::Rect::new(bbox.x(), bbox.y(), width, height) {
+            issues.push((rect, message));
+        }
+    }
+
+    /// Whether the conversion was cancelled and should wind down.
+    fn cancelled(&self) -> bool {
+        self.cancel.is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// Compress a stream if compression is enabled. Identical streams, like
+    /// repeated masks, are only compressed once and share their data.
+    fn finish_content(&mut self, content: Vec<u8>) -> Rc<[u8]> {
+        self.stream_bytes += content.len();
+        if !self.compress {
+            return content.into();
+        }
+
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let key = Hasher::finish(&hasher);
+        let seen = match self.deflated.get(&key) {
+            Some(Some((original, data))) if **original == *content => {
+                return data.clone();
+            }
+            Some(_) => true,
+            None => false,
+        };
+
+        let start = Instant::now();
+        let data: Rc<[u8]> = match self.deflate(&content, self.compression.content()) {
+            Some(data) => data.into(),
+            None => return content.into(),
+        };
+        self.timings.compress += start.elapsed();
+
+        // Most streams occur only once, so their data is only kept once they
+        // repeat.
+        let entry = seen.then(|| (content.into(), data.clone()));
+        self.deflated.insert(key, entry);
+        data
+    }
+
+    /// Compress data at a specific level, on multiple threads if parallel
+    /// compression is enabled. Returns `None` without the `compress` feature.
+    #[cfg(feature = "compress")]
+    fn deflate(&self, data: &[u8], level: u8) -> Option<Vec<u8>> {
+        Some(if self.parallel_compression {
+            deflate_parallel(data, level)
+        } else {
+            deflate_with_level(data, level)
+        })
+    }
+
+    #[cfg(not(feature = "compress"))]
+    fn deflate(&self, _: &[u8], _: u8) -> Option<Vec<u8>> {
+        None
     }
 
     /// Allocate a new indirect reference id.
     fn alloc_ref(&mut self) -> Ref {
+        if let Some(allocator) = &mut self.allocator {
+            return allocator();
+        }
+
         let reference = Ref::new(self.next_id);
         self.next_id += 1;
         reference
     }
 
-    /// Allocate a new pattern id.
-    fn alloc_pattern(&mut self) -> u32 {
+    /// Allocate a new pattern name for the element with the given id.
+    fn alloc_pattern(&mut self, id: &str) -> ResourceName {
         let num = self.next_pattern;
         self.next_pattern += 1;
-        num
+        self.resource_name("p", num, id)
     }
 
-    /// Allocate a new graphics state id.
-    fn alloc_gs(&mut self) -> u32 {
+    /// Allocate a new graphics state name.
+    fn alloc_gs(&mut self) -> ResourceName {
         let num = self.next_graphic;
         self.next_graphic += 1;
-        num
+        ResourceName::numbered("gs", num)
     }
 
-    /// Allocate a new XObject id.
-    fn alloc_xobject(&mut self) -> u32 {
+    /// Allocate a new XObject name for the element with the given id.
+    fn alloc_xobject(&mut self, id: &str) -> ResourceName {
         let num = self.next_xobject;
         self.next_xobject += 1;
-        num
+        self.resource_name("xo", num, id)
     }
 
-    /// Allocate a new shading id.
-    fn alloc_shading(&mut self) -> u32 {
+    /// Allocate a new shading name.
+    fn alloc_shading(&mut self) -> ResourceName {
         let num = self.next_shading;
         self.next_shading += 1;
-        num
+        ResourceName::numbered("sh", num)
+    }
+
+    /// Name a resource either sequentially, e.g. `xo3`, or after the id of
+    /// its element, e.g. `xo_chart`, if debug names are enabled.
+    fn resource_name(&mut self, prefix: &str, num: u32, id: &str) -> ResourceName {
+        if let Some(taken) = self.debug_names.as_mut().filter(|_| !id.is_empty()) {
+            // A suffixed name may itself be the id of another element, so keep
+            // counting up until it is free.
+            let mut name: Rc<str> = format!("{}_{}", prefix, id).into();
+            let mut suffix = num;
+            while !taken.insert(name.clone()) {
+                name = format!("{}_{}_{}", prefix, id, suffix).into();
+                suffix += 1;
+            }
+
+            return ResourceName::Shared(name);
+        }
+
+        ResourceName::numbered(prefix, num)
     }
 }
 
 /// Convert an SVG source string to a standalone PDF buffer.
 ///
 /// Does not load any fonts and consequently cannot convert `text` elements. To
-/// convert text, you should convert your source string to a usvg [`Tree`]
-/// manually (providing a [font database](usvg::Options::fontdb)) and then use
-/// [`convert_tree`].
+/// convert text, enable the `text` feature and use `convert_str_with_fonts` or
+/// convert your source string to a usvg [`Tree`] manually (providing a [font
+/// database](usvg::Options::fontdb)) and then use [`convert_tree`].
 ///
 /// Returns an error if the SVG string is malformed.
 pub fn convert_str(src: &str, options: Options) -> Result<Vec<u8>, usvg::Error> {
-    let mut usvg_opts = usvg::Options::default();
-    if let Some((width, height)) = options.viewport {
-        usvg_opts.default_size =
-            usvg::Size::new(width.max(1.0), height.max(1.0)).unwrap();
-    }
-    let tree = Tree::from_str(src, &usvg_opts.to_ref())?;
-    Ok(convert_tree(&tree, options))
+    let (tree, data) = parse(src, usvg::Options::default().to_ref(), &options)?;
+    Ok(convert_tree_cancellable(&tree, &data, options, None))
 }
 
-/// Convert a [`usvg` tree](Tree) to a standalone PDF buffer.
-pub fn convert_tree(tree: &Tree, options: Options) -> Vec<u8> {
-    let (c, bbox) = get_sizings(tree, &options);
-    let mut ctx = Context::new(&tree, options.compress, &bbox, c);
-
-    let mut writer = PdfWriter::new();
-    let catalog_id = ctx.alloc_ref();
-    let page_tree_id = ctx.alloc_ref();
-    let page_id = ctx.alloc_ref();
-    let content_id = ctx.alloc_ref();
+/// Convert an animated SVG source string to a PDF with one page per frame.
+///
+/// The animation is sampled at `frames` evenly spaced points in time, from its
+/// start up to the time at which its last animation ends or, if it repeats
+/// indefinitely, completes its first iteration. This is a convenient way to
+/// review animations in print or share them with PDF-only recipients. See
+/// [`Options::animation_time`] for the supported kinds of animations, the
+/// field itself is ignored. A `frames` count of zero is treated as one, so the
+/// PDF always has at least one page.
+///
+/// Returns an error if the SVG string is malformed.
+///
+/// ## Example
+/// ```rust
+/// let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+///   <circle cx="50" cy="50" r="10">
+///     <animate attributeName="r" from="10" to="50" dur="2s"/>
+///   </circle>
+/// </svg>"#;
+///
+/// let pdf = svg2pdf::convert_str_flipbook(svg, 5, svg2pdf::Options::default()).unwrap();
+/// # assert!(pdf.starts_with(b"%PDF"));
+/// ```
+pub fn convert_str_flipbook(
+    src: &str,
+    frames: usize,
+    options: Options,
+) -> Result<Vec<u8>, usvg::Error> {
+    let frames = frames.max(1);
+    let duration = animate::duration(src).unwrap_or(0.0);
+    let frame = |i: usize| {
+        let time = if frames > 1 {
+            duration * i as f64 / (frames - 1) as f64
+        } else {
+            0.0
+        };
 
-    writer.catalog(catalog_id).pages(page_tree_id);
-    writer.pages(page_tree_id).count(1).kids([page_id]);
+        let frame = Options {
+            animation_time: Some(time),
+            ..options.clone()
+        };
+        parse(src, usvg::Options::default().to_ref(), &frame)
+    };
 
-    preregister(tree, &mut writer, &mut ctx);
+    // The frames are parsed while writing, so that only one is held in memory
+    // at a time. As animations may change which images are shown, any image
+    // element in the source counts when looking for images.
+    let (first, first_data) = frame(0)?;
+    let pages = Pages {
+        count: frames,
+        images: src.contains("image") || has_raster_images(&first),
+        page: Box::new(|i| match frame(i) {
+            Ok((tree, data)) => Page::Owned(tree, data),
+            Err(err) => {
+                log::warn!("Failed to parse frame {}: {}. Repeating the first.", i, err);
+                Page::Borrowed(&first, &first_data)
+            }
+        }),
+    };
 
-    ctx.push();
-    let content = content_stream(&tree.root(), &mut writer, &mut ctx);
+    Ok(convert_pages(&pages, &options, None, None).0)
+}
 
-    write_masks(tree, &mut writer, &mut ctx);
+/// Convert an SVG source string with text to a standalone PDF buffer.
+///
+/// Text is converted to paths using the fonts from the given database. The
+/// database is only borrowed, so it can be loaded once and shared between
+/// calls.
+///
+/// Requires the `text` feature. Returns an error if the SVG string is
+/// malformed.
+///
+/// ## Example
+/// ```rust,no_run
+/// let mut fontdb = usvg::fontdb::Database::new();
+/// fontdb.load_font_data(std::fs::read("fonts/NotoSans-Regular.ttf").unwrap());
+///
+/// let svg = std::fs::read_to_string("tests/example.svg").unwrap();
+/// let options = svg2pdf::Options::default();
+/// let pdf = svg2pdf::convert_str_with_fonts(&svg, &fontdb, options).unwrap();
+/// ```
+#[cfg(feature = "text")]
+pub fn convert_str_with_fonts(
+    src: &str,
+    fontdb: &usvg::fontdb::Database,
+    options: Options,
+) -> Result<Vec<u8>, usvg::Error> {
+    let usvg_opts = usvg::Options::default();
+    let opt = usvg::OptionsRef { fontdb, ..usvg_opts.to_ref() };
 
-    let mut page = writer.page(page_id);
-    page.media_box(bbox);
-    page.parent(page_tree_id);
-    page.contents(content_id);
+    let (tree, data) = parse(src, opt, &options)?;
+    Ok(convert_tree_cancellable(&tree, &data, options, None))
+}
 
-    let mut resources = page.resources();
-    ctx.pop(&mut resources);
+/// Parse an SVG source string into a usvg [`Tree`], using the viewport of the
+/// conversion options as the default size.
+fn parse(
+    src: &str,
+    usvg_opts: usvg::OptionsRef,
+    options: &Options,
+) -> Result<(Tree, SourceData), usvg::Error> {
+    let mut opt = usvg_opts;
+    if let Some((width, height)) = options.viewport() {
+        opt.default_size = usvg::Size::new(width.max(1.0), height.max(1.0)).unwrap();
+    }
 
-    resources.finish();
-    page.finish();
+    let local_only = usvg::ImageHrefResolver {
+        resolve_data: usvg::ImageHrefResolver::default_data_resolver(),
+        resolve_string: Box::new(|href, _| {
+            log::warn!("Refusing to load external image '{}'. Skipped.", href);
+            None
+        }),
+    };
 
-    let mut stream = writer.stream(content_id, &content);
-    if ctx.compress {
-        stream.filter(Filter::FlateDecode);
+    if !options.load_external {
+        opt.image_href_resolver = &local_only;
     }
 
-    stream.finish();
+    // Each rewrite parses the source on its own. They only run if their
+    // options are set and return early without parsing if a scan of the
+    // source shows that they can't apply.
+    let switched =
+        if options.languages.is_empty() && options.supported_features.is_empty() {
+            None
+        } else {
+            let languages = if options.languages.is_empty() {
+                opt.languages
+            } else {
+                &options.languages
+            };
+            switch::resolve(src, languages, &options.supported_features)
+        };
+    let src = switched.as_deref().unwrap_or(src);
+
+    let animated = options.animation_time.and_then(|time| animate::snapshot(src, time));
+    let src = animated.as_deref().unwrap_or(src);
+
+    let styled = options.style_sheet.as_deref().and_then(|css| style::inject(src, css));
+    let src = styled.as_deref().unwrap_or(src);
+
+    let rasterized = options
+        .foreign_objects
+        .as_ref()
+        .and_then(|renderer| foreign::rasterize(src, renderer));
+    let src = rasterized.as_deref().unwrap_or(src);
+
+    let guarded = options
+        .max_svg_image_size
+        .and_then(|limit| limits::guard_svg_images(src, limit));
+    let src = guarded.as_deref().unwrap_or(src);
+
+    let (marked, data) = if options.data_attributes.is_empty() {
+        (None, DataAttributes::new())
+    } else {
+        data::collect(src, &options.data_attributes)
+    };
+    let src = marked.as_deref().unwrap_or(src);
+
+    let (labelled, labels) = if options.tagged {
+        tags::collect(src)
+    } else {
+        (None, Labels::default())
+    };
+    let src = labelled.as_deref().unwrap_or(src);
 
-    writer.document_info(ctx.alloc_ref()).producer(TextStr("svg2pdf"));
+    Ok((Tree::from_str(src, &opt)?, SourceData { data, labels }))
+}
 
-    writer.finish()
+/// What usvg discards about the elements of a tree while parsing, looked up by
+/// element id during the conversion.
+#[derive(Default)]
+struct SourceData {
+    /// The selected `data-*` attributes of elements.
+    data: DataAttributes,
+    /// The alternative text of elements for the structure tree.
+    labels: Labels,
 }
 
-/// Convert a [`usvg` tree](Tree) into a Form XObject that can be used as part
-/// of a larger document.
-///
-/// This method is intended for use in an existing [`PdfWriter`] workflow. It
-/// will always return an XObject with the width and height of one printer's
-/// point, just like an [`ImageXObject`](pdf_writer::writers::ImageXObject)
-/// would.
+/// A reusable converter for applications that convert many SVG files.
 ///
-/// The resulting object can be used by registering a name and the `id` with a
-/// page's [`/XObject`](pdf_writer::writers::Resources::x_objects) resources
-/// dictionary and then invoking the [`Do`](pdf_writer::Content::x_object)
-/// operator with the name in the page's content stream.
+/// Setting up the [`usvg::Options`], most notably loading a [font
+/// database](usvg::Options::fontdb), can take much longer than the conversion
+/// of a small SVG file itself. A `Converter` owns these parsing options
+/// together with the default conversion [`Options`] so that long-running
+/// applications only have to prepare them once.
 ///
-/// As the conversion process may need to create multiple indirect objects in
-/// the PDF, this function allocates consecutive IDs starting at `id` for its
-/// objects and returns the next available ID for your future writing.
+/// Conversions only need shared access to the converter and it is both `Send`
+/// and `Sync`. A single instance can thus serve a multithreaded server without
+/// cloning the font database for each thread.
 ///
 /// ## Example
-/// Write a PDF file with some text and an SVG graphic.
+/// Convert files on multiple threads with the same converter.
 ///
 /// ```rust
-/// use svg2pdf;
-/// use pdf_writer::{Content, Finish, Name, PdfWriter, Rect, Ref, Str};
-///
-/// // Allocate the indirect reference IDs and names.
-/// let catalog_id = Ref::new(1);
-/// let page_tree_id = Ref::new(2);
-/// let page_id = Ref::new(3);
-/// let font_id = Ref::new(4);
-/// let content_id = Ref::new(5);
-/// let svg_id = Ref::new(6);
-/// let font_name = Name(b"F1");
-/// let svg_name = Name(b"S1");
+/// let converter = svg2pdf::Converter::new(svg2pdf::Options::default());
 ///
-/// // Start writing a PDF.
-/// let mut writer = PdfWriter::new();
-/// writer.catalog(catalog_id).pages(page_tree_id);
-/// writer.pages(page_tree_id).kids([page_id]).count(1);
-///
-/// // Set up a simple A4 page.
-/// let mut page = writer.page(page_id);
-/// page.media_box(Rect::new(0.0, 0.0, 595.0, 842.0));
-/// page.parent(page_tree_id);
-/// page.contents(content_id);
-///
-/// // Add the font and, more importantly, the SVG to the resource dictionary
-/// // so that it can be referenced in the content stream.
-/// let mut resources = page.resources();
-/// resources.x_objects().pair(svg_name, svg_id);
-/// resources.fonts().pair(font_name, font_id);
+/// std::thread::scope(|s| {
+///     for file in ["tests/example.svg", "tests/group.svg"] {
+///         let converter = &converter;
+///         s.spawn(move || {
+///             let svg = std::fs::read_to_string(file).unwrap();
+///             let pdf = converter.convert(&svg).unwrap();
+///             assert!(pdf.starts_with(b"%PDF"));
+///         });
+///     }
+/// });
+/// ```
+#[derive(Debug)]
+pub struct Converter {
+    /// The options for parsing SVG source strings.
+    usvg_opts: usvg::Options,
+    /// The default conversion options.
+    options: Options,
+}
+
+impl Converter {
+    /// Create a new converter with default parsing options.
+    pub fn new(options: Options) -> Self {
+        Self::with_usvg_options(usvg::Options::default(), options)
+    }
+
+    /// Create a new converter with custom parsing options, e.g. with a
+    /// populated font database.
+    pub fn with_usvg_options(usvg_opts: usvg::Options, options: Options) -> Self {
+        Self { usvg_opts, options }
+    }
+
+    /// The default conversion options.
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Mutable access to the default conversion options.
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    /// The options used for parsing SVG source strings.
+    pub fn usvg_options(&self) -> &usvg::Options {
+        &self.usvg_opts
+    }
+
+    /// Mutable access to the options used for parsing SVG source strings.
+    pub fn usvg_options_mut(&mut self) -> &mut usvg::Options {
+        &mut self.usvg_opts
+    }
+
+    /// Register a font from the data of a TrueType or OpenType file or
+    /// collection, e.g. one that is included in the binary or was fetched over
+    /// the network, for setting text.
+    ///
+    /// Requires the `text` feature.
+    #[cfg(feature = "text")]
+    pub fn add_font_data(&mut self, data: Vec<u8>) {
+        self.usvg_opts.fontdb.load_font_data(data);
+    }
+
+    /// Register multiple fonts from their data, as with
+    /// [`add_font_data`](Self::add_font_data).
+    ///
+    /// Requires the `text` feature.
+    #[cfg(feature = "text")]
+    pub fn with_fonts(mut self, fonts: Vec<Vec<u8>>) -> Self {
+        for data in fonts {
+            self.add_font_data(data);
+        }
+        self
+    }
+
+    /// Convert an SVG source string to a standalone PDF buffer with the
+    /// default options.
+    ///
+    /// Returns an error if the SVG string is malformed.
+    pub fn convert(&self, src: &str) -> Result<Vec<u8>, usvg::Error> {
+        self.convert_with(src, self.options.clone())
+    }
+
+    /// Convert an SVG source string to a standalone PDF buffer, overriding the
+    /// default conversion options for this call.
+    ///
+    /// Returns an error if the SVG string is malformed.
+    pub fn convert_with(
+        &self,
+        src: &str,
+        options: Options,
+    ) -> Result<Vec<u8>, usvg::Error> {
+        let (tree, data) = parse(src, self.usvg_opts.to_ref(), &options)?;
+        Ok(convert_tree_cancellable(&tree, &data, options, None))
+    }
+
+    /// Convert an SVG source string to a standalone PDF buffer like
+    /// [`convert_with`](Self::convert_with) and additionally return the time
+    /// spent in each phase of the conversion.
+    ///
+    /// Returns an error if the SVG string is malformed.
+    pub fn convert_profiled(
+        &self,
+        src: &str,
+        options: Options,
+    ) -> Result<(Vec<u8>, Timings), usvg::Error> {
+        let start = Instant::now();
+        let (tree, data) = parse(src, self.usvg_opts.to_ref(), &options)?;
+        let mut timings = Timings {
+            parse: start.elapsed(),
+            ..Timings::default()
+        };
+        let pages = [(&tree, &data)];
+        let (pdf, _) =
+            convert_pages(&Pages::parsed(&pages), &options, None, Some(&mut timings));
+        Ok((pdf, timings))
+    }
+
+    /// Convert a [`usvg` tree](Tree) to a standalone PDF buffer with the
+    /// default options.
+    pub fn convert_tree(&self, tree: &Tree) -> Vec<u8> {
+        convert_tree(tree, self.options.clone())
+    }
+
+    /// Convert an SVG source string to a standalone PDF buffer with the
+    /// default options without blocking the async runtime.
+    ///
+    /// See [`convert_str_async`] for details on scheduling and cancellation.
+    #[cfg(feature = "async")]
+    pub async fn convert_async(
+        self: Arc<Self>,
+        src: String,
+    ) -> Result<Vec<u8>, AsyncError> {
+        spawn_conversion(move |cancel| {
+            let (tree, data) = parse(&src, self.usvg_opts.to_ref(), &self.options)?;
+            Ok(convert_tree_cancellable(
+                &tree,
+                &data,
+                self.options.clone(),
+                Some(cancel),
+            ))
+        })
+        .await
+    }
+}
+
+// Sharing a converter between threads is part of its contract, so adding state
+// that is not thread-safe must fail to compile.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Converter>();
+};
+
+/// Convert an SVG source string to a standalone PDF buffer without blocking
+/// the async runtime.
+///
+/// The conversion runs on Tokio's blocking thread pool, so this function must
+/// be called from within a Tokio runtime. Dropping the returned future cancels
+/// the conversion: Instead of running to completion in the background, it stops
+/// at the next node of the SVG.
+///
+/// Requires the `async` feature. Returns an error if the SVG string is
+/// malformed or the runtime shut down before the conversion finished.
+///
+/// ## Example
+/// ```rust
+/// # let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// # runtime.block_on(async {
+/// let svg = std::fs::read_to_string("tests/example.svg").unwrap();
+/// let options = svg2pdf::Options::default();
+/// let pdf = svg2pdf::convert_str_async(svg, options).await.unwrap();
+/// # assert!(pdf.starts_with(b"%PDF"));
+/// # });
+/// ```
+#[cfg(feature = "async")]
+pub async fn convert_str_async(
+    src: String,
+    options: Options,
+) -> Result<Vec<u8>, AsyncError> {
+    spawn_conversion(move |cancel| {
+        let (tree, data) = parse(&src, usvg::Options::default().to_ref(), &options)?;
+        Ok(convert_tree_cancellable(
+            &tree,
+            &data,
+            options,
+            Some(cancel),
+        ))
+    })
+    .await
+}
+
+/// Run a conversion on the blocking thread pool and signal it to stop when the
+/// future is dropped before the conversion finished.
+#[cfg(feature = "async")]
+async fn spawn_conversion<F>(f: F) -> Result<Vec<u8>, AsyncError>
+where
+    F: FnOnce(&AtomicBool) -> Result<Vec<u8>, usvg::Error> + Send + 'static,
+{
+    /// Sets the flag once the awaiting future goes away.
+    struct CancelOnDrop(Arc<AtomicBool>);
+
+    impl Drop for CancelOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    let guard = CancelOnDrop(Arc::new(AtomicBool::new(false)));
+    let flag = Arc::clone(&guard.0);
+    match tokio::task::spawn_blocking(move || f(&flag)).await {
+        Ok(result) => result.map_err(AsyncError::Parse),
+        Err(err) if err.is_cancelled() => Err(AsyncError::Cancelled),
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+/// An error that can occur during an async conversion.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub enum AsyncError {
+    /// The SVG string is malformed.
+    Parse(usvg::Error),
+    /// The runtime shut down before the conversion finished.
+    Cancelled,
+}
+
+#[cfg(feature = "async")]
+impl std::fmt::Display for AsyncError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Parse(err) => err.fmt(f),
+            Self::Cancelled => f.write_str("the conversion was cancelled"),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl std::error::Error for AsyncError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Parse(err) => Some(err),
+            Self::Cancelled => None,
+        }
+    }
+}
+
+/// Convert a [`usvg` tree](Tree) to a standalone PDF buffer.
+pub fn convert_tree(tree: &Tree, options: Options) -> Vec<u8> {
+    convert_tree_cancellable(tree, &SourceData::default(), options, None)
+}
+
+/// Convert multiple [`usvg` trees](Tree) to a standalone PDF buffer with one
+/// page per tree.
+///
+/// Images that appear in multiple trees are only embedded once and shared
+/// between the pages. This avoids converting each tree separately and
+/// merging the results, which duplicates such resources.
+///
+/// ## Example
+/// ```rust
+/// let opt = usvg::Options::default();
+/// let trees: Vec<usvg::Tree> = ["tests/example.svg", "tests/group.svg"]
+///     .iter()
+///     .map(|path| {
+///         let svg = std::fs::read_to_string(path).unwrap();
+///         usvg::Tree::from_str(&svg, &opt.to_ref()).unwrap()
+///     })
+///     .collect();
+///
+/// let pdf = svg2pdf::convert_trees(&trees, svg2pdf::Options::default());
+/// # assert!(pdf.starts_with(b"%PDF"));
+/// ```
+pub fn convert_trees(trees: &[Tree], options: Options) -> Vec<u8> {
+    let data = SourceData::default();
+    let pages: Vec<(&Tree, &SourceData)> =
+        trees.iter().map(|tree| (tree, &data)).collect();
+    let pages = Pages::parsed(&pages);
+    convert_pages(&pages, &options, None, None).0
+}
+
+/// Convert a [`usvg` tree](Tree) to a standalone PDF buffer and return it
+/// along with the width and height of its page in points and the measures
+/// that were taken to meet the [target size](Options::target_size).
+///
+/// The size accounts for the viewport, the DPI, trimming and bleed, so callers
+/// that place the result do not have to derive it from the options. The
+/// measures are listed in the order they were applied, each on top of the
+/// previous ones, and are empty if the first attempt was small enough.
+pub fn convert_tree_sized(
+    tree: &Tree,
+    options: Options,
+) -> (Vec<u8>, (f32, f32), Vec<Reduction>) {
+    let size = page_size(tree, &options);
+    let data = SourceData::default();
+    let (pdf, applied) =
+        convert_pages(&Pages::parsed(&[(tree, &data)]), &options, None, None);
+    (pdf, size, applied)
+}
+
+/// Convert a tree to a standalone PDF buffer, stopping early if the `cancel`
+/// flag is set. The output of a cancelled conversion is incomplete.
+fn convert_tree_cancellable(
+    tree: &Tree,
+    data: &SourceData,
+    options: Options,
+    cancel: Option<&AtomicBool>,
+) -> Vec<u8> {
+    convert_pages(&Pages::parsed(&[(tree, data)]), &options, cancel, None).0
+}
+
+/// The pages of a standalone PDF buffer.
+struct Pages<'a> {
+    /// The number of pages.
+    count: usize,
+    /// Whether any page may contain raster images.
+    images: bool,
+    /// Produces the tree of a page along with what usvg discarded about its
+    /// elements. Trees may be parsed on demand, so that only the one of the
+    /// page that is being written is held in memory.
+    page: Box<dyn Fn(usize) -> Page<'a> + 'a>,
+}
+
+impl<'a> Pages<'a> {
+    /// Pages whose trees were parsed up front.
+    fn parsed(pages: &'a [(&'a Tree, &'a SourceData)]) -> Self {
+        Self {
+            count: pages.len(),
+            images: pages.iter().any(|&(tree, _)| has_raster_images(tree)),
+            page: Box::new(move |i| Page::Borrowed(pages[i].0, pages[i].1)),
+        }
+    }
+}
+
+/// The tree of a page along with what usvg discarded about its elements.
+enum Page<'a> {
+    Borrowed(&'a Tree, &'a SourceData),
+    Owned(Tree, SourceData),
+}
+
+impl Page<'_> {
+    fn get(&self) -> (&Tree, &SourceData) {
+        match self {
+            Self::Borrowed(tree, data) => (tree, data),
+            Self::Owned(tree, data) => (tree, data),
+        }
+    }
+}
+
+/// Convert pages to a standalone PDF buffer, stopping early if the `cancel`
+/// flag is set. The time spent in each phase is added to `timings` if given.
+/// Also returns the measures taken to meet the target size.
+fn convert_pages(
+    pages: &Pages,
+    options: &Options,
+    cancel: Option<&AtomicBool>,
+    mut timings: Option<&mut Timings>,
+) -> (Vec<u8>, Vec<Reduction>) {
+    let mut usage = Usage::default();
+    let (pdf, applied) =
+        reduce(pages.images, options.target_size, cancel, |reductions| {
+            let (pdf, written, spent) = write_pages(pages, options, reductions, cancel);
+            usage = written;
+            if let Some(timings) = timings.as_deref_mut() {
+                *timings += spent;
+            }
+            pdf
+        });
+
+    if let Some(budget) = &options.budget {
+        budget.check(&usage);
+    }
+
+    (pdf, applied)
+}
+
+/// Write the pages of a standalone PDF buffer with the given measures to
+/// reduce its size in effect. Also returns the size of the document and the
+/// time spent writing it.
+fn write_pages(
+    pages: &Pages,
+    options: &Options,
+    reductions: &Reductions,
+    cancel: Option<&AtomicBool>,
+) -> (Vec<u8>, Usage, Timings) {
+    let start = Instant::now();
+    let mut timings = Timings::default();
+    let mut stream_bytes = 0;
+    let mut xobjects = 0;
+    let mut writer = PdfWriter::new();
+    let mut next_id = 1;
+    let mut alloc = || {
+        let reference = Ref::new(next_id);
+        next_id += 1;
+        reference
+    };
+
+    let catalog_id = alloc();
+    let page_tree_id = alloc();
+    let page_ids: Vec<Ref> = (0 .. pages.count).map(|_| alloc()).collect();
+    let (nodes, parents) = page_tree(page_tree_id, &page_ids, &mut alloc);
+    let metadata_id = options
+        .provenance
+        .as_ref()
+        .filter(|_| !options.strip_metadata)
+        .map(|_| alloc());
+    let struct_tree_id = options.tagged.then(&mut alloc);
+    let mut struct_tree = options.tagged.then(|| StructTree::new(alloc()));
+
+    for node in nodes {
+        let mut tree = writer.pages(node.id);
+        tree.count(node.count as i32).kids(node.kids);
+        if let Some(parent) = node.parent {
+            tree.parent(parent);
+        }
+    }
+
+    // Pages are written as they are produced, so the layer for hidden elements
+    // is only created once a page needs it.
+    let mut hidden_layer = None;
+
+    // Images, Form XObjects, patterns and compressed streams are shared
+    // between pages.
+    let mut images = HashMap::new();
+    let mut forms = HashMap::new();
+    let mut deflated = HashMap::new();
+    for (i, (&page_id, &parent)) in page_ids.iter().zip(&parents).enumerate() {
+        let page = (pages.page)(i);
+        let (tree, data) = page.get();
+        if options.keep_hidden && hidden_layer.is_none() && has_hidden(tree) {
+            hidden_layer = Some(Ref::new(next_id));
+            next_id += 1;
+        }
+
+        let (mut c, bbox, user_unit) = get_page_sizings(tree, options);
+        if let Some(user_unit) = user_unit {
+            log::warn!(
+                "Page {} exceeds the maximum page size of {} pt and was scaled down by a factor of {}.",
+                i + 1,
+                MAX_PAGE_SIZE,
+                user_unit,
+            );
+        }
+
+        c.set_precision(reductions.precision);
+        let mut ctx = Context::new(tree, options.compress, bbox, c);
+        ctx.reductions = *reductions;
+        ctx.compress_patterns = options.compress_patterns;
+        ctx.parallel_compression = options.parallel_compression;
+        ctx.compression = options.compression;
+        ctx.outline_strokes = options.outline_strokes;
+        ctx.flatten_clip_paths = options.flatten_clip_paths;
+        ctx.distribute_opacity = options.distribute_opacity;
+        ctx.max_image_pixels = options.max_image_pixels;
+        ctx.image_policy = options.image_policy;
+        ctx.next_id = next_id;
+        ctx.cancel = cancel;
+        ctx.hidden_layer = hidden_layer;
+        ctx.source = Some(data);
+        ctx.tags = options.tagged.then(Vec::new);
+        ctx.issues = options.annotate_issues.then(Vec::new);
+        ctx.images = std::mem::take(&mut images);
+        ctx.forms = std::mem::take(&mut forms);
+        ctx.deflated = std::mem::take(&mut deflated);
+        if options.debug_names {
+            ctx.debug_names = Some(HashSet::new());
+        }
+
+        preregister(tree, &mut writer, &mut ctx);
+
+        ctx.push();
+        let mut content = render_content(&tree.root(), &mut writer, &mut ctx);
+        let boxes = options.bleed.map(|bleed| {
+            let size = (bbox.x2 - bbox.x1, bbox.y2 - bbox.y1);
+            let unit = user_unit.unwrap_or(1.0);
+            let (surrounded, boxes) =
+                marks::surround(&content, size, bleed, unit, &mut writer, &mut ctx);
+            content = surrounded;
+            boxes
+        });
+
+        let chunks = match options.content_chunk_size {
+            Some(size) => split_content(&content, size),
+            None => vec![content.as_slice()],
+        };
+
+        let content_ids: Vec<Ref> = chunks.iter().map(|_| ctx.alloc_ref()).collect();
+
+        write_masks(tree, &mut writer, &mut ctx);
+
+        let struct_parents =
+            struct_tree.as_mut().zip(ctx.tags.take()).map(|(st, marks)| {
+                st.write_page(&mut writer, page_id, &data.labels, &marks, &mut ctx)
+            });
+
+        let mut page = writer.page(page_id);
+        if let Some(boxes) = &boxes {
+            page.media_box(boxes.media);
+            page.bleed_box(boxes.bleed);
+            page.trim_box(boxes.trim);
+        } else {
+            page.media_box(bbox);
+        }
+
+        page.parent(parent);
+        if let Some(user_unit) = user_unit {
+            page.user_unit(user_unit);
+        }
+        if let Some(key) = struct_parents {
+            page.struct_parents(key);
+        }
+
+        if let [content_id] = content_ids[..] {
+            page.contents(content_id);
+        } else {
+            page.contents_array(content_ids.iter().copied());
+        }
+
+        if let Some(issues) = ctx.issues.take().filter(|issues| !issues.is_empty()) {
+            let mut annotations = page.annotations();
+            for (rect, message) in issues {
+                annotations
+                    .push()
+                    .subtype(AnnotationType::Text)
+                    .rect(ctx.c.pdf_rect(rect))
+                    .contents(TextStr(&message))
+                    .color_rgb(1.0, 0.5, 0.0);
+            }
+        }
+
+        let mut resources = page.resources();
+        ctx.pop(&mut resources);
+
+        resources.finish();
+        page.finish();
+
+        for (&content_id, chunk) in content_ids.iter().zip(chunks) {
+            let data = ctx.finish_content(chunk.to_vec());
+            let mut stream = writer.stream(content_id, &data);
+            if ctx.compress {
+                stream.filter(Filter::FlateDecode);
+            }
+        }
+
+        next_id = ctx.next_id;
+        stream_bytes += ctx.stream_bytes;
+        xobjects += ctx.xobjects;
+        timings += ctx.timings;
+        images = ctx.images;
+        forms = ctx.forms;
+        deflated = ctx.deflated;
+    }
+
+    let mut catalog = writer.catalog(catalog_id);
+    catalog.pages(page_tree_id);
+    if let Some(metadata_id) = metadata_id {
+        catalog.pair(Name(b"Metadata"), metadata_id);
+    }
+    if let Some(struct_tree_id) = struct_tree_id {
+        catalog.pair(Name(b"StructTreeRoot"), struct_tree_id);
+        catalog.mark_info().marked(true);
+    }
+    if let Some(layer) = hidden_layer {
+        let mut properties = catalog.insert(Name(b"OCProperties")).dict();
+        properties.insert(Name(b"OCGs")).array().item(layer);
+        let mut config = properties.insert(Name(b"D")).dict();
+        config.insert(Name(b"Order")).array().item(layer);
+        config.insert(Name(b"OFF")).array().item(layer);
+    }
+    catalog.finish();
+
+    if let Some(layer) = hidden_layer {
+        writer
+            .indirect(layer)
+            .dict()
+            .pair(Name(b"Type"), Name(b"OCG"))
+            .pair(Name(b"Name"), TextStr("Hidden"));
+    }
+
+    if let Some((provenance, metadata_id)) = options.provenance.as_ref().zip(metadata_id)
+    {
+        // The metadata stays uncompressed, so that tools which do not parse
+        // PDF files can find it.
+        let xmp = provenance::xmp(provenance, options);
+        writer
+            .stream(metadata_id, xmp.as_bytes())
+            .pair(Name(b"Type"), Name(b"Metadata"))
+            .pair(Name(b"Subtype"), Name(b"XML"));
+    }
+
+    if let Some((struct_tree, root)) = struct_tree.zip(struct_tree_id) {
+        struct_tree.finish(&mut writer, root);
+    }
+
+    write_document_info(&mut writer, Ref::new(next_id), options);
+
+    let finishing = Instant::now();
+    let pdf = writer.finish();
+    let pdf = if options.object_streams {
+        xref::pack(&pdf, |data| deflate(data, options)).unwrap_or(pdf)
+    } else {
+        pdf
+    };
+    timings.write = finishing.elapsed();
+    timings.render =
+        (finishing - start).saturating_sub(timings.images + timings.compress);
+
+    let usage = Usage {
+        objects: next_id as usize,
+        xobjects,
+        stream_bytes,
+        pages: pages.count,
+    };
+
+    (pdf, usage, timings)
+}
+
+/// The maximum number of kids of a node in the page tree.
+const PAGE_TREE_KIDS: usize = 32;
+
+/// A node of the page tree.
+struct PageTreeNode {
+    id: Ref,
+    parent: Option<Ref>,
+    kids: Vec<Ref>,
+    count: usize,
+}
+
+/// Lay out a balanced page tree with the given root above the pages, so that
+/// no node has more than [`PAGE_TREE_KIDS`] kids. Returns the nodes and the
+/// parent of each page.
+fn page_tree(
+    root: Ref,
+    pages: &[Ref],
+    alloc: &mut impl FnMut() -> Ref,
+) -> (Vec<PageTreeNode>, Vec<Ref>) {
+    let mut nodes: Vec<PageTreeNode> = vec![];
+    let mut parents = vec![root; pages.len()];
+
+    // Group the pages and then the nodes of each level until the root can hold
+    // them. The nodes of a level are contiguous, starting at `start`, which is
+    // `None` while the level consists of pages.
+    let mut level: Vec<(Ref, usize)> = pages.iter().map(|&id| (id, 1)).collect();
+    let mut start: Option<usize> = None;
+    while level.len() > PAGE_TREE_KIDS {
+        let next_start = nodes.len();
+        let mut next = vec![];
+        for (j, chunk) in level.chunks(PAGE_TREE_KIDS).enumerate() {
+            let id = alloc();
+            for k in j * PAGE_TREE_KIDS .. j * PAGE_TREE_KIDS + chunk.len() {
+                match start {
+                    Some(start) => nodes[start + k].parent = Some(id),
+                    None => parents[k] = id,
+                }
+            }
+
+            let count = chunk.iter().map(|&(_, count)| count).sum();
+            let kids = chunk.iter().map(|&(kid, _)| kid).collect();
+            nodes.push(PageTreeNode { id, parent: None, kids, count });
+            next.push((id, count));
+        }
+
+        level = next;
+        start = Some(next_start);
+    }
+
+    if let Some(start) = start {
+        for node in &mut nodes[start ..] {
+            node.parent = Some(root);
+        }
+    }
+
+    let kids = level.iter().map(|&(kid, _)| kid).collect();
+    nodes.push(PageTreeNode {
+        id: root,
+        parent: None,
+        kids,
+        count: pages.len(),
+    });
+    (nodes, parents)
+}
+
+/// Convert a [`usvg` tree](Tree) into a Form XObject that can be used as part
+/// of a larger document.
+///
+/// This method is intended for use in an existing [`PdfWriter`] workflow. It
+/// will always return an XObject with the width and height of one printer's
+/// point, just like an [`ImageXObject`](pdf_writer::writers::ImageXObject)
+/// would.
+///
+/// The resulting object can be used by registering a name and the `id` with a
+/// page's [`/XObject`](pdf_writer::writers::Resources::x_objects) resources
+/// dictionary and then invoking the [`Do`](pdf_writer::Content::x_object)
+/// operator with the name in the page's content stream.
+///
+/// As the conversion process may need to create multiple indirect objects in
+/// the PDF, this function allocates consecutive IDs starting at `id` for its
+/// objects and returns the next available ID for your future writing.
+///
+/// ## Example
+/// Write a PDF file with some text and an SVG graphic.
+///
+/// ```rust
+/// use svg2pdf;
+/// use pdf_writer::{Content, Finish, Name, PdfWriter, Rect, Ref, Str};
+///
+/// // Allocate the indirect reference IDs and names.
+/// let catalog_id = Ref::new(1);
+/// let page_tree_id = Ref::new(2);
+/// let page_id = Ref::new(3);
+/// let font_id = Ref::new(4);
+/// let content_id = Ref::new(5);
+/// let svg_id = Ref::new(6);
+/// let font_name = Name(b"F1");
+/// let svg_name = Name(b"S1");
+///
+/// // Start writing a PDF.
+/// let mut writer = PdfWriter::new();
+/// writer.catalog(catalog_id).pages(page_tree_id);
+/// writer.pages(page_tree_id).kids([page_id]).count(1);
+///
+/// // Set up a simple A4 page.
+/// let mut page = writer.page(page_id);
+/// page.media_box(Rect::new(0.0, 0.0, 595.0, 842.0));
+/// page.parent(page_tree_id);
+/// page.contents(content_id);
+///
+/// // Add the font and, more importantly, the SVG to the resource dictionary
+/// // so that it can be referenced in the content stream.
+/// let mut resources = page.resources();
+/// resources.x_objects().pair(svg_name, svg_id);
+/// resources.fonts().pair(font_name, font_id);
 /// resources.finish();
 /// page.finish();
 ///
@@ -401,54 +2064,257 @@ pub fn convert_tree_into(
     writer: &mut PdfWriter,
     id: Ref,
 ) -> Ref {
-    let (c, bbox) = get_sizings(tree, &options);
-    let mut ctx = Context::new(&tree, options.compress, &bbox, c);
-
-    ctx.next_id = id.get() + 1;
-
-    preregister(tree, writer, &mut ctx);
-
-    ctx.push();
-    let content = content_stream(&tree.root(), writer, &mut ctx);
-
-    write_masks(tree, writer, &mut ctx);
-
-    let mut xobject = writer.form_xobject(id, &content);
-    xobject.bbox(bbox);
-    xobject.matrix([
-        1.0 / (bbox.x2 - bbox.x1),
-        0.0,
-        0.0,
-        1.0 / (bbox.y2 - bbox.y1),
-        0.0,
-        0.0,
-    ]);
-
-    if ctx.compress {
-        xobject.filter(Filter::FlateDecode);
-    }
-
-    let mut resources = xobject.resources();
-    ctx.pop(&mut resources);
-
-    ctx.alloc_ref()
+    tree_to_xobject(tree, options, &Reductions::default(), writer, id)
+}
+
+/// Convert a [`usvg` tree](Tree) into a Form XObject like
+/// [`convert_tree_into`] and additionally return the width and height in
+/// points that the SVG would have on a page with the same options.
+///
+/// As the XObject is one point wide and high, this is the scaling to apply to
+/// draw it at its design size.
+pub fn convert_tree_into_sized(
+    tree: &Tree,
+    options: Options,
+    writer: &mut PdfWriter,
+    id: Ref,
+) -> (Ref, (f32, f32)) {
+    let (_, bbox) = get_sizings(tree, &options);
+    let next_id = convert_tree_into(tree, options, writer, id);
+    (next_id, (bbox.x2 - bbox.x1, bbox.y2 - bbox.y1))
+}
+
+/// Convert a [`usvg` tree](Tree) into a Form XObject like
+/// [`convert_tree_into`], but without scaling it down to one point.
+///
+/// The XObject has no matrix and its bounding box, which is also returned,
+/// spans the width and height in points that the SVG would have on a page with
+/// the same options, starting at the origin. This is for embedders that set up
+/// the placement transformation themselves. The XObject is written with the
+/// given `id` and the next free id is returned along with the bounding box.
+///
+/// ## Example
+/// ```rust
+/// use pdf_writer::{Content, Name, PdfWriter, Ref};
+///
+/// let svg = std::fs::read_to_string("tests/example.svg").unwrap();
+/// let tree = usvg::Tree::from_str(&svg, &usvg::Options::default().to_ref()).unwrap();
+///
+/// let mut writer = PdfWriter::new();
+/// let svg_id = Ref::new(5);
+/// let (next_id, bbox) =
+///     svg2pdf::convert_tree_into_raw(&tree, svg2pdf::Options::default(), &mut writer, svg_id);
+///
+/// // Draw the graphic at half its size, centered on an A4 page.
+/// let x = (595.0 - (bbox.x2 - bbox.x1) / 2.0) / 2.0;
+/// let y = (842.0 - (bbox.y2 - bbox.y1) / 2.0) / 2.0;
+/// let mut content = Content::new();
+/// content.transform([0.5, 0.0, 0.0, 0.5, x, y]).x_object(Name(b"S1"));
+/// # assert!(next_id.get() > svg_id.get());
+/// ```
+pub fn convert_tree_into_raw(
+    tree: &Tree,
+    options: Options,
+    writer: &mut PdfWriter,
+    id: Ref,
+) -> (Ref, Rect) {
+    let (_, bbox) = get_sizings(tree, &options);
+    let reductions = Reductions::default();
+    let (next_id, _) =
+        write_tree_xobject(tree, &options, &reductions, writer, id, false, None);
+    (next_id, bbox)
+}
+
+/// Convert a [`usvg` tree](Tree) into a Form XObject like
+/// [`convert_tree_into`], but obtain the indirect reference ids of all written
+/// objects from `allocator` instead of using consecutive ids.
+///
+/// This allows interleaving the objects with those of the caller when ids
+/// are managed globally, without guessing how many ids the conversion needs.
+/// Returns the reference of the Form XObject, which is allocated first.
+///
+/// ## Example
+/// ```rust
+/// use pdf_writer::{PdfWriter, Ref};
+///
+/// let svg = std::fs::read_to_string("tests/example.svg").unwrap();
+/// let tree = usvg::Tree::from_str(&svg, &usvg::Options::default().to_ref()).unwrap();
+///
+/// let mut next = 100;
+/// let mut alloc = || {
+///     next += 1;
+///     Ref::new(next)
+/// };
+///
+/// let mut writer = PdfWriter::new();
+/// let options = svg2pdf::Options::default();
+/// let svg_id = svg2pdf::convert_tree_into_with_allocator(&tree, options, &mut writer, &mut alloc);
+/// assert_eq!(svg_id, Ref::new(101));
+/// ```
+pub fn convert_tree_into_with_allocator(
+    tree: &Tree,
+    options: Options,
+    writer: &mut PdfWriter,
+    allocator: &mut dyn FnMut() -> Ref,
+) -> Ref {
+    let id = allocator();
+    let reductions = Reductions::default();
+    write_tree_xobject(
+        tree,
+        &options,
+        &reductions,
+        writer,
+        id,
+        true,
+        Some(allocator),
+    );
+    id
+}
+
+/// Convert a tree into a Form XObject with the given measures to reduce its
+/// size in effect.
+fn tree_to_xobject(
+    tree: &Tree,
+    options: Options,
+    reductions: &Reductions,
+    writer: &mut PdfWriter,
+    id: Ref,
+) -> Ref {
+    write_tree_xobject(tree, &options, reductions, writer, id, true, None).0
+}
+
+/// Write a tree into a Form XObject whose bounding box spans its size in
+/// points. If `normalize` is true, its matrix scales it down to one point.
+/// The ids of further objects are taken from the `allocator` if there is one.
+/// Returns the next free id otherwise, along with the number of XObjects
+/// written, including the tree's own.
+fn write_tree_xobject<'a>(
+    tree: &'a Tree,
+    options: &Options,
+    reductions: &Reductions,
+    writer: &mut PdfWriter,
+    id: Ref,
+    normalize: bool,
+    allocator: Option<&'a mut dyn FnMut() -> Ref>,
+) -> (Ref, usize) {
+    let mut ctx = tree_context(tree, options, reductions, Ref::new(id.get() + 1));
+    ctx.allocator = allocator;
+    let bbox = ctx.bbox;
+
+    preregister(tree, writer, &mut ctx);
+
+    ctx.push();
+    let content = content_stream(&tree.root(), writer, &mut ctx);
+
+    write_masks(tree, writer, &mut ctx);
+
+    let mut xobject = writer.form_xobject(id, &content);
+    xobject.bbox(bbox);
+    if normalize {
+        xobject.matrix([
+            1.0 / (bbox.x2 - bbox.x1),
+            0.0,
+            0.0,
+            1.0 / (bbox.y2 - bbox.y1),
+            0.0,
+            0.0,
+        ]);
+    }
+
+    if ctx.compress {
+        xobject.filter(Filter::FlateDecode);
+    }
+
+    let mut resources = xobject.resources();
+    ctx.pop(&mut resources);
+
+    (Ref::new(ctx.next_id), ctx.xobjects + 1)
+}
+
+/// Set up a context for converting a tree into a Form XObject or content of
+/// the caller, with the first free reference id.
+fn tree_context<'a>(
+    tree: &'a Tree,
+    options: &Options,
+    reductions: &Reductions,
+    next_id: Ref,
+) -> Context<'a> {
+    let (mut c, bbox) = get_sizings(tree, options);
+    c.set_precision(reductions.precision);
+    let mut ctx = Context::new(tree, options.compress, bbox, c);
+    ctx.reductions = *reductions;
+    ctx.compress_patterns = options.compress_patterns;
+    ctx.parallel_compression = options.parallel_compression;
+    ctx.compression = options.compression;
+    ctx.outline_strokes = options.outline_strokes;
+    ctx.flatten_clip_paths = options.flatten_clip_paths;
+    ctx.distribute_opacity = options.distribute_opacity;
+    ctx.max_image_pixels = options.max_image_pixels;
+    ctx.image_policy = options.image_policy;
+    if options.debug_names {
+        ctx.debug_names = Some(HashSet::new());
+    }
+
+    ctx.next_id = next_id.get();
+    ctx
+}
+
+/// The physical width and height in points of the page a tree is converted
+/// to, including bleed and marks.
+fn page_size(tree: &Tree, options: &Options) -> (f32, f32) {
+    let (_, bbox, user_unit) = get_page_sizings(tree, options);
+    let unit = user_unit.unwrap_or(1.0);
+    let margin = options.bleed.map_or(0.0, |bleed| marks::margin(bleed, unit));
+    (
+        (bbox.x2 - bbox.x1 + 2.0 * margin) * unit,
+        (bbox.y2 - bbox.y1 + 2.0 * margin) * unit,
+    )
 }
 
 /// Calculates the bounding box and size conversions for an usvg tree.
 fn get_sizings(tree: &Tree, options: &Options) -> (CoordToPdf, Rect) {
     let native_size = tree.svg_node().size;
-    let viewport = if let Some((width, height)) = options.viewport {
+    let viewport = if let Some((width, height)) = options.viewport() {
         (width, height)
     } else {
         (native_size.width(), native_size.height())
     };
 
-    let c = CoordToPdf::new(
-        viewport,
-        options.dpi,
-        tree.svg_node().view_box,
-        options.aspect,
-    );
+    let c = match (options.viewport(), options.align) {
+        (Some(_), Some(align)) => {
+            // Keep the native size unless the SVG does not fit.
+            let scale = (viewport.0 / native_size.width())
+                .min(viewport.1 / native_size.height())
+                .min(1.0);
+            let size = (native_size.width() * scale, native_size.height() * scale);
+            let (fx, fy) = align.fractions();
+
+            let mut c = CoordToPdf::new(
+                size,
+                options.dpi,
+                tree.svg_node().view_box,
+                options.aspect,
+            );
+            c.place(
+                (viewport.0 - size.0) * fx,
+                (viewport.1 - size.1) * fy,
+                viewport.1,
+            );
+            c
+        }
+        _ => CoordToPdf::new(
+            viewport,
+            options.dpi,
+            tree.svg_node().view_box,
+            options.aspect,
+        ),
+    };
+
+    if let Some(padding) = options.trim_to_content {
+        if let Some(trimmed) = get_trimmed_sizings(tree, &c, viewport, padding) {
+            return trimmed;
+        }
+    }
 
     (
         c,
@@ -456,6 +2322,183 @@ fn get_sizings(tree: &Tree, options: &Options) -> (CoordToPdf, Rect) {
     )
 }
 
+/// Calculates the bounding box and size conversions for an usvg tree that is
+/// trimmed to its drawn content plus the padding in pixels, keeping the scale
+/// of the untrimmed conversion `c`. Returns `None` if nothing visible is drawn.
+fn get_trimmed_sizings(
+    tree: &Tree,
+    c: &CoordToPdf,
+    viewport: (f64, f64),
+    padding: f64,
+) -> Option<(CoordToPdf, Rect)> {
+    let (x1, y1, x2, y2) = content_bbox(tree)?;
+
+    // Convert to pixels in the viewport and cut off what lies outside of it.
+    let (fx, fy, ox, oy) = (c.factor_x(), c.factor_y(), c.offset_x(), c.offset_y());
+    let left = (x1 * fx + ox).max(0.0) - padding;
+    let top = (y1 * fy + oy).max(0.0) - padding;
+    let right = (x2 * fx + ox).min(viewport.0) + padding;
+    let bottom = (y2 * fy + oy).min(viewport.1) + padding;
+    if right <= left || bottom <= top {
+        return None;
+    }
+
+    let view_box = ViewBox {
+        rect: usvg::Rect::new(
+            (left - ox) / fx,
+            (top - oy) / fy,
+            (right - left) / fx,
+            (bottom - top) / fy,
+        )?,
+        aspect: AspectRatio {
+            defer: false,
+            align: Align::None,
+            slice: false,
+        },
+    };
+
+    let viewport = (right - left, bottom - top);
+    let c = CoordToPdf::new(viewport, c.dpi(), view_box, Some(view_box.aspect));
+    Some((
+        c,
+        Rect::new(0.0, 0.0, c.px_to_pt(viewport.0), c.px_to_pt(viewport.1)),
+    ))
+}
+
+/// Calculates the bounding box of the visible shapes, including their strokes,
+/// and images of a tree in its user space as left, top, right and bottom.
+fn content_bbox(tree: &Tree) -> Option<(f64, f64, f64, f64)> {
+    let mut bbox: Option<(f64, f64, f64, f64)> = None;
+    let mut add = |x1: f64, y1: f64, x2: f64, y2: f64| {
+        bbox = Some(match bbox {
+            Some((l, t, r, b)) => (l.min(x1), t.min(y1), r.max(x2), b.max(y2)),
+            None => (x1, y1, x2, y2),
+        });
+    };
+
+    for node in tree.root().descendants() {
+        // Clip paths, masks and patterns are not drawn by themselves.
+        if node.ancestors().any(|node| matches!(*node.borrow(), NodeKind::Defs)) {
+            continue;
+        }
+
+        match *node.borrow() {
+            NodeKind::Path(ref path)
+                if path.visibility == Visibility::Visible
+                    && (path.fill.is_some() || path.stroke.is_some()) =>
+            {
+                let ts = node.abs_transform();
+                if let Some(b) = path.data.bbox_with_transform(ts, path.stroke.as_ref()) {
+                    add(b.left(), b.top(), b.right(), b.bottom());
+                }
+            }
+            NodeKind::Image(ref image) if image.visibility == Visibility::Visible => {
+                let ts = node.abs_transform();
+                let rect = image.view_box.rect;
+                for (x, y) in [
+                    (rect.left(), rect.top()),
+                    (rect.right(), rect.top()),
+                    (rect.left(), rect.bottom()),
+                    (rect.right(), rect.bottom()),
+                ] {
+                    let (x, y) = ts.apply(x, y);
+                    add(x, y, x, y);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    bbox
+}
+
+/// Calculates the bounding box and size conversions for a standalone page.
+///
+/// Pages larger than the maximum page size are scaled down to fit. They then
+/// declare a user space unit larger than one point so that they retain their
+/// physical dimensions, which is returned as the third element.
+fn get_page_sizings(tree: &Tree, options: &Options) -> (CoordToPdf, Rect, Option<f32>) {
+    let (c, bbox) = get_sizings(tree, options);
+
+    // The bleed and marks around the page need to fit as well.
+    let margin = options.bleed.map_or(0.0, |bleed| marks::margin(bleed, 1.0));
+    let largest = (bbox.x2 - bbox.x1).max(bbox.y2 - bbox.y1) + 2.0 * margin;
+    let user_unit = match fit_user_unit(largest) {
+        Some(user_unit) => user_unit,
+        None => {
+            let (c, bbox) = round_page(c, bbox, options, 1.0);
+            return (c, bbox, None);
+        }
+    };
+
+    let scaled = Options {
+        dpi: options.dpi * user_unit as f64,
+        ..options.clone()
+    };
+
+    let (c, bbox) = get_sizings(tree, &scaled);
+    let (c, bbox) = round_page(c, bbox, options, user_unit);
+    (c, bbox, Some(user_unit))
+}
+
+/// Enlarge a page to whole points or pixels if the options ask for it and
+/// stretch its content along. The page has the given user space unit.
+fn round_page(
+    mut c: CoordToPdf,
+    bbox: Rect,
+    options: &Options,
+    user_unit: f32,
+) -> (CoordToPdf, Rect) {
+    // The size of the unit to round to in user space units.
+    let step = match options.page_rounding {
+        Some(PageRounding::Points) => 1.0 / user_unit as f64,
+        Some(PageRounding::Pixels) => 72.0 / options.dpi / user_unit as f64,
+        None => return (c, bbox),
+    };
+
+    let (width, height) = (bbox.x2 - bbox.x1, bbox.y2 - bbox.y1);
+    if width <= 0.0 || height <= 0.0 {
+        return (c, bbox);
+    }
+
+    // Sizes that are whole already shouldn't grow because of float errors.
+    let round = |length: f32| ((length as f64 / step - 1e-4).ceil() * step) as f32;
+    let (rounded_width, rounded_height) = (round(width), round(height));
+    c.stretch(
+        rounded_width as f64 / width as f64,
+        rounded_height as f64 / height as f64,
+    );
+    (
+        c,
+        Rect::new(
+            bbox.x1,
+            bbox.y1,
+            bbox.x1 + rounded_width,
+            bbox.y1 + rounded_height,
+        ),
+    )
+}
+
+/// The user space unit that a page whose largest side measures `largest`
+/// points needs to fit within the maximum page size, if it exceeds it.
+fn fit_user_unit(largest: f32) -> Option<f32> {
+    // Round up so that the scaled page is guaranteed to fit.
+    (largest > MAX_PAGE_SIZE).then(|| (largest / MAX_PAGE_SIZE * 1000.0).ceil() / 1000.0)
+}
+
+/// Whether the tree contains hidden elements that would draw something if they
+/// were visible.
+fn has_hidden(tree: &Tree) -> bool {
+    tree.root().descendants().any(|node| match *node.borrow() {
+        NodeKind::Path(ref path) => {
+            path.visibility != Visibility::Visible
+                && (path.fill.is_some() || path.stroke.is_some())
+        }
+        NodeKind::Image(ref image) => image.visibility != Visibility::Visible,
+        _ => false,
+    })
+}
+
 fn preregister(tree: &Tree, writer: &mut PdfWriter, ctx: &mut Context) {
     for element in tree.defs().children() {
         match *element.borrow() {
@@ -475,308 +2518,2856 @@ fn content_stream<'a>(
     node: &usvg::Node,
     writer: &mut PdfWriter,
     ctx: &mut Context<'a>,
+) -> Rc<[u8]> {
+    let res = render_content(node, writer, ctx);
+    ctx.finish_content(res)
+}
+
+/// Write the uncompressed operators for the children of a node.
+fn render_content(
+    node: &usvg::Node,
+    writer: &mut PdfWriter,
+    ctx: &mut Context,
 ) -> Vec<u8> {
     let mut content = Content::new();
-    let num = ctx.alloc_gs();
+    let gs = ctx.alloc_gs();
 
     if let Some(reference) = ctx
         .initial_mask
         .as_ref()
         .and_then(|id| ctx.pending_groups.get(id).map(|g| g.reference))
     {
-        content.set_parameters(Name(format!("gs{}", num).as_bytes()));
-        ctx.pending_graphics.push(PendingGS::soft_mask(reference, num));
+        content.set_parameters(gs.as_name());
+        ctx.pending_graphics.push(PendingGS::soft_mask(reference, gs));
+    }
+
+    let inline_clips = std::mem::take(&mut ctx.inline_clips);
+    render_children(node, writer, &mut content, ctx);
+    ctx.inline_clips = inline_clips;
+    content.finish()
+}
+
+/// Write the operators for the children of a node into an existing content
+/// stream.
+fn render_children(
+    node: &usvg::Node,
+    writer: &mut PdfWriter,
+    content: &mut Content,
+    ctx: &mut Context,
+) {
+    for element in node.children() {
+        if &element == node {
+            continue;
+        }
+
+        if ctx.cancelled() {
+            break;
+        }
+
+        // Only the elements directly on a page are tagged. Marked content in
+        // Form XObjects would need its own entries in the parent tree.
+        let tagged = node.parent().is_none() && tags::begin(&element, content, ctx);
+
+        let data = ctx.source.and_then(|source| source.data.get(&*element.id()));
+        if let Some(attrs) = data {
+            let mut marked = content.begin_marked_content_with_properties(Name(b"Data"));
+            let mut properties = marked.properties_direct();
+            for (name, value) in attrs {
+                properties.pair(Name(name.as_bytes()), TextStr(value));
+            }
+        }
+
+        render_element(&element, writer, content, ctx);
+
+        if data.is_some() {
+            content.end_marked_content();
+        }
+
+        if tagged {
+            content.end_marked_content();
+        }
+    }
+}
+
+/// Write the operators for a single node.
+fn render_element(
+    element: &usvg::Node,
+    writer: &mut PdfWriter,
+    content: &mut Content,
+    ctx: &mut Context,
+) {
+    match *element.borrow() {
+        NodeKind::Path(ref path) => path.render(element, writer, content, ctx),
+        NodeKind::Group(ref group) => group.render(element, writer, content, ctx),
+        NodeKind::Image(ref image) => image.render(element, writer, content, ctx),
+        _ => {}
+    }
+}
+
+/// Split an uncompressed content stream into chunks of at most `size` bytes.
+///
+/// The chunks only end where an operator does, so an operator never ends up
+/// apart from its operands. An operator longer than `size` gets a chunk of its
+/// own.
+fn split_content(content: &[u8], size: usize) -> Vec<&[u8]> {
+    let mut chunks = vec![];
+    let mut start = 0;
+    let mut last = 0;
+
+    // Anything after the last newline is an operator, too.
+    let mut ends = operator_ends(content);
+    if ends.last() != Some(&content.len()) {
+        ends.push(content.len());
+    }
+
+    for end in ends {
+        if end - start > size && last > start {
+            chunks.push(&content[start .. last]);
+            start = last;
+        }
+
+        if end - start > size {
+            chunks.push(&content[start .. end]);
+            start = end;
+        }
+
+        last = end;
+    }
+
+    if start < content.len() || chunks.is_empty() {
+        chunks.push(&content[start ..]);
+    }
+
+    chunks
+}
+
+/// The offsets after the newlines that terminate the operators of a content
+/// stream.
+///
+/// The content writer ends each operator with a newline, but strings and the
+/// property dictionaries of marked content can contain newlines of their own,
+/// so these are skipped.
+fn operator_ends(content: &[u8]) -> Vec<usize> {
+    let mut ends = vec![];
+    let mut strings = 0;
+    let mut nesting = 0;
+    let mut i = 0;
+
+    while i < content.len() {
+        match content[i] {
+            b'\\' if strings > 0 => i += 1,
+            b'(' => strings += 1,
+            b')' if strings > 0 => strings -= 1,
+            _ if strings > 0 => {}
+            b'<' if content.get(i + 1) == Some(&b'<') => {
+                nesting += 1;
+                i += 1;
+            }
+            b'>' if content.get(i + 1) == Some(&b'>') => {
+                nesting -= 1;
+                i += 1;
+            }
+            b'<' => {
+                // Hex strings can't contain newlines that matter, but their
+                // closing bracket must not close a dictionary.
+                i += content[i ..].iter().position(|&b| b == b'>').unwrap_or(0);
+            }
+            b'[' => nesting += 1,
+            b']' => nesting -= 1,
+            b'\n' if nesting <= 0 => ends.push(i + 1),
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    ends
+}
+
+/// Draw a clipping path into a content stream.
+fn apply_clip_path(path_id: Option<&String>, content: &mut Content, ctx: &mut Context) {
+    if let Some(clip_path) = path_id.and_then(|id| ctx.tree.defs_by_id(id)) {
+        if let NodeKind::ClipPath(ref path) = *clip_path.borrow() {
+            apply_clip_path(path.clip_path.as_ref(), content, ctx);
+
+            for child in clip_path.children() {
+                match *child.borrow() {
+                    NodeKind::Path(ref path) => {
+                        draw_path(&path.data.0, path.transform, content, &ctx.c);
+                        content.clip_nonzero();
+                        content.end_path();
+                    }
+                    NodeKind::ClipPath(_) => {}
+                    _ => unreachable!(),
+                }
+            }
+        } else {
+            unreachable!();
+        }
     }
+}
+
+/// Prepare a mask to be written to the file. This will calculate the metadata
+/// and create a `pending_group`.
+fn apply_mask(
+    mask_id: Option<&String>,
+    bbox: usvg::Rect,
+    pdf_bbox: Rect,
+    ctx: &mut Context,
+) -> Option<Ref> {
+    if let Some(mask_node) = mask_id.and_then(|id| ctx.tree.defs_by_id(id)) {
+        if let NodeKind::Mask(ref mask) = *mask_node.borrow() {
+            let reference = ctx.alloc_ref();
+            let (bbox, matrix) = if mask.content_units == usvg::Units::UserSpaceOnUse {
+                (ctx.bbox, None)
+            } else {
+                let point = mask_node.transform().apply(mask.rect.x(), mask.rect.y());
+                let (x, y) = ctx.c.precise_point(point);
+                let transform = [
+                    1.0,
+                    0.0,
+                    0.0,
+                    1.0,
+                    (bbox.x() + x) as f32,
+                    (bbox.y() + y) as f32,
+                ];
+                (pdf_bbox, Some(transform))
+            };
+
+            apply_mask(mask.mask.as_ref(), mask.rect, pdf_bbox, ctx);
+
+            ctx.pending_groups.insert(mask.id.clone(), PendingGroup {
+                reference,
+                bbox,
+                matrix,
+                initial_mask: mask.mask.clone(),
+            });
+
+            Some(reference)
+        } else {
+            unreachable!()
+        }
+    } else {
+        None
+    }
+}
+
+/// A color helper function that stores colors with values between 0.0 and 1.0.
+#[derive(Debug, Clone, Copy)]
+struct RgbColor {
+    /// Red.
+    r: f32,
+    /// Green.
+    g: f32,
+    /// Blue.
+    b: f32,
+}
+
+impl RgbColor {
+    /// Create a new color.
+    fn new(r: f32, g: f32, b: f32) -> RgbColor {
+        RgbColor { r, g, b }
+    }
+
+    /// Create a new color from u8 color components between 0.0 and 255.0.
+    fn from_u8(r: u8, g: u8, b: u8) -> RgbColor {
+        RgbColor::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
+    }
+
+    /// Create a RGB array for use in PDF.
+    fn to_array(self) -> [f32; 3] {
+        [self.r, self.g, self.b]
+    }
+}
+
+impl From<usvg::Color> for RgbColor {
+    fn from(color: usvg::Color) -> Self {
+        Self::from_u8(color.red, color.green, color.blue)
+    }
+}
+
+/// Write the functions for a gradient with its stops. Also registers them with
+/// the context and can create an alpha gradient function.
+fn register_functions(
+    writer: &mut PdfWriter,
+    ctx: &mut Context,
+    id: &str,
+    stops: &[Stop],
+) {
+    let func_ref = ctx.alloc_ref();
+    stops_to_function(writer, func_ref, stops, false);
+
+    let alpha_ref = if stops.iter().any(|stop| stop.opacity.value() < 1.0) {
+        let alpha_ref = ctx.alloc_ref();
+        stops_to_function(writer, alpha_ref, stops, true);
+        Some(alpha_ref)
+    } else {
+        None
+    };
+
+    ctx.function_map.insert(id.to_string(), (func_ref, alpha_ref));
+}
+
+/// Convert a list of stops to a function and write it.
+fn stops_to_function(
+    writer: &mut PdfWriter,
+    id: Ref,
+    stops: &[Stop],
+    alpha: bool,
+) -> bool {
+    let range = [0.0f32, 1.0f32].into_iter().cycle().take(if alpha { 2 } else { 6 });
+
+    // The function of the colors or the alphas, with offsets and values.
+    let mut stops: Vec<(f32, Vec<f32>)> = stops
+        .iter()
+        .map(|stop| {
+            let value = if alpha {
+                vec![stop.opacity.value() as f32]
+            } else {
+                RgbColor::from(stop.color).to_array().to_vec()
+            };
+
+            (stop.offset.value() as f32, value)
+        })
+        .collect();
+
+    if stops.is_empty() {
+        return false;
+    }
+
+    if stops[0].0 != 0.0 {
+        stops.insert(0, (0.0, stops[0].1.clone()));
+    }
+
+    minimize_stops(&mut stops);
+
+    if stops.len() == 1 {
+        let mut exp = writer.exponential_function(id);
+        let value = &stops[0].1;
+
+        exp.domain([0.0, 1.0]);
+        exp.range(range);
+        exp.c0(value.iter().copied());
+        exp.c1(value.iter().copied());
+        exp.n(1.0);
+        return true;
+    }
+
+    let mut stitching = writer.stitching_function(id);
+    stitching.domain([0.0, 1.0]);
+    stitching.range(range.clone());
+
+    let mut func_array = stitching.insert(Name(b"Functions")).array();
+    let mut bounds = Vec::new();
+    let mut encode = Vec::with_capacity(2 * (stops.len() - 1));
+
+    for window in stops.windows(2) {
+        let (a, b) = (&window[0], &window[1]);
+        bounds.push(b.0);
+        let mut exp = ExponentialFunction::start(func_array.push());
+        exp.domain([0.0, 1.0]);
+        exp.range(range.clone());
+        exp.c0(a.1.iter().copied());
+        exp.c1(b.1.iter().copied());
+        exp.n(1.0);
+
+        encode.extend([0.0, 1.0]);
+    }
+
+    func_array.finish();
+    bounds.pop();
+    stitching.bounds(bounds);
+    stitching.encode(encode);
+
+    true
+}
+
+/// Remove the stops that do not change a gradient function. Tools often write
+/// gradients with many redundant stops.
+///
+/// These are stops that repeat the previous one, the middle of three stops at
+/// the same offset and stops in between two others with the same value. A
+/// function whose stops all have the same value is reduced to a single stop.
+fn minimize_stops(stops: &mut Vec<(f32, Vec<f32>)>) {
+    stops.dedup();
+
+    // Removing a stop can make the one before it redundant, e.g. if it
+    // separated two stops with the same offset and value, so that one is
+    // checked again.
+    let mut i = 1;
+    while i + 1 < stops.len() {
+        let (a, b, c) = (&stops[i - 1], &stops[i], &stops[i + 1]);
+        if (a.0 == b.0 && b.0 == c.0) || (a.1 == b.1 && b.1 == c.1) {
+            stops.remove(i);
+            i = (i - 1).max(1);
+        } else {
+            i += 1;
+        }
+    }
+
+    if stops.len() == 2 && stops[0].1 == stops[1].1 {
+        stops.pop();
+    }
+}
+
+/// Create and return the writer for an transparency group form XObject.
+fn form_xobject<'a>(
+    writer: &'a mut PdfWriter,
+    reference: Ref,
+    content: &'a [u8],
+    bbox: Rect,
+    compress: bool,
+    has_color: bool,
+) -> FormXObject<'a> {
+    let mut form = writer.form_xobject(reference, content);
+    form.bbox(bbox);
+
+    if compress {
+        form.filter(Filter::FlateDecode);
+    }
+
+    let mut group = form.group();
+    group.transparency();
+    group.isolated(true);
+    group.knockout(false);
+
+    let space = group.color_space();
+    if has_color {
+        space.srgb();
+    } else {
+        space.d65_gray();
+    }
+
+    group.finish();
+    form
+}
+
+/// Write the document information dictionary unless metadata is stripped.
+fn write_document_info(writer: &mut PdfWriter, id: Ref, options: &Options) {
+    if !options.strip_metadata {
+        writer.document_info(id).producer(TextStr("svg2pdf"));
+    }
+}
+
+/// The compression level for content streams.
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// Compress data with the DEFLATE algorithm if the options ask for it. Returns
+/// `None` if the data should be written uncompressed, which is always the case
+/// without the `compress` feature.
+#[cfg(feature = "compress")]
+fn deflate(data: &[u8], options: &Options) -> Option<Vec<u8>> {
+    options
+        .compress
+        .then(|| deflate_with_level(data, options.compression.content()))
+}
+
+#[cfg(not(feature = "compress"))]
+fn deflate(_: &[u8], _: &Options) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(feature = "compress")]
+thread_local! {
+    /// Setting up a compressor allocates its dictionary and Huffman tables, so
+    /// each thread reuses a single one for all streams.
+    static COMPRESSOR: RefCell<CompressorOxide> = RefCell::new(CompressorOxide::default());
+}
+
+/// Compress data with the DEFLATE algorithm at a specific level. If the
+/// compressor fails, the data is stored uncompressed in the same format so
+/// that the stream still decodes.
+#[cfg(feature = "compress")]
+fn deflate_with_level(data: &[u8], level: u8) -> Vec<u8> {
+    deflate_segment(data, level, DataFormat::Zlib, TDEFLFlush::Finish)
+        .unwrap_or_else(|| store(data))
+}
+
+/// Wrap data in uncompressed DEFLATE blocks in the zlib format.
+#[cfg(feature = "compress")]
+fn store(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = u16::MAX as usize;
+
+    let blocks = data.len().div_ceil(MAX_BLOCK).max(1);
+    let mut output = Vec::with_capacity(data.len() + 5 * blocks + 6);
+    output.extend([0x78, 0x01]);
+    for i in 0 .. blocks {
+        let block = &data[i * MAX_BLOCK .. data.len().min((i + 1) * MAX_BLOCK)];
+        let len = block.len() as u16;
+        output.push((i + 1 == blocks) as u8);
+        output.extend(len.to_le_bytes());
+        output.extend((!len).to_le_bytes());
+        output.extend(block);
+    }
+
+    output.extend(miniz_oxide::mz_adler32_oxide(1, data).to_be_bytes());
+    output
+}
+
+/// The minimum size of a stream before it is compressed on multiple threads.
+#[cfg(feature = "compress")]
+const PARALLEL_THRESHOLD: usize = 1 << 20;
+
+/// The minimum size of the segments of a stream compressed in parallel.
+#[cfg(feature = "compress")]
+const PARALLEL_SEGMENT: usize = 1 << 18;
+
+/// Compress data with the DEFLATE algorithm at a specific level, splitting
+/// large data into segments that are compressed on multiple threads.
+#[cfg(feature = "compress")]
+fn deflate_parallel(data: &[u8], level: u8) -> Vec<u8> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if data.len() < PARALLEL_THRESHOLD || threads < 2 {
+        return deflate_with_level(data, level);
+    }
+
+    deflate_segments(
+        data,
+        level,
+        (data.len() / threads + 1).max(PARALLEL_SEGMENT),
+    )
+}
+
+/// Compress segments of the given size on one thread each.
+///
+/// Each segment is compressed independently and ends on a byte boundary
+/// through a sync flush, so the raw segments concatenate into a single valid
+/// DEFLATE stream. If any segment fails, the data is compressed on the
+/// current thread instead.
+#[cfg(feature = "compress")]
+fn deflate_segments(data: &[u8], level: u8, size: usize) -> Vec<u8> {
+    let count = data.len().div_ceil(size);
+    let segments: Option<Vec<Vec<u8>>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = data
+            .chunks(size)
+            .enumerate()
+            .map(|(i, segment)| {
+                let flush = if i + 1 == count {
+                    TDEFLFlush::Finish
+                } else {
+                    TDEFLFlush::Sync
+                };
+                scope.spawn(move || {
+                    deflate_segment(segment, level, DataFormat::Raw, flush)
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().ok().flatten())
+            .collect()
+    });
+
+    let segments = match segments {
+        Some(segments) => segments,
+        None => return deflate_with_level(data, level),
+    };
+
+    // Wrap the raw DEFLATE stream in the zlib format.
+    let len = segments.iter().map(Vec::len).sum::<usize>();
+    let mut output = Vec::with_capacity(len + 6);
+    output.extend([0x78, 0x9C]);
+    for segment in segments {
+        output.extend(segment);
+    }
+
+    output.extend(miniz_oxide::mz_adler32_oxide(1, data).to_be_bytes());
+    output
+}
+
+/// Compress data with the DEFLATE algorithm in the given format. With a sync
+/// flush, the stream is not finished and can be continued. Returns `None` if
+/// the compressor fails.
+#[cfg(feature = "compress")]
+fn deflate_segment(
+    data: &[u8],
+    level: u8,
+    format: DataFormat,
+    flush: TDEFLFlush,
+) -> Option<Vec<u8>> {
+    COMPRESSOR.with(|compressor| {
+        let mut compressor = compressor.borrow_mut();
+        compressor.reset();
+        compressor.set_format_and_level(format, level);
+
+        let mut output = vec![0; (data.len() / 2).max(64)];
+        let mut in_pos = 0;
+        let mut out_pos = 0;
+        loop {
+            let (status, read, written) = compress(
+                &mut compressor,
+                &data[in_pos ..],
+                &mut output[out_pos ..],
+                flush,
+            );
+
+            in_pos += read;
+            out_pos += written;
+
+            // A sync flush is complete when all data was read and the output
+            // was not filled up, which means nothing is left to be written.
+            let synced = flush == TDEFLFlush::Sync
+                && in_pos == data.len()
+                && out_pos < output.len();
+
+            match status {
+                TDEFLStatus::Done => {
+                    output.truncate(out_pos);
+                    return Some(output);
+                }
+                TDEFLStatus::Okay if synced => {
+                    output.truncate(out_pos);
+                    return Some(output);
+                }
+                TDEFLStatus::Okay => {
+                    if output.len() - out_pos < 30 {
+                        output.resize(output.len() * 2, 0);
+                    }
+                }
+                status => {
+                    log::warn!("Failed to compress stream ({:?}), storing it.", status);
+                    return None;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn files() {
+        let paths = fs::read_dir("tests").unwrap();
+        for path in paths {
+            let path = path.unwrap();
+            if path.path().extension().is_none_or(|ext| ext != "svg") {
+                continue;
+            }
+
+            let base_name = path.file_name().to_string_lossy().to_string();
+
+            println!("{}", base_name);
+
+            let doc = fs::read_to_string(path.path()).unwrap();
+            let options = Options { dpi: 72.0, ..Options::default() };
+            let buf = convert_str(&doc, options).unwrap();
+
+            let len = base_name.len();
+            let file_name = format!("{}.pdf", &base_name[0 .. len - 4]);
+
+            std::fs::write(format!("target/{}", file_name), buf).unwrap();
+        }
+    }
+
+    #[test]
+    fn split_content_between_operators() {
+        let content = b"0 0 m\n10 0 l\n10 10 l\nh\nf";
+        assert_eq!(split_content(content, 100), [&content[..]]);
+        assert_eq!(split_content(content, 10), [
+            &b"0 0 m\n"[..],
+            b"10 0 l\n",
+            b"10 10 l\nh\n",
+            b"f"
+        ]);
+        assert_eq!(split_content(content, 2), [
+            &b"0 0 m\n"[..],
+            b"10 0 l\n",
+            b"10 10 l\n",
+            b"h\n",
+            b"f"
+        ]);
+    }
+
+    #[test]
+    fn split_content_outside_of_operands() {
+        let content =
+            b"/Span <<\n  /Alt (line\none)\n>> BDC\n0 0 m\n[(a\nb) 1] TJ\nEMC\n";
+        assert_eq!(operator_ends(content), [34, 40, 53, 57]);
+        assert_eq!(split_content(content, 10), [
+            &b"/Span <<\n  /Alt (line\none)\n>> BDC\n"[..],
+            b"0 0 m\n",
+            b"[(a\nb) 1] TJ\n",
+            b"EMC\n",
+        ]);
+
+        // Escaped parentheses don't end strings.
+        let content = b"(a\\)\n) Tj\nn\n";
+        assert_eq!(split_content(content, 1), [&b"(a\\)\n) Tj\n"[..], b"n\n"]);
+    }
+
+    #[test]
+    fn split_content_in_data_attributes() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <rect id="r" width="5" height="5" data-note="line one&#10;line two"/>
+            <rect x="5" width="5" height="5"/>
+        </svg>"#;
+
+        let options = Options {
+            compress: false,
+            tagged: true,
+            data_attributes: vec!["data-note".into()],
+            content_chunk_size: Some(1),
+            ..Options::default()
+        };
+
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        let note = pdf.find("/data#2Dnote").unwrap();
+        let stream = pdf[.. note].rfind("stream\n").unwrap();
+        let endstream = note + pdf[note ..].find("endstream").unwrap();
+        let chunk = &pdf[stream .. endstream];
+        assert!(chunk.contains("BDC"));
+        assert!(chunk.contains("\0l\0i\0n\0e\0 \0t\0w\0o)"));
+    }
+
+    #[test]
+    #[cfg(feature = "compress")]
+    fn stored_streams() {
+        use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+        let data: Vec<u8> = (0 .. 150_000).map(|i: u32| (i % 251) as u8).collect();
+        for len in [0, 10, 65_535, 65_536, data.len()] {
+            let stored = store(&data[.. len]);
+            assert_eq!(decompress_to_vec_zlib(&stored).unwrap(), &data[.. len]);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "compress")]
+    fn parallel_compression_round_trip() {
+        let data: Vec<u8> = (0 .. 1 << 20).map(|i: u32| (i % 251) as u8).collect();
+        let compressed = deflate_segments(&data, 6, 100_000);
+        let decompressed =
+            miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap();
+        assert!(decompressed == data);
+    }
+
+    #[test]
+    fn animation_snapshot() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg">
+            <rect width="10" height="10" style="fill: red">
+                <animate attributeName="fill" from="red" to="blue" dur="2s"/>
+                <animate attributeName="x" values="0;10;30" dur="4s" fill="freeze"/>
+                <set attributeName="opacity" to="0.5" begin="3s"/>
+            </rect>
+        </svg>"#;
+
+        let at = |time| animate::snapshot(src, time).unwrap();
+        assert!(at(1.0).contains(
+            r#"<rect x="5" width="10" height="10" style="fill: red;fill:rgb(128,0,128)">"#
+        ));
+        assert!(at(3.0).contains(
+            r#"<rect opacity="0.5" x="20" width="10" height="10" style="fill: red">"#
+        ));
+        assert!(at(9.0).contains(
+            r#"<rect opacity="0.5" x="30" width="10" height="10" style="fill: red">"#
+        ));
+    }
+    #[test]
+    fn target_size() {
+        let svg = fs::read_to_string("tests/metro.svg").unwrap();
+        let full = convert_str(&svg, Options::default()).unwrap();
+
+        let options = Options {
+            target_size: Some(full.len()),
+            ..Options::default()
+        };
+        assert_eq!(convert_str(&svg, options).unwrap(), full);
+
+        let options = Options {
+            target_size: Some(full.len() - 1),
+            ..Options::default()
+        };
+        assert!(convert_str(&svg, options).unwrap().len() < full.len());
+
+        // Without raster images, only the coordinates are rounded.
+        let opt = usvg::Options::default();
+        let tree = Tree::from_str(&svg, &opt.to_ref()).unwrap();
+        let options = Options {
+            target_size: Some(1),
+            ..Options::default()
+        };
+        let (_, _, applied) = convert_tree_sized(&tree, options.clone());
+        assert_eq!(applied, [Reduction::Precision(2), Reduction::Precision(1)]);
+
+        let svg = fs::read_to_string("tests/image.svg").unwrap();
+        let tree = Tree::from_str(&svg, &opt.to_ref()).unwrap();
+        let (_, _, applied) = convert_tree_sized(&tree, options);
+        assert_eq!(applied.first(), Some(&Reduction::Downsample(0.5)));
+        assert_eq!(applied.len(), if cfg!(feature = "jpeg") { 6 } else { 4 });
+
+        // A cancelled conversion is not repeated.
+        let cancel = AtomicBool::new(false);
+        let mut attempts = 0;
+        let (_, applied) = reduce(true, Some(1), Some(&cancel), |_| {
+            attempts += 1;
+            cancel.store(true, Ordering::Relaxed);
+            vec![0; 10]
+        });
+        assert_eq!(attempts, 1);
+        assert!(applied.is_empty());
+    }
+
+    #[test]
+    fn trim_to_content() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="1000" height="1000">
+            <rect x="100" y="200" width="50" height="30" stroke="black" stroke-width="10"/>
+        </svg>"#;
+
+        let media_box = |padding| {
+            let options = Options {
+                compress: false,
+                trim_to_content: padding,
+                ..Options::default()
+            };
+
+            let pdf = convert_str(src, options).unwrap();
+            let pdf = String::from_utf8_lossy(&pdf).into_owned();
+            let start = pdf.find("/MediaBox").unwrap();
+            pdf[start .. start + pdf[start ..].find(']').unwrap() + 1].to_string()
+        };
+
+        assert_eq!(media_box(None), "/MediaBox [0 0 1000 1000]");
+        assert_eq!(media_box(Some(0.0)), "/MediaBox [0 0 60 40]");
+        assert_eq!(media_box(Some(5.0)), "/MediaBox [0 0 70 50]");
+    }
+
+    #[test]
+    fn align_on_larger_viewport() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50">
+            <rect width="100" height="50"/>
+        </svg>"#;
+
+        let options = Options {
+            compress: false,
+            viewport: Some((300.0, 300.0)),
+            align: Some(Alignment::BottomRight),
+            ..Options::default()
+        };
+
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/MediaBox [0 0 300 300]"));
+        assert!(pdf.contains("200 50 m\n300 50 l\n300 0 l\n200 0 l\n"));
+    }
+
+    #[test]
+    fn bleed_and_marks() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50">
+            <rect x="-10" y="-10" width="120" height="70"/>
+        </svg>"#;
+
+        let options = Options {
+            compress: false,
+            bleed: Some(9.0),
+            ..Options::default()
+        };
+
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/MediaBox [0 0 154 104]"));
+        assert!(pdf.contains("/BleedBox [18 18 136 86]"));
+        assert!(pdf.contains("/TrimBox [27 27 127 77]"));
+        assert!(pdf.contains("/All [/Separation /All /DeviceCMYK"));
+    }
+
+    #[test]
+    fn explicit_page_size() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50">
+            <rect width="100" height="50"/>
+        </svg>"#;
+
+        let options = Options {
+            compress: false,
+            dpi: 96.0,
+            page_size: Some((300.0, 200.0)),
+            ..Options::default()
+        };
+
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/MediaBox [0 0 300 200]"));
+        assert!(pdf.contains("0 175 m\n300 175 l\n300 25 l\n0 25 l"));
+    }
+
+    #[test]
+    fn foreign_object_fallback() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50">
+            <switch>
+                <foreignObject x="10" y="5" width="40" height="20" opacity="0.5">
+                    <div xmlns="http://www.w3.org/1999/xhtml">Label</div>
+                </foreignObject>
+                <text>Fallback</text>
+            </switch>
+        </svg>"#;
+
+        let renderer = ForeignObjectRenderer::new(|object| {
+            assert!(object.content.starts_with("<div"));
+            assert_eq!((object.width, object.height), (40.0, 20.0));
+            Some(
+                br#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="2"/>"#
+                    .to_vec(),
+            )
+        });
+
+        let rasterized = foreign::rasterize(src, &renderer).unwrap();
+        assert!(rasterized.contains(r#"x="10" y="5" width="40" height="20""#));
+        assert!(
+            rasterized.contains(r#"opacity="0.5" xlink:href="data:text/plain;base64,"#)
+        );
+        assert!(!rasterized.contains("foreignObject"));
+
+        let options = Options {
+            foreign_objects: Some(renderer),
+            ..Options::default()
+        };
+
+        let (tree, _) = parse(src, usvg::Options::default().to_ref(), &options).unwrap();
+        assert!(tree
+            .root()
+            .descendants()
+            .any(|node| matches!(*node.borrow(), NodeKind::Image(_))));
+    }
+
+    #[test]
+    fn gradient_stop_minimization() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <linearGradient id="lg">
+                <stop offset="0.1" stop-color="red"/>
+                <stop offset="0.2" stop-color="red"/>
+                <stop offset="0.4" stop-color="red"/>
+                <stop offset="0.5" stop-color="blue"/>
+                <stop offset="0.5" stop-color="blue"/>
+                <stop offset="1" stop-color="lime"/>
+            </linearGradient>
+            <rect width="100" height="100" fill="url(#lg)"/>
+        </svg>"#;
+
+        let options = Options { compress: false, ..Options::default() };
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert_eq!(pdf.matches("/FunctionType 2").count(), 3);
+        assert!(pdf.contains("/Bounds [0.4 0.5]"));
+
+        let mut stops = vec![(0.0, vec![1.0]), (0.5, vec![1.0]), (1.0, vec![1.0])];
+        minimize_stops(&mut stops);
+        assert_eq!(stops, [(0.0, vec![1.0])]);
+
+        // Each removal makes the stop before the removed one redundant.
+        let mut stops = vec![
+            (0.0, vec![0.0]),
+            (0.5, vec![0.0]),
+            (0.5, vec![1.0]),
+            (0.5, vec![0.0]),
+            (0.5, vec![1.0]),
+            (0.5, vec![0.0]),
+            (1.0, vec![1.0]),
+        ];
+        minimize_stops(&mut stops);
+        assert_eq!(stops, [
+            (0.0, vec![0.0]),
+            (0.5, vec![0.0]),
+            (1.0, vec![1.0])
+        ]);
+    }
+
+    #[test]
+    fn direct_gradient_shading() {
+        let svg = |shape: &str| {
+            format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+                    <linearGradient id="lg">
+                        <stop stop-color="red"/>
+                        <stop offset="1" stop-color="blue"/>
+                    </linearGradient>
+                    {}
+                </svg>"#,
+                shape
+            )
+        };
+
+        let convert = |shape| {
+            let options = Options { compress: false, ..Options::default() };
+            let pdf = convert_str(&svg(shape), options).unwrap();
+            String::from_utf8_lossy(&pdf).into_owned()
+        };
+
+        let pdf = convert(r#"<rect x="10" width="50" height="20" fill="url(#lg)"/>"#);
+        assert!(pdf.contains("10 80 50 20 re\nW\nn\n/sh0 sh"));
+        assert!(!pdf.contains("/PatternType"));
+
+        let pdf = convert(r#"<circle cx="50" cy="50" r="20" fill="url(#lg)"/>"#);
+        assert!(pdf.contains("/PatternType 2"));
+
+        let pdf =
+            convert(r#"<rect width="50" height="20" fill="url(#lg)" stroke="black"/>"#);
+        assert!(pdf.contains("/PatternType 2"));
+    }
+
+    #[test]
+    fn gradient_spread_methods() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <linearGradient id="lg" x1="0.4" x2="0.6" spreadMethod="reflect">
+                <stop stop-color="red"/>
+                <stop offset="1" stop-color="blue" stop-opacity="0.5"/>
+            </linearGradient>
+            <radialGradient id="rg" r="0.1" spreadMethod="repeat">
+                <stop stop-color="red"/>
+                <stop offset="1" stop-color="blue"/>
+            </radialGradient>
+            <rect width="100" height="50" fill="url(#lg)"/>
+            <rect y="50" width="100" height="50" fill="url(#rg)"/>
+        </svg>"#;
+
+        let options = Options { compress: false, ..Options::default() };
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+
+        // The vector from 40 to 60 is mirrored twice to the left and the
+        // right, for both the colors and the soft mask.
+        assert_eq!(pdf.matches("/Coords [0 100 100 100]").count(), 2);
+        assert_eq!(pdf.matches("/Encode [0 1 1 0 0 1 1 0 0 1]").count(), 2);
+
+        // Ten point wide rings reach the far corners.
+        assert!(pdf.contains("/Coords [50 25 0 50 25 60]"));
+        assert!(pdf.contains("/Encode [0 1 0 1 0 1 0 1 0 1 0 1]"));
+
+        let opt = usvg::Options::default();
+        let tree = Tree::from_str(src, &opt.to_ref()).unwrap();
+        let report = analyze(&tree);
+        assert_eq!(report.count(Feature::SpreadMethod), 2);
+        assert!(report.is_fully_supported());
+    }
+
+    #[test]
+    fn feature_report() {
+        let src = r##"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <defs>
+                <clipPath id="clip" transform="scale(2)">
+                    <rect width="40" height="40"/>
+                </clipPath>
+                <pattern id="pat" width="10" height="10" patternUnits="userSpaceOnUse">
+                    <circle cx="5" cy="5" r="4"/>
+                    <path d="M 0 0 L 10 10" stroke="black"/>
+                </pattern>
+                <linearGradient id="lg">
+                    <stop stop-color="red"/>
+                    <stop offset="1" stop-color="blue"/>
+                </linearGradient>
+                <filter id="blur"><feGaussianBlur stdDeviation="2"/></filter>
+                <rect id="unused" width="10" height="10"/>
+            </defs>
+            <g opacity="0.5" clip-path="url(#clip)">
+                <rect width="50" height="50" fill="url(#pat)"/>
+                <rect x="50" width="50" height="50" fill="url(#lg)"/>
+            </g>
+            <rect y="50" width="50" height="50" filter="url(#blur)"/>
+            <rect x="50" y="50" width="50" height="50" filter="url(#missing)"/>
+        </svg>"##;
+
+        let opt = usvg::Options::default();
+        let tree = Tree::from_str(src, &opt.to_ref()).unwrap();
+        let report = analyze(&tree);
+
+        // Only the drawn rectangles count, not the contents of definitions.
+        // Without the `filter` feature, usvg drops filter attributes, so the
+        // element with a broken filter reference is drawn like the others.
+        let filtered = usize::from(cfg!(feature = "filter"));
+        assert_eq!(report.count(Feature::Path), 4 - filtered);
+        assert_eq!(report.count(Feature::Filter), filtered);
+        assert_eq!(report.count(Feature::GroupOpacity), 1);
+        assert_eq!(report.count(Feature::ClipPath), 1);
+        assert_eq!(report.count(Feature::ClipPathTransform), 1);
+        assert_eq!(report.count(Feature::Pattern), 1);
+        assert_eq!(report.count(Feature::LinearGradient), 1);
+        assert_eq!(report.count(Feature::RadialGradient), 0);
+        assert!(!report.is_fully_supported());
+    }
+
+    #[test]
+    fn provenance_metadata() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+        let options = Options {
+            dpi: 96.0,
+            provenance: Some(Provenance {
+                source: Some("a<b>.svg".into()),
+                source_hash: None,
+            }),
+            ..Options::default()
+        };
+
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/Metadata 4 0 R"));
+        assert!(pdf.contains("/Subtype /XML"));
+        assert!(pdf.contains(&format!(
+            "<svg2pdf:Version>{}</svg2pdf:Version>",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(pdf.contains("<svg2pdf:Options>Options { viewport: None,"));
+        assert!(pdf.contains("dpi: 96.0"));
+        assert!(pdf.contains("<svg2pdf:Source>a&lt;b>.svg</svg2pdf:Source>"));
+        assert!(!pdf.contains("SourceHash"));
+    }
+
+    #[test]
+    fn library_names() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <rect width="10" height="10"/>
+        </svg>"#;
+
+        let tree = Tree::from_str(src, &usvg::Options::default().to_ref()).unwrap();
+        let item = LibraryItem { name: "square".into(), tree: &tree };
+        let pdf = convert_library(&[item.clone(), item], Options::default());
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/Kids []\n  /Count 0"));
+        assert!(pdf.contains("/square 3 0 R"));
+        assert!(pdf.contains("/square#2D2 4 0 R"));
+    }
+
+    #[test]
+    fn outline_round_strokes() {
+        // With round joins and caps, a stroke covers exactly the points within
+        // half its width of the path.
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <path d="M 10 10 L 80 20 L 20 50 C 60 60 90 90 50 90" stroke-width="8"
+                  stroke-linejoin="round" stroke-linecap="round" stroke="black"/>
+            <path d="M 60 40 h 30 v 20 h -30 z" stroke-width="6" stroke-linejoin="round"
+                  stroke="black" fill="none"/>
+        </svg>"#;
+
+        let tree = Tree::from_str(src, &usvg::Options::default().to_ref()).unwrap();
+        for node in tree.root().descendants() {
+            let path = match *node.borrow() {
+                NodeKind::Path(ref path) => path.clone(),
+                _ => continue,
+            };
+
+            let stroke = path.stroke.as_ref().unwrap();
+            let hw = stroke.width.value() / 2.0;
+            let outline = outline::outline(&path.data, stroke, 1.0);
+            let exact = outline::outline(
+                &path.data,
+                &usvg::Stroke {
+                    width: usvg::StrokeWidth::new(1e-6),
+                    ..stroke.clone()
+                },
+                100.0,
+            );
+
+            for x in 0 .. 100 {
+                for y in 0 .. 100 {
+                    let p = (x as f64 + 0.5, y as f64 + 0.5);
+                    let distance = distance_to_outline(&exact, p);
+                    if (distance - hw).abs() < 0.1 {
+                        continue;
+                    }
+
+                    assert_eq!(winding(&outline, p) != 0, distance < hw, "{:?}", p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flatten_clip_paths() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <clipPath id="square"><rect x="10" y="10" width="50" height="50"/></clipPath>
+            <clipPath id="star"><path d="M 50 0 L 80 100 L 0 40 H 100 L 20 100 Z"/></clipPath>
+            <g clip-path="url(#square)"><rect width="100" height="100"/></g>
+            <g clip-path="url(#star)"><rect width="100" height="100"/></g>
+        </svg>"#;
+
+        let options = Options {
+            compress: false,
+            flatten_clip_paths: true,
+            ..Options::default()
+        };
+
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("60 90 m\n60 40 l\n10 40 l\n10 90 l\nh\nf"));
+
+        // The star is not convex, so it is still applied as a clipping path.
+        assert_eq!(pdf.matches("\nW\n").count(), 1);
+    }
+
+    #[test]
+    fn patterns_in_transformed_groups() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <pattern id="p" width="0.5" height="0.5" patternContentUnits="userSpaceOnUse">
+                <rect width="5" height="5" fill="red"/>
+            </pattern>
+            <pattern id="q" width="10" height="10" patternUnits="userSpaceOnUse"
+                    patternTransform="translate(2 3)">
+                <rect width="5" height="5" fill="blue"/>
+            </pattern>
+            <g transform="scale(2 1)">
+                <rect x="10" y="10" width="20" height="40" fill="url(#p)" stroke="black"/>
+            </g>
+            <g transform="translate(0 50) scale(1 3)">
+                <rect width="80" height="10" fill="none" stroke="url(#q)"/>
+            </g>
+        </svg>"#;
+
+        let pdf = convert_str(src, Options { compress: false, ..Options::default() });
+        let pdf = String::from_utf8_lossy(&pdf.unwrap()).into_owned();
+
+        // The cell of the first pattern is half of the untransformed rectangle
+        // and stretched along with it.
+        assert!(pdf.contains("/BBox [10 70 20 90]\n  /XStep 10\n  /YStep 20"));
+        assert!(pdf.contains("/Matrix [2 0 0 1 0 0]"));
+
+        // The second pattern is moved in the scaled coordinate system.
+        assert!(pdf.contains("/BBox [0 90 10 100]"));
+        assert!(pdf.contains("/Matrix [1 0 0 3 2 -259]"));
+    }
+
+    #[test]
+    #[cfg(feature = "compress")]
+    fn uncompressed_patterns() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <pattern id="p" width="10" height="10" patternUnits="userSpaceOnUse">
+                <rect width="5" height="5" fill="blue"/>
+            </pattern>
+            <rect width="80" height="80" fill="url(#p)"/>
+            <rect x="80" width="20" height="20" fill="green"/>
+        </svg>"#;
+
+        let options = Options {
+            compress_patterns: false,
+            ..Options::default()
+        };
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+
+        // Only the pattern can be read, the page content is still compressed.
+        assert!(pdf.contains("/PatternType 1"));
+        assert!(pdf.contains("0 0 1 scn"));
+        assert!(!pdf.contains("0 0.5019608 0 scn"));
+        assert_eq!(pdf.matches("/FlateDecode").count(), 1);
+    }
+
+    #[test]
+    fn object_streams() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <linearGradient id="g">
+                <stop stop-color="red"/>
+                <stop offset="1" stop-color="blue"/>
+            </linearGradient>
+            <rect width="80" height="80" fill="url(#g)" opacity="0.5"/>
+        </svg>"#;
+
+        let convert = |object_streams, compress| {
+            let options = Options {
+                object_streams,
+                compress,
+                ..Options::default()
+            };
+            convert_str(src, options).unwrap()
+        };
+
+        #[cfg(feature = "compress")]
+        assert!(convert(true, true).len() < convert(false, true).len());
+
+        // The content stream stays on its own, the five other objects are
+        // packed, and the cross-reference stream lists the objects at their
+        // offsets.
+        let pdf = convert(true, false);
+        let find =
+            |needle: &[u8]| pdf.windows(needle.len()).rposition(|w| w == needle).unwrap();
+        assert!(!String::from_utf8_lossy(&pdf).contains("\nxref\n"));
+        assert!(
+            pdf[find(b"7 0 obj") ..].starts_with(b"7 0 obj\n<<\n  /Type /ObjStm\n  /N 5")
+        );
+
+        let start = find(b"startxref\n") + 10;
+        let offset: usize = std::str::from_utf8(&pdf[start .. pdf.len() - 6])
+            .unwrap()
+            .parse()
+            .unwrap();
+        let xref = &pdf[offset ..];
+        assert!(xref.starts_with(b"8 0 obj\n<<\n  /Type /XRef\n  /Size 9\n  /W [1 2 2]"));
+
+        let data = &xref[xref.windows(7).position(|w| w == b"stream\n").unwrap() + 7 ..];
+        let mut packed = 0;
+        for (id, entry) in data[.. 9 * 5].chunks(5).enumerate() {
+            let field = u16::from_be_bytes([entry[1], entry[2]]) as usize;
+            match entry[0] {
+                0 => assert_eq!(id, 0),
+                1 => {
+                    assert!(pdf[field ..].starts_with(format!("{id} 0 obj\n").as_bytes()))
+                }
+                _ => {
+                    assert_eq!(field, 7);
+                    packed += 1;
+                }
+            }
+        }
+        assert_eq!(packed, 5);
+    }
+
+    #[test]
+    fn annotated_issues() {
+        let src = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+                <filter id="f"><feGaussianBlur stdDeviation="2"/></filter>
+                <rect x="10" y="10" width="20" height="20" filter="url(#f)"/>
+                <image x="50" y="50" width="10" height="10" href="data:image/png;base64,{}"/>
+            </svg>"#,
+            base64::encode(b"\x89PNG\r\n\x1a\nbroken"),
+        );
+
+        let convert = |annotate_issues| {
+            let options = Options {
+                annotate_issues,
+                compress: false,
+                ..Options::default()
+            };
+            String::from_utf8_lossy(&convert_str(&src, options).unwrap()).into_owned()
+        };
+
+        assert!(!convert(false).contains("/Annots"));
+
+        let pdf = convert(true);
+        #[cfg(feature = "filter")]
+        assert!(pdf.contains(
+            "/Rect [10 70 30 90]\n    /Contents (Drew the element without its filter"
+        ));
+        #[cfg(not(feature = "filter"))]
+        assert!(!pdf.contains("filter"));
+        let filtered = usize::from(cfg!(feature = "filter"));
+        assert_eq!(pdf.matches("/Subtype /Text").count(), 1 + filtered);
+        #[cfg(feature = "png")]
+        assert!(pdf.contains("/Rect [50 40 60 50]\n    /Contents (Skipped PNG image that could not be decoded.)"));
+        #[cfg(not(feature = "png"))]
+        assert!(pdf.contains("because the `png` feature is disabled"));
+    }
+
+    #[test]
+    fn rounded_page_size() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100.4" height="50.2">
+            <rect width="100.4" height="50.2"/>
+        </svg>"#;
+
+        let convert = |page_rounding, dpi| {
+            let options = Options {
+                page_rounding,
+                dpi,
+                compress: false,
+                ..Options::default()
+            };
+            String::from_utf8_lossy(&convert_str(src, options).unwrap()).into_owned()
+        };
+
+        assert!(convert(None, 72.0).contains("/MediaBox [0 0 100.4 50.2]"));
+
+        // The content is stretched to fill the rounded page.
+        let pdf = convert(Some(PageRounding::Points), 72.0);
+        assert!(pdf.contains("/MediaBox [0 0 101 51]"));
+        assert!(pdf.contains("101 51 l"));
+
+        // At 96 dpi, 101 by 51 pixels measure 75.75 by 38.25 points.
+        let pdf = convert(Some(PageRounding::Pixels), 96.0);
+        assert!(pdf.contains("/MediaBox [0 0 75.75 38.25]"));
+    }
+
+    #[test]
+    fn switch_preferences() {
+        let src = r##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <switch>
+                <rect width="10" height="10" fill="#0000ff" requiredExtensions="urn:x-blue"/>
+                <rect width="10" height="10" fill="#ff0000" systemLanguage="en"/>
+                <rect width="10" height="10" fill="#00ff00" systemLanguage="de-CH, fr"/>
+                <rect width="10" height="10" fill="#000000"/>
+            </switch>
+        </svg>"##;
+
+        let convert = |languages: &[&str], features: &[&str]| {
+            let options = Options {
+                languages: languages.iter().map(ToString::to_string).collect(),
+                supported_features: features.iter().map(ToString::to_string).collect(),
+                compress: false,
+                ..Options::default()
+            };
+            String::from_utf8_lossy(&convert_str(src, options).unwrap()).into_owned()
+        };
+
+        // usvg picks the first child in any of its languages.
+        assert!(convert(&[], &[]).contains("1 0 0 sc"));
+        assert!(convert(&["de", "en"], &[]).contains("0 1 0 sc"));
+        assert!(convert(&["it"], &[]).contains("0 0 0 sc"));
+        assert!(convert(&["it"], &["urn:x-blue"]).contains("0 0 1 sc"));
+        assert_eq!(
+            switch::resolve(src, &["fr".into()], &[])
+                .unwrap()
+                .matches("<rect")
+                .count(),
+            1,
+        );
+    }
+
+    #[test]
+    fn transformed_dashes() {
+        let convert = |transform: &str, dpi| {
+            let src = format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+                    <path d="M0 10 H40 M10 0 V40" transform="{}" stroke="black"
+                        stroke-dasharray="4 2" stroke-dashoffset="1"/>
+                </svg>"#,
+                transform
+            );
+
+            let options = Options {
+                dpi,
+                compress: false,
+                ..Options::default()
+            };
+            String::from_utf8_lossy(&convert_str(&src, options).unwrap()).into_owned()
+        };
+
+        assert!(convert("scale(2)", 72.0).contains("[8 4] 2 d"));
+        assert!(convert("rotate(30) scale(0.5)", 144.0).contains("[1 0.5] 0.25 d"));
+
+        // Under a non-uniform scale, the dashes are split off beforehand.
+        let pdf = convert("scale(2 1)", 72.0);
+        assert!(!pdf.contains(" d\n"));
+        assert!(pdf.contains("0 90 m\n6 90 l\n10 90 m\n18 90 l"));
+        assert!(pdf.contains("20 100 m\n20 97 l\n20 95 m\n20 91 l"));
+    }
+
+    #[test]
+    fn distributed_group_opacity() {
+        let svg = |x: u32| {
+            format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+                    <g opacity="0.5">
+                        <rect width="40" height="40" fill="red" fill-opacity="0.5"/>
+                        <rect x="{}" width="40" height="40" fill="blue"/>
+                    </g>
+                </svg>"#,
+                x
+            )
+        };
+
+        let convert = |x, distribute_opacity| {
+            let options = Options {
+                compress: false,
+                distribute_opacity,
+                ..Options::default()
+            };
+            let pdf = convert_str(&svg(x), options).unwrap();
+            String::from_utf8_lossy(&pdf).into_owned()
+        };
+
+        let pdf = convert(50, false);
+        assert!(!pdf.contains("/Subtype /Form"));
+        assert!(pdf.contains("/ca 0.25"));
+        assert!(pdf.contains("/ca 0.5"));
+
+        let pdf = convert(20, false);
+        assert_eq!(pdf.matches("/Subtype /Form").count(), 1);
+        assert!(!pdf.contains("/ca 0.25"));
+
+        let pdf = convert(20, true);
+        assert!(!pdf.contains("/Subtype /Form"));
+        assert!(pdf.contains("/ca 0.25"));
+    }
+
+    #[test]
+    fn nested_clip_depth() {
+        let convert = |attrs: &str, distribute_opacity| {
+            let mut src = String::from(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+                    <clipPath id="c"><rect width="50" height="50"/></clipPath>"#,
+            );
+            src += &format!(r#"<g clip-path="url(#c)" {}>"#, attrs).repeat(40);
+            src += r#"<rect width="100" height="100"/>"#;
+            src += &"</g>".repeat(40);
+            src += "</svg>";
+
+            let options = Options {
+                compress: false,
+                distribute_opacity,
+                ..Options::default()
+            };
+            let pdf = convert_str(&src, options).unwrap();
+            max_state_depth(&String::from_utf8_lossy(&pdf))
+        };
+
+        for depth in [convert("", false), convert(r#"opacity="0.99""#, true)] {
+            assert!(depth > 16 && depth <= 28, "depth {}", depth);
+        }
+    }
+
+    /// The maximum nesting depth of `q` operators in the content streams of a
+    /// PDF.
+    fn max_state_depth(pdf: &str) -> usize {
+        let mut max = 0;
+        for stream in pdf.split("stream\n").skip(1) {
+            let mut depth = 0;
+            for line in stream.split("endstream").next().unwrap().lines() {
+                match line {
+                    "q" => depth += 1,
+                    "Q" => depth -= 1,
+                    _ => {}
+                }
+                max = max.max(depth);
+            }
+        }
+
+        max
+    }
+
+    /// The winding number of a polygonal path around a point.
+    fn winding(data: &usvg::PathData, p: (f64, f64)) -> i32 {
+        let mut winding = 0;
+        for (a, b) in edges(data) {
+            if (a.1 <= p.1) != (b.1 <= p.1) {
+                let cross = (b.0 - a.0) * (p.1 - a.1) - (p.0 - a.0) * (b.1 - a.1);
+                if (b.1 > a.1) == (cross > 0.0) {
+                    winding += if b.1 > a.1 { 1 } else { -1 };
+                }
+            }
+        }
+
+        winding
+    }
+
+    /// The distance of a point to the edges of a polygonal path.
+    fn distance_to_outline(data: &usvg::PathData, p: (f64, f64)) -> f64 {
+        edges(data)
+            .into_iter()
+            .map(|(a, b)| {
+                let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+                let len = dx * dx + dy * dy;
+                let t = if len > 0.0 {
+                    (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+
+                (a.0 + t * dx - p.0).hypot(a.1 + t * dy - p.1)
+            })
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// The edges of the closed polygons of a path.
+    fn edges(data: &usvg::PathData) -> Vec<((f64, f64), (f64, f64))> {
+        let mut edges = vec![];
+        let mut start = (0.0, 0.0);
+        let mut last = (0.0, 0.0);
+        for &segment in &data.0 {
+            match segment {
+                usvg::PathSegment::MoveTo { x, y } => {
+                    start = (x, y);
+                    last = start;
+                }
+                usvg::PathSegment::LineTo { x, y } => {
+                    edges.push((last, (x, y)));
+                    last = (x, y);
+                }
+                usvg::PathSegment::ClosePath => edges.push((last, start)),
+                usvg::PathSegment::CurveTo { .. } => unreachable!(),
+            }
+        }
+
+        edges
+    }
+
+    #[test]
+    fn sized_conversion() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50"/>"#;
+        let tree = Tree::from_str(src, &usvg::Options::default().to_ref()).unwrap();
+
+        let options = Options { dpi: 144.0, ..Options::default() };
+        assert_eq!(convert_tree_sized(&tree, options).1, (50.0, 25.0));
+
+        let options = Options { bleed: Some(9.0), ..Options::default() };
+        assert_eq!(convert_tree_sized(&tree, options).1, (154.0, 104.0));
+
+        let mut writer = PdfWriter::new();
+        let options = Options {
+            viewport: Some((200.0, 50.0)),
+            ..Options::default()
+        };
+        let (next_id, size) =
+            convert_tree_into_sized(&tree, options, &mut writer, Ref::new(1));
+        assert!(next_id.get() > 1);
+        assert_eq!(size, (200.0, 50.0));
+    }
+
+    #[test]
+    fn style_sheet() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <style>rect { fill: red }</style>
+            <rect width="5" height="5" fill="green"/>
+        </svg>"#;
+
+        let options = Options {
+            compress: false,
+            style_sheet: Some("rect { fill: blue }".into()),
+            ..Options::default()
+        };
+
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("0 0 1 scn"));
+    }
+
+    #[test]
+    fn data_attributes() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <g data-series="revenue" data-other="x">
+                <rect width="5" height="5" data-point="q1"/>
+            </g>
+        </svg>"#;
+
+        let options = Options {
+            compress: false,
+            data_attributes: vec!["data-series".into(), "data-point".into()],
+            ..Options::default()
+        };
+
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains(
+            "/Data <<\n  /data#2Dpoint (q1)\n  /data#2Dseries (revenue)\n>> BDC"
+        ));
+        assert!(!pdf.contains("data#2Dother"));
+    }
+
+    #[test]
+    fn budget_warnings() {
+        use std::sync::{Arc, Mutex};
+
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <rect width="10" height="10"/>
+        </svg>"#;
+
+        let warnings = Arc::new(Mutex::new(vec![]));
+        let sink = warnings.clone();
+        let options = Options {
+            budget: Some(Budget {
+                objects: Some(2),
+                xobjects: Some(1),
+                pages: Some(0),
+                handler: Some(BudgetHandler::new(move |warning| {
+                    sink.lock().unwrap().push(*warning)
+                })),
+                ..Budget::default()
+            }),
+            ..Options::default()
+        };
+
+        convert_str(src, options).unwrap();
+        let warnings = warnings.lock().unwrap();
+        let measures: Vec<_> = warnings.iter().map(|w| w.measure).collect();
+        assert_eq!(measures, [Measure::Objects, Measure::Pages]);
+        assert_eq!(warnings[1].value, 1);
+        assert_eq!(warnings[1].limit, 0);
+    }
+
+    #[test]
+    fn xobject_usage() {
+        let opt = usvg::Options {
+            resources_dir: Some("tests".into()),
+            ..usvg::Options::default()
+        };
+
+        for name in [
+            "alpha",
+            "group",
+            "image",
+            "image2",
+            "mask",
+            "radial_gradient2",
+        ] {
+            let svg = fs::read_to_string(format!("tests/{}.svg", name)).unwrap();
+            let tree = Tree::from_str(&svg, &opt.to_ref()).unwrap();
+            let data = SourceData::default();
+            let pages = [(&tree, &data)];
+            let usage = |object_streams| {
+                let options = Options {
+                    compress: false,
+                    object_streams,
+                    ..Options::default()
+                };
+                let pages = Pages::parsed(&pages);
+                write_pages(&pages, &options, &Reductions::default(), None)
+            };
+
+            // Every XObject dictionary is marked with its type, which is only
+            // visible in the file without object streams.
+            let (pdf, plain, _) = usage(false);
+            let written = pdf.windows(14).filter(|w| *w == b"/Type /XObject").count();
+            assert_eq!(plain.xobjects, written, "{}", name);
+            assert_eq!(usage(true).1.xobjects, written, "{}", name);
+        }
+    }
+
+    #[test]
+    fn profiled_conversion() {
+        let svg = fs::read_to_string("tests/example.svg").unwrap();
+        let converter = Converter::new(Options::default());
+        let (pdf, timings) =
+            converter.convert_profiled(&svg, Options::default()).unwrap();
+        assert_eq!(pdf, converter.convert(&svg).unwrap());
+        assert!(timings.parse > std::time::Duration::ZERO);
+        assert!(timings.total() >= timings.parse + timings.render);
+        assert!(timings.to_string().contains("rendering nodes"));
+    }
+
+    #[test]
+    fn balanced_page_tree() {
+        let mut next = 2;
+        let mut alloc = || {
+            next += 1;
+            Ref::new(next)
+        };
+
+        let pages: Vec<Ref> = (0 .. 2000).map(|_| alloc()).collect();
+        let root = Ref::new(2);
+        let (nodes, parents) = page_tree(root, &pages, &mut alloc);
+
+        // 63 nodes above the pages, 2 above those and the root.
+        assert_eq!(nodes.len(), 66);
+        assert!(nodes.iter().all(|node| node.kids.len() <= PAGE_TREE_KIDS));
+        assert_eq!(nodes.last().unwrap().count, 2000);
+
+        for (page, parent) in pages.iter().zip(&parents) {
+            let node = nodes.iter().find(|node| node.id == *parent).unwrap();
+            assert!(node.kids.contains(page));
+        }
+
+        for node in &nodes {
+            let mut depth = 0;
+            let mut parent = node.parent;
+            while let Some(id) = parent {
+                depth += 1;
+                parent = nodes.iter().find(|node| node.id == id).unwrap().parent;
+            }
+            assert!(depth <= 2);
+        }
+
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+        let pdf = convert_str_flipbook(svg, 40, Options::default()).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/Count 40"));
+        assert!(pdf.contains("/Count 32"));
+        assert!(pdf.contains("/Count 8"));
+    }
+
+    #[test]
+    fn flipbook_frame_counts() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <circle cx="50" cy="50" r="10">
+                <animate attributeName="r" from="10" to="50" dur="2s"/>
+            </circle>
+        </svg>"#;
+
+        // Zero frames still yield a page, showing the start of the animation
+        // just like a single frame does.
+        let still = Options {
+            animation_time: Some(0.0),
+            ..Options::default()
+        };
+        let expected = convert_str(svg, still).unwrap();
+        for frames in [0, 1] {
+            let pdf = convert_str_flipbook(svg, frames, Options::default()).unwrap();
+            assert!(String::from_utf8_lossy(&pdf).contains("/Count 1"));
+            assert_eq!(pdf, expected);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "compress")]
+    fn decompression_limits() {
+        let svg = fs::read_to_string("tests/image.svg").unwrap();
+        let pdf = convert_str(&svg, Options::default()).unwrap();
+        assert!(String::from_utf8_lossy(&pdf).contains("/Subtype /Image"));
+
+        // The image has 5x5 pixels.
+        let options = Options {
+            max_image_pixels: Some(24),
+            ..Options::default()
+        };
+        let pdf = convert_str(&svg, options).unwrap();
+        assert!(!String::from_utf8_lossy(&pdf).contains("/Subtype /Image"));
+
+        // A gzip member with a file name whose content is a megabyte of spaces.
+        let mut gzip = vec![0x1f, 0x8b, 8, 1 << 3, 0, 0, 0, 0, 0, 255];
+        gzip.extend(b"bomb.svg\0");
+        gzip.extend(miniz_oxide::deflate::compress_to_vec(&[b' '; 1 << 20], 6));
+        let image = format!(
+            r#"<image width="5" height="5" href="data:image/svg+xml;base64,{}"/>"#,
+            base64::encode(&gzip),
+        );
+
+        let src = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="5" height="5">{}</svg>"#,
+            image,
+        );
+
+        let guarded = limits::guard_svg_images(&src, 1 << 16).unwrap();
+        assert!(!guarded.contains("<image"));
+        assert!(limits::guard_svg_images(&src, 1 << 20).is_none());
+
+        // A small compressed SVG image that contains the bomb.
+        let mut outer = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+        outer.extend(miniz_oxide::deflate::compress_to_vec(src.as_bytes(), 6));
+        assert!(outer.len() < 1 << 16);
+        let src = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="5" height="5">
+                <image width="5" height="5" href="data:image/svg+xml;base64,{}"/>
+                <rect width="5" height="5"/>
+            </svg>"#,
+            base64::encode(&outer),
+        );
+
+        let guarded = limits::guard_svg_images(&src, 1 << 16).unwrap();
+        assert!(!guarded.contains("<image"));
+        assert!(guarded.contains("<rect"));
+        assert!(limits::guard_svg_images(&src, 1 << 20).is_none());
+
+        // An uncompressed SVG image that contains the bomb is still inspected.
+        let plain = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="5" height="5">
+                <image width="5" height="5" href='data:image/svg+xml;base64,{}'/>
+            </svg>"#,
+            base64::encode(&src),
+        );
+        assert!(limits::may_contain_svg_images(&plain));
+        assert!(limits::guard_svg_images(&plain, 1 << 16).is_some());
+    }
+
+    #[test]
+    fn raster_data_urls() {
+        // Sources whose data URLs only hold raster images aren't parsed.
+        for magic in [&b"\x89PNG\r\n"[..], b"\xff\xd8\xff\xe0", b"GIF89a"] {
+            let src = format!(
+                r#"<svg><image href="data:image/png;base64, {}"/></svg>"#,
+                base64::encode(magic),
+            );
+            assert!(!limits::may_contain_svg_images(&src));
+        }
+
+        let svg = base64::encode("<svg/>");
+        let src = format!(r#"<image href="data:image/svg+xml;base64,{}"/>"#, svg);
+        assert!(limits::may_contain_svg_images(&src));
+        assert!(!limits::may_contain_svg_images(
+            r#"<image href="data:,<svg/>"/>"#
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn bilevel_images() {
+        let encode = |image: image::DynamicImage| {
+            let mut png = vec![];
+            image
+                .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+                .unwrap();
+            base64::encode(&png)
+        };
+
+        // Black-and-white line art in RGB and a gray shape with a binary
+        // alpha channel.
+        let art = image::RgbImage::from_fn(10, 4, |x, y| {
+            image::Rgb(if x == y { [0; 3] } else { [255; 3] })
+        });
+        let shape = image::RgbaImage::from_fn(10, 4, |x, _| {
+            image::Rgba([128, 128, 128, if x < 5 { 255 } else { 0 }])
+        });
+
+        let src = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+                <image width="10" height="4" href="data:image/png;base64,{}"/>
+                <image y="10" width="10" height="4" href="data:image/png;base64,{}"/>
+            </svg>"#,
+            encode(image::DynamicImage::ImageRgb8(art)),
+            encode(image::DynamicImage::ImageRgba8(shape)),
+        );
+
+        let options = Options { compress: false, ..Options::default() };
+        let pdf = convert_str(&src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert_eq!(pdf.matches("/BitsPerComponent 1").count(), 2);
+        assert_eq!(pdf.matches("/DeviceGray").count(), 2);
+        assert_eq!(pdf.matches("/DeviceRGB").count(), 1);
+
+        // Each row starts at a new byte. Sixteen-bit samples are only
+        // bilevel if both of their bytes are.
+        let rows = [0, 0, 0, 255, 255, 255, 255, 255, 255, 0, 0, 0];
+        assert_eq!(render::pack_bilevel(&rows, 3, 2), Some(vec![0x40, 0x80]));
+        assert_eq!(render::pack_bilevel(&[0, 255, 255, 255], 2, 2), None);
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn image_policy() {
+        let image = image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([x as u8 * 4, y as u8 * 4, (x ^ y) as u8])
+        });
+
+        let mut png = vec![];
+        image::DynamicImage::ImageRgb8(image)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+
+        // The image is displayed with 16 and 64 points, i.e. 288 and 72 dpi.
+        let src = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+                <image width="16" height="16" href="data:image/png;base64,{0}"/>
+                <image x="20" width="64" height="64" href="data:image/png;base64,{0}"/>
+            </svg>"#,
+            base64::encode(&png),
+        );
+
+        let convert = |image_policy| {
+            let options = Options { image_policy, ..Options::default() };
+            let pdf = convert_str(&src, options).unwrap();
+            String::from_utf8_lossy(&pdf).into_owned()
+        };
+
+        let pdf = convert(None);
+        assert_eq!(pdf.matches("/Width 64").count(), 1);
+
+        let pdf = convert(Some(ImagePolicy {
+            max_dpi: Some(144.0),
+            jpeg_quality: None,
+        }));
+        assert!(pdf.contains("/Width 32"));
+        assert!(pdf.contains("/Width 64"));
+        assert!(!pdf.contains("/DCTDecode"));
+
+        #[cfg(feature = "jpeg")]
+        {
+            let policy = ImagePolicy { max_dpi: None, jpeg_quality: Some(80) };
+            let pdf = convert(Some(policy));
+            assert_eq!(pdf.matches("/Width 64").count(), 1);
+            assert!(pdf.contains("/DCTDecode"));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn shared_page_images() {
+        let opt = usvg::Options::default();
+        let svg = fs::read_to_string("tests/image.svg").unwrap();
+        let tree = Tree::from_str(&svg, &opt.to_ref()).unwrap();
+        let trees = [tree.clone(), tree];
+
+        let pdf = convert_trees(&trees, Options::default());
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/Count 2"));
+        // The image and its soft mask.
+        assert_eq!(pdf.matches("/Subtype /Image").count(), 2);
+
+        // Raster images are compared by their data, not by their source.
+        let key = |data: Vec<u8>| ImageKey::Raster(std::sync::Arc::new(data), None);
+        assert!(key(vec![1, 2, 3]) == key(vec![1, 2, 3]));
+        assert!(key(vec![1, 2, 3]) != key(vec![1, 2, 4]));
+    }
+
+    #[test]
+    #[cfg(feature = "jpeg")]
+    fn exif_orientations() {
+        // A JPEG header with an APP1 segment whose first IFD has a single
+        // entry, followed by the start of the image data.
+        let jpeg = |order: &[u8; 2], tag: u16, value: u16, ifd: u32| {
+            let le = order == b"II";
+            let u16 = |v: u16| if le { v.to_le_bytes() } else { v.to_be_bytes() };
+            let u32 = |v: u32| if le { v.to_le_bytes() } else { v.to_be_bytes() };
+
+            let mut app1 = b"Exif\0\0".to_vec();
+            app1.extend(order);
+            app1.extend(u16(42));
+            app1.extend(u32(ifd));
+            app1.extend(u16(1));
+            app1.extend(u16(tag));
+            app1.extend(u16(3));
+            app1.extend(u32(1));
+            app1.extend(u16(value));
+            app1.extend([0, 0, 0, 0, 0, 0]);
+
+            let mut data = vec![0xFF, 0xD8, 0xFF, 0xE1];
+            data.extend((app1.len() as u16 + 2).to_be_bytes());
+            data.extend(app1);
+            data.extend([0xFF, 0xDA, 0, 2]);
+            data
+        };
+
+        // Where a point of the stored image, in coordinates from its top left
+        // corner, ends up in the upright image.
+        let upright = |orientation, (u, v): (f32, f32)| match orientation {
+            2 => (1.0 - u, v),
+            3 => (1.0 - u, 1.0 - v),
+            4 => (u, 1.0 - v),
+            5 => (v, u),
+            6 => (1.0 - v, u),
+            7 => (1.0 - v, 1.0 - u),
+            8 => (v, 1.0 - u),
+            _ => (u, v),
+        };
+
+        for order in [b"II", b"MM"] {
+            for orientation in 1 ..= 8 {
+                let data = jpeg(order, 0x0112, orientation, 8);
+                assert_eq!(exif_orientation(&data), orientation);
+
+                // The matrix works in image space, where the y-axis points up.
+                let [a, b, c, d, e, f] = orientation_matrix(orientation);
+                for (u, v) in [(0.0, 0.0), (1.0, 0.0), (0.25, 0.75)] {
+                    let (x, y) = (u, 1.0 - v);
+                    let (uu, vv) = upright(orientation, (u, v));
+                    assert_eq!((a * x + c * y + e, b * x + d * y + f), (uu, 1.0 - vv));
+                }
+            }
+
+            // Invalid values, a missing tag and a bad IFD offset.
+            assert_eq!(exif_orientation(&jpeg(order, 0x0112, 9, 8)), 1);
+            assert_eq!(exif_orientation(&jpeg(order, 0x0110, 6, 8)), 1);
+            assert_eq!(exif_orientation(&jpeg(order, 0x0112, 6, 1000)), 1);
+            assert_eq!(exif_orientation(&jpeg(order, 0x0112, 6, u32::MAX)), 1);
+
+            // Truncated data, an APP1 segment that ends before the tag and one
+            // whose length is invalid.
+            let data = jpeg(order, 0x0112, 6, 8);
+            for len in 0 .. data.len() - 4 {
+                assert_eq!(exif_orientation(&data[.. len]), 1);
+            }
+
+            let mut data = data;
+            data[4 .. 6].copy_from_slice(&20u16.to_be_bytes());
+            assert_eq!(exif_orientation(&data), 1);
+            data[4 .. 6].copy_from_slice(&1u16.to_be_bytes());
+            assert_eq!(exif_orientation(&data), 1);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "jpeg")]
+    fn original_jpeg_data() {
+        use image::codecs::jpeg::JpegEncoder;
+        use image::ColorType;
+
+        let pixels: Vec<u8> = (0 .. 32 * 32 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        let mut jpeg = vec![];
+        JpegEncoder::new(&mut jpeg)
+            .encode(&pixels, 32, 32, ColorType::Rgb8)
+            .unwrap();
+
+        let src = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg"
+                xmlns:xlink="http://www.w3.org/1999/xlink" width="10" height="10">
+                <image width="10" height="10" xlink:href="data:image/jpeg;base64,{}"/>
+            </svg>"#,
+            base64::encode(&jpeg),
+        );
+
+        let pdf = convert_str(&src, Options::default()).unwrap();
+        assert!(pdf.windows(jpeg.len()).any(|window| window == jpeg));
+
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/Width 32"));
+        assert!(pdf.contains("/Filter /DCTDecode"));
+
+        // Lossless JPEGs can't be decoded by PDF viewers.
+        let mut lossless = jpeg.clone();
+        let sof = lossless.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+        lossless[sof + 1] = 0xC3;
+        assert!(render::jpeg_header(&jpeg).is_some());
+        assert!(render::jpeg_header(&lossless).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "jpeg")]
+    fn color_managed_images() {
+        use image::codecs::jpeg::JpegEncoder;
+        use image::ColorType;
+
+        // Insert an APP2 segment with an ICC profile of the given color space
+        // after the start of a JPEG image.
+        let with_profile = |jpeg: &[u8], space: &[u8; 4]| {
+            let mut profile = vec![0; 128];
+            profile[16 .. 20].copy_from_slice(space);
+            let mut segment = b"ICC_PROFILE\0\x01\x01".to_vec();
+            segment.extend(&profile);
+
+            let mut out = jpeg[.. 2].to_vec();
+            out.extend([0xFF, 0xE2]);
+            out.extend((segment.len() as u16 + 2).to_be_bytes());
+            out.extend(segment);
+            out.extend(&jpeg[2 ..]);
+            out
+        };
+
+        let mut rgb = vec![];
+        JpegEncoder::new(&mut rgb)
+            .encode(&[255, 0, 0].repeat(64), 8, 8, ColorType::Rgb8)
+            .unwrap();
+
+        // An 8x8 CMYK image from Adobe with a single Huffman code for a zero
+        // DC difference and the end of each block.
+        let mut cmyk = vec![0xFF, 0xD8];
+        cmyk.extend(b"\xFF\xEE\x00\x0EAdobe\x00\x64\x00\x00\x00\x00\x00");
+        cmyk.extend(b"\xFF\xDB\x00\x43\x00");
+        cmyk.extend([1; 64]);
+        cmyk.extend(b"\xFF\xC0\x00\x14\x08\x00\x08\x00\x08\x04");
+        for id in 1 ..= 4 {
+            cmyk.extend([id, 0x11, 0]);
+        }
+        for class in [0x00, 0x10] {
+            cmyk.extend([0xFF, 0xC4, 0x00, 0x14, class, 1]);
+            cmyk.extend([0; 16]);
+        }
+        cmyk.extend(b"\xFF\xDA\x00\x0E\x04\x01\x00\x02\x00\x03\x00\x04\x00\x00\x3F\x00");
+        cmyk.extend([0x00, 0xFF, 0xD9]);
+
+        let images = [
+            with_profile(&rgb, b"RGB "),
+            with_profile(&rgb, b"CMYK"),
+            with_profile(&cmyk, b"CMYK"),
+            cmyk,
+        ];
+
+        let mut src = String::from(
+            r#"<svg xmlns="http://www.w3.org/2000/svg"
+                xmlns:xlink="http://www.w3.org/1999/xlink" width="40" height="10">"#,
+        );
+        for (i, image) in images.iter().enumerate() {
+            src += &format!(
+                r#"<image x="{}" width="10" height="10"
+                    xlink:href="data:image/jpeg;base64,{}"/>"#,
+                i * 10,
+                base64::encode(image),
+            );
+        }
+        src += "</svg>";
+
+        let pdf = convert_str(&src, Options::default()).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert_eq!(pdf.matches("/Subtype /Image").count(), 4);
+        assert_eq!(pdf.matches("/ColorSpace [/ICCBased").count(), 2);
+        assert_eq!(pdf.matches("/N 3").count(), 1);
+        assert_eq!(pdf.matches("/N 4").count(), 1);
+        assert_eq!(pdf.matches("/ColorSpace /DeviceRGB").count(), 1);
+        assert_eq!(pdf.matches("/ColorSpace /DeviceCMYK").count(), 1);
+        assert_eq!(pdf.matches("/Decode [1 0 1 0 1 0 1 0]").count(), 2);
+    }
+
+    #[test]
+    fn shared_forms_and_patterns() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <defs>
+                <pattern id="p" width="4" height="4" patternUnits="userSpaceOnUse">
+                    <rect width="2" height="2" fill="green"/>
+                </pattern>
+            </defs>
+            <g opacity="0.5"><circle cx="20" cy="20" r="10"/></g>
+            <rect y="50" width="20" height="20" fill="url(#p)"/>
+            <rect y="50" width="20" height="20" fill="url(#p)" stroke="red"/>
+        </svg>"#;
+
+        let opt = usvg::Options::default();
+        let tree = Tree::from_str(src, &opt.to_ref()).unwrap();
+        let single = convert_tree(&tree, Options::default());
+        let single = String::from_utf8_lossy(&single);
+        assert_eq!(single.matches("/PatternType 1").count(), 1);
+
+        let trees = [tree.clone(), tree];
+        let pdf = convert_trees(&trees, Options::default());
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert_eq!(pdf.matches("/PatternType 1").count(), 1);
+        assert_eq!(
+            pdf.matches("/Subtype /Form").count(),
+            single.matches("/Subtype /Form").count(),
+        );
+    }
+
+    #[test]
+    fn shared_forms_with_gradients() {
+        let tree = |color: &str| {
+            let src = format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+                    <linearGradient id="g">
+                        <stop offset="0" stop-color="{}"/>
+                        <stop offset="1" stop-color="white"/>
+                    </linearGradient>
+                    <g opacity="0.5">
+                        <rect width="50" height="50" fill="url(#g)"/>
+                        <rect x="40" y="40" width="50" height="50" fill="url(#g)"/>
+                    </g>
+                </svg>"#,
+                color,
+            );
+            Tree::from_str(&src, &usvg::Options::default().to_ref()).unwrap()
+        };
+
+        // Both pages have a gradient with the id `g`, but with different
+        // colors, so they can't share the group.
+        let trees = [tree("red"), tree("blue")];
+        let options = Options { compress: false, ..Options::default() };
+        let pdf = convert_trees(&trees, options);
+        let pdf = String::from_utf8_lossy(&pdf);
+        let functions: HashSet<_> = pdf
+            .split("/Function ")
+            .skip(1)
+            .filter_map(|rest| rest.split(" R").next())
+            .collect();
+        assert_eq!(pdf.matches("/Subtype /Form").count(), 2);
+        assert_eq!(functions.len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "text")]
+    fn converter_fonts() {
+        // The font maps `A` to a square from 100 to 600 units horizontally
+        // and up to 700 units above the baseline, at 1000 units per em.
+        let font = fs::read("tests/square.ttf").unwrap();
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <text x="10" y="90" font-family="Square" font-size="100">A</text>
+        </svg>"#;
+
+        let options = Options { compress: false, ..Options::default() };
+        let has_square = |pdf: Vec<u8>| {
+            String::from_utf8_lossy(&pdf).contains("20 10 m\n70 10 l\n70 80 l\n20 80 l")
+        };
+
+        // Without fonts, usvg drops the text.
+        let converter = Converter::new(options.clone());
+        assert!(!has_square(converter.convert(src).unwrap()));
+
+        let mut converter = Converter::new(options.clone());
+        converter.add_font_data(font.clone());
+        assert_eq!(converter.usvg_options().fontdb.len(), 1);
+        assert!(has_square(converter.convert(src).unwrap()));
+
+        let converter =
+            Converter::new(options.clone()).with_fonts(vec![font.clone(), font]);
+        assert_eq!(converter.usvg_options().fontdb.len(), 2);
+        assert!(has_square(converter.convert(src).unwrap()));
+    }
+
+    #[test]
+    fn rendered_resources() {
+        let src = r##"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20">
+            <linearGradient id="lg">
+                <stop stop-color="red"/>
+                <stop offset="1" stop-color="blue"/>
+            </linearGradient>
+            <rect width="20" height="20" fill="url(#lg)"/>
+            <rect id="second" x="20" width="20" height="20" opacity="0.5"/>
+        </svg>"##;
+
+        let tree = Tree::from_str(src, &usvg::Options::default().to_ref()).unwrap();
+        let options = Options { compress: false, ..Options::default() };
+
+        // The gradient's function is written right away with the first id.
+        let mut writer = PdfWriter::new();
+        let rendered = render_tree(&tree, options.clone(), &mut writer, Ref::new(5));
+        assert_eq!(rendered.bbox(), Rect::new(0.0, 0.0, 40.0, 20.0));
+        assert_eq!(rendered.next_id(), Ref::new(6));
+
+        let content = String::from_utf8_lossy(rendered.content()).into_owned();
+        assert!(content.contains("/sh0 sh"));
+        assert!(content.contains("/gs1 gs"));
+
+        // The names used by the operators resolve in the caller's resources.
+        let mut page = writer.page(Ref::new(3));
+        rendered.write_resources(&mut page.resources());
+        page.finish();
+        let pdf = writer.finish();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("5 0 obj\n<<\n  /FunctionType 3"));
+        assert!(pdf.contains("/Shading <<\n      /sh0 <<"));
+        assert!(pdf.contains("/Function 5 0 R"));
+        assert!(pdf.contains("/ExtGState <<\n      /gs1 <<"));
+        assert!(pdf.contains("/ColorSpace <<\n      /srgb"));
+
+        // A single node is drawn where it is in the full tree.
+        let node = tree.node_by_id("second").unwrap();
+        let mut writer = PdfWriter::new();
+        let rendered = render_node(&tree, &node, options, &mut writer, Ref::new(5));
+        let content = String::from_utf8_lossy(rendered.content());
+        assert!(content.contains("20 20 m\n40 20 l\n40 0 l\n20 0 l"));
+        assert!(!content.contains("/sh0"));
+    }
+
+    #[test]
+    fn raw_xobjects() {
+        let src = r##"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20">
+            <linearGradient id="lg">
+                <stop stop-color="red"/>
+                <stop offset="1" stop-color="blue"/>
+            </linearGradient>
+            <rect width="40" height="20" fill="url(#lg)"/>
+        </svg>"##;
+
+        let tree = Tree::from_str(src, &usvg::Options::default().to_ref()).unwrap();
+        let options = Options {
+            compress: false,
+            dpi: 144.0,
+            ..Options::default()
+        };
+
+        let mut writer = PdfWriter::new();
+        let (next_id, bbox) =
+            convert_tree_into_raw(&tree, options.clone(), &mut writer, Ref::new(5));
+        let raw = String::from_utf8_lossy(&writer.finish()).into_owned();
+
+        let mut writer = PdfWriter::new();
+        let normalized_next = convert_tree_into(&tree, options, &mut writer, Ref::new(5));
+        let normalized = String::from_utf8_lossy(&writer.finish()).into_owned();
+
+        // The bounding box is the size on a page at the given resolution and
+        // only the normalized XObject scales it down to one point.
+        assert_eq!(bbox, Rect::new(0.0, 0.0, 20.0, 10.0));
+        assert!(raw.contains("/Subtype /Form\n  /BBox [0 0 20 10]\n  /Resources"));
+        assert!(normalized.contains("/BBox [0 0 20 10]\n  /Matrix [0.05 0 0 0.1 0 0]"));
 
-    for element in node.children() {
-        if &element == node {
-            continue;
+        // The ids after the XObject are used by its gradient.
+        assert_eq!(next_id, normalized_next);
+        assert!(next_id.get() > 6);
+        for id in 5 .. next_id.get() {
+            assert!(raw.contains(&format!("\n{} 0 obj", id)));
         }
+        assert!(!raw.contains(&format!("\n{} 0 obj", next_id.get())));
+    }
 
-        match *element.borrow() {
-            NodeKind::Defs => continue,
-            NodeKind::Path(ref path) => {
-                path.render(&element, writer, &mut content, ctx);
-            }
-            NodeKind::Group(ref group) => {
-                group.render(&element, writer, &mut content, ctx);
-            }
-            NodeKind::Image(ref image) => {
-                image.render(&element, writer, &mut content, ctx);
-            }
-            _ => {}
-        }
+    #[test]
+    fn unique_debug_names() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+        let tree = Tree::from_str(src, &usvg::Options::default().to_ref()).unwrap();
+        let (c, bbox, _) = get_page_sizings(&tree, &Options::default());
+        let mut ctx = Context::new(&tree, true, bbox, c);
+        ctx.debug_names = Some(HashSet::new());
+
+        let names: Vec<_> = ["grad", "grad_2", "grad", "grad", "grad_1", ""]
+            .into_iter()
+            .map(|id| {
+                let name = ctx.alloc_xobject(id);
+                String::from_utf8(name.as_name().0.to_vec()).unwrap()
+            })
+            .collect();
+
+        assert_eq!(names, [
+            "xo_grad",
+            "xo_grad_2",
+            "xo_grad_3",
+            "xo_grad_4",
+            "xo_grad_1",
+            "xo5"
+        ]);
     }
 
-    let res = content.finish();
+    #[test]
+    #[cfg(feature = "compress")]
+    fn shared_compressed_streams() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+        let tree = Tree::from_str(src, &usvg::Options::default().to_ref()).unwrap();
+        let (c, bbox, _) = get_page_sizings(&tree, &Options::default());
+        let mut ctx = Context::new(&tree, true, bbox, c);
 
-    if ctx.compress { deflate(&res) } else { res }
-}
+        // Only the data of streams that repeat is kept and then shared.
+        let stream = || b"0 0 m 10 10 l S".to_vec();
+        let first = ctx.finish_content(stream());
+        let second = ctx.finish_content(stream());
+        let third = ctx.finish_content(stream());
+        assert_eq!(first, second);
+        assert!(!Rc::ptr_eq(&first, &second));
+        assert!(Rc::ptr_eq(&second, &third));
 
-/// Draw a clipping path into a content stream.
-fn apply_clip_path(path_id: Option<&String>, content: &mut Content, ctx: &mut Context) {
-    if let Some(clip_path) = path_id.and_then(|id| ctx.tree.defs_by_id(id)) {
-        if let NodeKind::ClipPath(ref path) = *clip_path.borrow() {
-            apply_clip_path(path.clip_path.as_ref(), content, ctx);
+        let other = ctx.finish_content(b"0 0 m 5 5 l S".to_vec());
+        assert_ne!(other, third);
+        assert_eq!(
+            ctx.deflated.values().filter(|entry| entry.is_some()).count(),
+            1
+        );
+    }
 
-            for child in clip_path.children() {
-                match *child.borrow() {
-                    NodeKind::Path(ref path) => {
-                        draw_path(&path.data.0, path.transform, content, &ctx.c);
-                        content.clip_nonzero();
-                        content.end_path();
-                    }
-                    NodeKind::ClipPath(_) => {}
-                    _ => unreachable!(),
-                }
-            }
-        } else {
-            unreachable!();
-        }
+    #[test]
+    fn exact_form_keys() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+        let tree = Tree::from_str(src, &usvg::Options::default().to_ref()).unwrap();
+        let (c, bbox, _) = get_page_sizings(&tree, &Options::default());
+        let mut ctx = Context::new(&tree, false, bbox, c);
+
+        // Keys hold the content and properties themselves instead of a hash.
+        ctx.push();
+        let key = ctx.form_key(b"0 0 m 10 10 l S", ("group", 1));
+        assert_eq!(key, ctx.form_key(b"0 0 m 10 10 l S", ("group", 1)));
+        assert_ne!(key, ctx.form_key(b"0 0 m 10 10 l S", ("group", 2)));
+        assert_ne!(key, ctx.form_key(b"0 0 m 5 5 l S", ("group", 1)));
+        assert!(key.windows(15).any(|w| w == b"0 0 m 10 10 l S"));
     }
-}
 
-/// Prepare a mask to be written to the file. This will calculate the metadata
-/// and create a `pending_group`.
-fn apply_mask(
-    mask_id: Option<&String>,
-    bbox: usvg::Rect,
-    pdf_bbox: Rect,
-    ctx: &mut Context,
-) -> Option<Ref> {
-    if let Some(mask_node) = mask_id.and_then(|id| ctx.tree.defs_by_id(id)) {
-        if let NodeKind::Mask(ref mask) = *mask_node.borrow() {
-            let reference = ctx.alloc_ref();
-            let (bbox, matrix) = if mask.content_units == usvg::Units::UserSpaceOnUse {
-                (*ctx.bbox, None)
-            } else {
-                let point = mask_node.transform().apply(mask.rect.x(), mask.rect.y());
-                let (x, y) = ctx.c.point(point);
-                let transform =
-                    [1.0, 0.0, 0.0, 1.0, bbox.x() as f32 + x, bbox.y() as f32 + y];
-                (pdf_bbox, Some(transform))
-            };
+    #[test]
+    #[cfg(feature = "async")]
+    fn async_conversion() {
+        use std::future::Future;
+        use std::task::{Poll, Waker};
 
-            apply_mask(mask.mask.as_ref(), mask.rect, pdf_bbox, ctx);
+        let svg = fs::read_to_string("tests/example.svg").unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let pdf = convert_str_async(svg.clone(), Options::default()).await.unwrap();
+            assert!(pdf.starts_with(b"%PDF"));
 
-            ctx.pending_groups.insert(mask.id.clone(), PendingGroup {
-                reference,
-                bbox,
-                matrix,
-                initial_mask: mask.mask.clone(),
-            });
+            let result = convert_str_async("<svg".into(), Options::default()).await;
+            assert!(matches!(result, Err(AsyncError::Parse(_))));
+        });
 
-            Some(reference)
-        } else {
-            unreachable!()
-        }
-    } else {
-        None
+        // The conversion is spawned onto a runtime that already shut down.
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let handle = runtime.handle().clone();
+        runtime.shutdown_background();
+
+        let _guard = handle.enter();
+        let conversion = std::pin::pin!(convert_str_async(svg, Options::default()));
+        let mut cx = std::task::Context::from_waker(Waker::noop());
+        let result = conversion.poll(&mut cx);
+        assert!(matches!(result, Poll::Ready(Err(AsyncError::Cancelled))));
     }
-}
 
-/// A color helper function that stores colors with values between 0.0 and 1.0.
-#[derive(Debug, Clone, Copy)]
-struct RgbColor {
-    /// Red.
-    r: f32,
-    /// Green.
-    g: f32,
-    /// Blue.
-    b: f32,
-}
+    #[test]
+    fn pass_through_groups() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <clipPath id="clip"><rect width="4" height="4"/></clipPath>
+            <g clip-path="url(#clip)"><rect width="5" height="5"/></g>
+            <g opacity="0.5"><rect width="5" height="5"/></g>
+            <g opacity="0.5"><rect width="5" height="5" visibility="hidden"/></g>
+        </svg>"#;
 
-impl RgbColor {
-    /// Create a new color.
-    fn new(r: f32, g: f32, b: f32) -> RgbColor {
-        RgbColor { r, g, b }
+        // The opacity of the group is applied to the rectangle directly.
+        let pdf = convert_str(src, Options { compress: false, ..Options::default() });
+        let pdf = String::from_utf8_lossy(&pdf.unwrap()).into_owned();
+        assert!(!pdf.contains("/Subtype /Form"));
+        assert!(!pdf.contains("q\nQ"));
     }
 
-    /// Create a new color from u8 color components between 0.0 and 255.0.
-    fn from_u8(r: u8, g: u8, b: u8) -> RgbColor {
-        RgbColor::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
-    }
+    #[test]
+    fn compression_feature() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <rect width="5" height="5"/>
+        </svg>"#;
 
-    /// Create a RGB array for use in PDF.
-    fn to_array(&self) -> [f32; 3] {
-        [self.r, self.g, self.b]
+        let pdf = convert_str(src, Options::default()).unwrap();
+        let compressed = String::from_utf8_lossy(&pdf).contains("/FlateDecode");
+        assert_eq!(compressed, cfg!(feature = "compress"));
     }
-}
 
-impl From<usvg::Color> for RgbColor {
-    fn from(color: usvg::Color) -> Self {
-        Self::from_u8(color.red, color.green, color.blue)
+    #[test]
+    fn structure_tree() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <g aria-label="First bar"><rect width="20" height="80"/></g>
+            <g><title>Second bar</title><desc>Half as high</desc>
+                <rect x="40" width="20" height="40"/>
+                <rect x="40" y="40" width="20" height="10" opacity="0.5"/>
+            </g>
+            <rect x="70" width="20" height="20"/>
+        </svg>"#;
+
+        let options = Options {
+            tagged: true,
+            compress: false,
+            ..Options::default()
+        };
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/StructTreeRoot 4 0 R"));
+        assert!(pdf.contains("/Marked true"));
+        assert!(pdf.contains("/StructParents 0"));
+        assert!(pdf.contains("/S /Document"));
+        assert_eq!(pdf.matches("/S /Figure").count(), 2);
+        assert!(pdf.contains("/Alt (First bar)"));
+        assert!(pdf.contains("/K [1 2]"));
+        assert!(pdf.contains("/Nums [0 9 0 R]"));
+        assert!(pdf.contains("[7 0 R 8 0 R 8 0 R]"));
+        assert_eq!(pdf.matches("/MCID").count(), 3);
+        assert_eq!(pdf.matches("/Artifact BMC").count(), 1);
+
+        let pdf = convert_str(src, Options { compress: false, ..Options::default() });
+        assert!(!String::from_utf8_lossy(&pdf.unwrap()).contains("MCID"));
     }
-}
 
-/// Write the functions for a gradient with its stops. Also registers them with
-/// the context and can create an alpha gradient function.
-fn register_functions(
-    writer: &mut PdfWriter,
-    ctx: &mut Context,
-    id: &str,
-    stops: &[Stop],
-) {
-    let func_ref = ctx.alloc_ref();
-    stops_to_function(writer, func_ref, stops, false);
+    #[test]
+    fn stripped_metadata() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+        let pdf = convert_str(src, Options::default()).unwrap();
+        assert!(String::from_utf8_lossy(&pdf).contains("/Producer (svg2pdf)"));
 
-    let alpha_ref = if stops.iter().any(|stop| stop.opacity.value() < 1.0) {
-        let alpha_ref = ctx.alloc_ref();
-        stops_to_function(writer, alpha_ref, &stops, true);
-        Some(alpha_ref)
-    } else {
-        None
-    };
+        let options = Options {
+            provenance: Some(Provenance { source: None, source_hash: None }),
+            strip_metadata: true,
+            ..Options::default()
+        };
 
-    ctx.function_map.insert(id.to_string(), (func_ref, alpha_ref));
-}
+        let pdf = convert_str(src, options).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(!pdf.contains("/Producer"));
+        assert!(!pdf.contains("/Metadata"));
+        assert!(!pdf.contains("svg2pdf"));
+    }
 
-/// Convert a list of stops to a function and write it.
-fn stops_to_function(
-    writer: &mut PdfWriter,
-    id: Ref,
-    stops: &[Stop],
-    alpha: bool,
-) -> bool {
-    let range = [0.0f32, 1.0f32].into_iter().cycle().take(if alpha { 2 } else { 6 });
+    #[test]
+    fn caller_allocated_ids() {
+        let inner = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <rect width="10" height="10" fill="blue" opacity="0.5"/>
+        </svg>"#;
+
+        let src = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg"
+                    xmlns:xlink="http://www.w3.org/1999/xlink" width="100" height="100">
+                <linearGradient id="g"><stop stop-color="red"/><stop offset="1"/></linearGradient>
+                <rect width="50" height="50" fill="url(#g)"/>
+                <image width="20" height="20" xlink:href="data:image/svg+xml;base64,{}"/>
+            </svg>"#,
+            base64::encode(inner),
+        );
+
+        let opt = usvg::Options::default();
+        let tree = Tree::from_str(&src, &opt.to_ref()).unwrap();
 
-    let set_alphas =
-        |exp: &mut ExponentialFunction, a_alpha: Opacity, b_alpha: Opacity| {
-            exp.c0([a_alpha.value() as f32]);
-            exp.c1([b_alpha.value() as f32]);
+        // Hand out every other id, as if the caller wrote objects in between.
+        let mut next = 100;
+        let mut allocated = vec![];
+        let mut alloc = || {
+            next += 2;
+            allocated.push(next);
+            Ref::new(next)
         };
 
-    let set_rgbs =
-        |exp: &mut ExponentialFunction, a_color: RgbColor, b_color: RgbColor| {
-            exp.c0(a_color.to_array());
-            exp.c1(b_color.to_array());
+        let mut writer = PdfWriter::new();
+        let id = convert_tree_into_with_allocator(
+            &tree,
+            Options { compress: false, ..Options::default() },
+            &mut writer,
+            &mut alloc,
+        );
+
+        assert_eq!(id, Ref::new(102));
+        let pdf = writer.finish();
+        let pdf = String::from_utf8_lossy(&pdf);
+        let mut written: Vec<i32> = pdf
+            .lines()
+            .filter_map(|line| line.strip_suffix(" 0 obj"))
+            .map(|id| id.parse().unwrap())
+            .collect();
+
+        written.sort_unstable();
+        assert_eq!(written, allocated);
+        assert_eq!(pdf.matches("/Subtype /Form").count(), 2);
+    }
+
+    #[test]
+    fn deferred_aspect_ratio() {
+        let svg = |aspect: &str| {
+            format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50"
+                    viewBox="0 0 100 50" {}><rect width="100" height="50"/></svg>"#,
+                aspect
+            )
         };
 
-    if stops.is_empty() {
-        return false;
-    } else if stops.len() == 1 {
-        let mut exp = writer.exponential_function(id);
-        let stop = stops[0];
-        let color = RgbColor::from(stop.color);
+        // The offsets and scale of the SVG in a square viewport.
+        let place = |svg: &str, aspect: &str| {
+            let tree = Tree::from_str(svg, &usvg::Options::default().to_ref()).unwrap();
+            let options = Options {
+                viewport: Some((200.0, 200.0)),
+                aspect: Some(aspect.parse().unwrap()),
+                ..Options::default()
+            };
 
-        exp.domain([0.0, 1.0]);
-        exp.range(range);
-        if alpha {
-            set_alphas(&mut exp, stop.opacity, stop.opacity)
-        } else {
-            set_rgbs(&mut exp, color, color);
-        }
+            let (c, _) = get_sizings(&tree, &options);
+            (c.offset_x(), c.offset_y(), c.factor_x(), c.factor_y())
+        };
 
-        exp.n(1.0);
-        return true;
+        let own = svg(r#"preserveAspectRatio="xMinYMin slice""#);
+        assert_eq!(place(&svg(""), "xMinYMax meet"), (0.0, 0.0, 2.0, 2.0));
+        assert_eq!(place(&svg(""), "defer xMinYMax meet"), (0.0, 0.0, 2.0, 2.0));
+        assert_eq!(place(&own, "xMinYMax meet"), (0.0, 0.0, 2.0, 2.0));
+        assert_eq!(place(&own, "defer xMinYMax meet"), (0.0, 0.0, 4.0, 4.0));
+        assert_eq!(place(&own, "defer none"), (0.0, 0.0, 4.0, 4.0));
+        assert_eq!(place(&svg(""), "defer none"), (0.0, 0.0, 2.0, 4.0));
     }
 
-    let mut stitching = writer.stitching_function(id);
-    stitching.domain([0.0, 1.0]);
-    stitching.range(range.clone());
+    #[test]
+    fn oversized_pages() {
+        let media_box = |pdf: &[u8]| -> Vec<f32> {
+            let pdf = String::from_utf8_lossy(pdf);
+            let start = pdf.find("/MediaBox [").unwrap() + 11;
+            let end = start + pdf[start ..].find(']').unwrap();
+            pdf[start .. end].split(' ').map(|v| v.parse().unwrap()).collect()
+        };
 
-    let mut func_array = stitching.insert(Name(b"Functions")).array();
-    let mut bounds = Vec::new();
-    let mut encode = Vec::with_capacity(2 * (stops.len() - 1));
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="40000" height="100">
+            <rect width="40000" height="100"/>
+        </svg>"#;
 
-    let stops = if stops[0].offset.value() != 0.0 {
-        let mut appended = stops[0].clone();
-        appended.offset = usvg::StopOffset::new(0.0);
+        let pdf = convert_str(src, Options::default()).unwrap();
+        assert!(media_box(&pdf)[2] <= MAX_PAGE_SIZE);
+        assert!(String::from_utf8_lossy(&pdf).contains("/UserUnit 2.778"));
 
-        let mut res = vec![appended];
-        res.extend_from_slice(stops);
-        res
-    } else {
-        stops.to_vec()
-    };
+        // A page that only exceeds the limit with its bleed and marks.
+        let src =
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="14400" height="100"/>"#;
+        let options = Options { bleed: Some(9.0), ..Options::default() };
+        let pdf = convert_str(src, options).unwrap();
+        assert!(media_box(&pdf)[2] <= MAX_PAGE_SIZE);
+        assert!(String::from_utf8_lossy(&pdf).contains("/UserUnit"));
 
-    for window in stops.windows(2) {
-        let (a, b) = (window[0], window[1]);
-        let (a_color, b_color) = (RgbColor::from(a.color), RgbColor::from(b.color));
-        bounds.push(b.offset.value() as f32);
-        let mut exp = ExponentialFunction::start(func_array.push());
-        exp.domain([0.0, 1.0]);
-        exp.range(range.clone());
-        if alpha {
-            set_alphas(&mut exp, a.opacity, b.opacity);
-        } else {
-            set_rgbs(&mut exp, a_color, b_color);
-        }
+        let opt = usvg::Options::default();
+        let tree = Tree::from_str(src, &opt.to_ref()).unwrap();
+        let rect = Rect::new(0.0, 0.0, 30000.0, 500.0);
+        let placements = [Placement { tree: &tree, rect }];
+        let pdf = convert_composition((30000.0, 500.0), &placements, Options::default());
+        assert!(media_box(&pdf)[2] <= MAX_PAGE_SIZE);
+        assert!(String::from_utf8_lossy(&pdf).contains("/UserUnit 2.084"));
+    }
 
-        exp.n(1.0);
+    #[test]
+    fn composed_placements() {
+        let opt = usvg::Options::default();
+        let square = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <rect width="10" height="10"/>
+        </svg>"#;
+        let circle = r#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+            <circle cx="10" cy="5" r="5"/>
+        </svg>"#;
+        let square = Tree::from_str(square, &opt.to_ref()).unwrap();
+        let circle = Tree::from_str(circle, &opt.to_ref()).unwrap();
 
-        encode.extend([0.0, 1.0]);
+        let place =
+            |tree, x1, y1, x2, y2| Placement { tree, rect: Rect::new(x1, y1, x2, y2) };
+        let placements = [
+            place(&square, 10.0, 20.0, 110.0, 120.0),
+            place(&square, 200.0, 20.0, 300.0, 120.0),
+            place(&circle, 10.0, 200.0, 110.0, 250.0),
+            place(&square, 200.0, 200.0, 250.0, 250.0),
+            place(&circle, 300.0, 300.0, 300.0, 350.0),
+        ];
+
+        let options = Options { compress: false, ..Options::default() };
+        let pdf = convert_composition((400.0, 400.0), &placements, options);
+        let pdf = String::from_utf8_lossy(&pdf);
+
+        // The two squares of the same size share an XObject, the square of
+        // another size gets its own and the empty area is skipped.
+        assert_eq!(pdf.matches("/Subtype /Form").count(), 3);
+        assert!(pdf.contains("/xo0 5 0 R\n      /xo1 6 0 R\n      /xo2 7 0 R\n"));
+
+        // Each XObject is normalized to one point and scaled to its area.
+        assert!(pdf.contains("/BBox [0 0 100 100]\n  /Matrix [0.01 0 0 0.01 0 0]"));
+        assert!(pdf.contains("/BBox [0 0 100 50]\n  /Matrix [0.01 0 0 0.02 0 0]"));
+        assert!(pdf.contains(
+            "q\n100 0 0 100 10 20 cm\n/xo0 Do\nQ\n\
+             q\n100 0 0 100 200 20 cm\n/xo0 Do\nQ\n\
+             q\n100 0 0 50 10 200 cm\n/xo1 Do\nQ\n\
+             q\n50 0 0 50 200 200 cm\n/xo2 Do\nQ\n"
+        ));
     }
 
-    func_array.finish();
-    bounds.pop();
-    stitching.bounds(bounds);
-    stitching.encode(encode);
+    #[test]
+    fn portfolio_collection() {
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+        let tree = Tree::from_str(src, &usvg::Options::default().to_ref()).unwrap();
+        let file = |name: &str| PortfolioFile {
+            name: name.into(),
+            tree: &tree,
+            modified: None,
+        };
 
-    true
-}
+        let files = [file("b.svg"), file("a.svg"), file("a.svg"), file("c")];
+        let options = Options { compress: false, ..Options::default() };
+        let pdf = convert_portfolio(&files, options);
+        let pdf = String::from_utf8_lossy(&pdf);
 
-/// Create and return the writer for an transparency group form XObject.
-fn form_xobject<'a>(
-    writer: &'a mut PdfWriter,
-    reference: Ref,
-    content: &'a [u8],
-    bbox: Rect,
-    compress: bool,
-    has_color: bool,
-) -> FormXObject<'a> {
-    let mut form = writer.form_xobject(reference, content);
-    form.bbox(bbox);
+        // The catalog of the portfolio comes after the embedded documents.
+        let catalog = &pdf[pdf.rfind("/Type /Catalog").unwrap() ..];
+        assert!(catalog.contains(
+            "/Type /Collection\n    /View /D\n    /D (b.pdf)\n    \
+             /Schema <<\n      /Type /CollectionSchema"
+        ));
+        for field in ["/F <<", "/Size <<", "/ModDate <<"] {
+            assert!(catalog.contains(field));
+        }
 
-    if compress {
-        form.filter(Filter::FlateDecode);
+        // The duplicate name gets a number and the keys are sorted by their
+        // bytes, which puts `a (2).pdf` first.
+        assert!(catalog.contains(
+            "/Names [<61202832292E706466> 10 0 R (a.pdf) 8 0 R (b.pdf) 6 0 R (c.pdf) 12 0 R]"
+        ));
+        assert_eq!(pdf.matches("/Type /EmbeddedFile").count(), 4);
     }
 
-    let mut group = form.group();
-    group.transparency();
-    group.isolated(true);
-    group.knockout(false);
+    #[test]
+    fn precise_coordinates() {
+        let viewbox = ViewBox {
+            rect: usvg::Rect::new(0.0, 0.0, 1e7, 1e7).unwrap(),
+            aspect: AspectRatio::default(),
+        };
 
-    let space = group.color_space();
-    if has_color {
-        space.srgb();
-    } else {
-        space.d65_gray();
+        // Coordinates far from the origin keep their fraction until they
+        // are written.
+        let mut c = CoordToPdf::new((1e7, 1e7), 72.0, viewbox, None);
+        let (x, y) = c.precise_point((1234567.891, 1e7 - 7654321.123));
+        assert!((x - 1234567.891).abs() < 1e-6);
+        assert!((y - 7654321.123).abs() < 1e-6);
+
+        c.set_precision(Some(2));
+        let (x, _) = c.precise_point((1234567.891, 0.0));
+        assert!((x - 1234567.89).abs() < 1e-6);
+        assert_eq!(c.point((1234567.891, 0.0)).0, 1234567.89f64 as f32);
     }
 
-    group.finish();
-    form
-}
+    /// Memory regression tests, which count the allocations of the test
+    /// thread with an instrumented global allocator. Run them with
+    /// `cargo test --features memory-tests memory`. The limits leave about
+    /// half of the measured usage as headroom.
+    #[cfg(feature = "memory-tests")]
+    mod memory {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::cell::Cell;
 
-/// Compress data with the DEFLATE algorithm.
-fn deflate(data: &[u8]) -> Vec<u8> {
-    const COMPRESSION_LEVEL: u8 = 6;
-    miniz_oxide::deflate::compress_to_vec_zlib(data, COMPRESSION_LEVEL)
-}
+        use super::*;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
+        #[global_allocator]
+        static ALLOCATOR: Counting = Counting;
 
-    #[test]
-    fn files() {
-        let paths = fs::read_dir("tests").unwrap();
-        for path in paths {
-            let path = path.unwrap();
-            let base_name = path.file_name().to_string_lossy().to_string();
+        thread_local! {
+            /// The number of bytes allocated by this thread that are still live.
+            static CURRENT: Cell<isize> = const { Cell::new(0) };
+            /// The maximum of `CURRENT` since the last measurement started.
+            static PEAK: Cell<isize> = const { Cell::new(0) };
+            /// The number of allocations made by this thread.
+            static COUNT: Cell<usize> = const { Cell::new(0) };
+        }
 
-            println!("{}", base_name);
+        /// The system allocator, recording allocations per thread.
+        struct Counting;
 
-            let doc = fs::read_to_string(path.path()).unwrap();
-            let mut options = Options::default();
-            options.dpi = 72.0;
-            let buf = convert_str(&doc, options).unwrap();
+        unsafe impl GlobalAlloc for Counting {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                record(layout.size() as isize, 1);
+                System.alloc(layout)
+            }
 
-            let len = base_name.len();
-            let file_name = format!("{}.pdf", &base_name[0 .. len - 4]);
+            unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+                record(layout.size() as isize, 1);
+                System.alloc_zeroed(layout)
+            }
 
-            std::fs::write(format!("target/{}", file_name), buf).unwrap();
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                record(-(layout.size() as isize), 0);
+                System.dealloc(ptr, layout)
+            }
+
+            unsafe fn realloc(
+                &self,
+                ptr: *mut u8,
+                layout: Layout,
+                size: usize,
+            ) -> *mut u8 {
+                record(size as isize - layout.size() as isize, 1);
+                System.realloc(ptr, layout, size)
+            }
+        }
+
+        fn record(delta: isize, count: usize) {
+            // The thread locals are gone while a thread shuts down.
+            let _ = CURRENT.try_with(|current| {
+                let now = current.get() + delta;
+                current.set(now);
+                PEAK.with(|peak| peak.set(peak.get().max(now)));
+                COUNT.with(|total| total.set(total.get() + count));
+            });
+        }
+
+        /// Run a closure and return the peak number of bytes it had allocated
+        /// at once and its number of allocations.
+        fn measure(f: impl FnOnce()) -> (usize, usize) {
+            let start = CURRENT.with(Cell::get);
+            let count = COUNT.with(Cell::get);
+            PEAK.with(|peak| peak.set(start));
+            f();
+            let peak = PEAK.with(Cell::get) - start;
+            (peak as usize, COUNT.with(Cell::get) - count)
+        }
+
+        /// Convert an SVG string and fail if the conversion needed more
+        /// memory or allocations than allowed.
+        fn check(name: &str, src: &str, max_bytes: usize, max_allocations: usize) {
+            let (bytes, allocations) = measure(|| {
+                convert_str(src, Options::default()).unwrap();
+            });
+
+            println!("{name}: {bytes} bytes at peak, {allocations} allocations");
+            assert!(
+                bytes <= max_bytes,
+                "{name} used {bytes} bytes at peak, more than {max_bytes}",
+            );
+            assert!(
+                allocations <= max_allocations,
+                "{name} made {allocations} allocations, more than {max_allocations}",
+            );
+        }
+
+        #[test]
+        fn many_paths() {
+            let mut src = String::from(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="1000" height="1000">"#,
+            );
+            for i in 0 .. 20_000 {
+                let (x, y) = (i % 200 * 5, i / 200 * 5);
+                src += &format!(r#"<rect x="{x}" y="{y}" width="4" height="4"/>"#);
+            }
+            src += "</svg>";
+            check("many paths", &src, 48 << 20, 120_000);
+        }
+
+        #[test]
+        fn masks() {
+            let mut src = String::from(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="1000" height="1000">
+                <linearGradient id="fade"><stop offset="0" stop-color="white"/>
+                <stop offset="1" stop-color="black"/></linearGradient>"#,
+            );
+            for i in 0 .. 1000 {
+                let (x, y) = (i % 40 * 25, i / 40 * 25);
+                src += &format!(
+                    r#"<mask id="m{i}"><rect x="{x}" y="{y}" width="20" height="20" fill="url(#fade)"/></mask>
+                    <g mask="url(#m{i})" opacity="0.5"><rect x="{x}" y="{y}" width="20" height="20" fill="red"/></g>"#
+                );
+            }
+            src += "</svg>";
+            check("masks", &src, 11 << 20, 50_000);
+        }
+
+        #[test]
+        #[cfg(feature = "png")]
+        fn large_image() {
+            let image = image::RgbaImage::from_fn(2048, 2048, |x, y| {
+                image::Rgba([x as u8, y as u8, (x ^ y) as u8, (x + y) as u8])
+            });
+
+            let mut png = vec![];
+            image::DynamicImage::ImageRgba8(image)
+                .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+                .unwrap();
+
+            let src = format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="2048" height="2048">
+                <image width="2048" height="2048" href="data:image/png;base64,{}"/>
+                </svg>"#,
+                base64::encode(&png),
+            );
+            check("large image", &src, 100 << 20, 200);
         }
     }
 }
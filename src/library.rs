@@ -0,0 +1,86 @@
+//! Bundle multiple SVG files into a library of named Form XObjects.
+
+use std::iter;
+
+use pdf_writer::{Finish, Name, PdfWriter, Ref};
+use usvg::Tree;
+
+use crate::{convert_tree_into, write_document_info, Options};
+
+/// A graphic to be included in a [resource library](convert_library).
+#[derive(Clone)]
+pub struct LibraryItem<'a> {
+    /// The name of the Form XObject, e.g. `arrow`.
+    pub name: String,
+    /// The parsed SVG file.
+    pub tree: &'a Tree,
+}
+
+/// Convert multiple [`usvg` trees](Tree) into a PDF file that contains each
+/// as a named Form XObject and has no pages.
+///
+/// The XObjects are listed by name in the `XObject` entry of the resource
+/// dictionary of the page tree root, where tools that build on `pdf-writer`
+/// can look them up to copy and stamp them repeatedly. Names that occur more
+/// than once get a numeric suffix. As with [`convert_tree_into`], each XObject
+/// is scaled to a unit square, while its bounding box has the size of the
+/// graphic in points.
+///
+/// ## Example
+/// ```rust
+/// use svg2pdf::LibraryItem;
+///
+/// let opt = usvg::Options::default();
+/// let mut trees = vec![];
+/// for name in ["example", "group"] {
+///     let svg = std::fs::read_to_string(format!("tests/{}.svg", name)).unwrap();
+///     trees.push((name, usvg::Tree::from_str(&svg, &opt.to_ref()).unwrap()));
+/// }
+///
+/// let items: Vec<_> = trees
+///     .iter()
+///     .map(|(name, tree)| LibraryItem { name: name.to_string(), tree })
+///     .collect();
+///
+/// let pdf = svg2pdf::convert_library(&items, svg2pdf::Options::default());
+/// # assert!(pdf.starts_with(b"%PDF"));
+/// ```
+pub fn convert_library(items: &[LibraryItem], options: Options) -> Vec<u8> {
+    let mut writer = PdfWriter::new();
+
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let mut next_id = Ref::new(3);
+
+    let mut entries: Vec<(String, Ref)> = vec![];
+    for item in items {
+        let mut name = item.name.clone();
+        let mut i = 2;
+        while entries.iter().any(|(other, _)| *other == name) {
+            name = format!("{}-{}", item.name, i);
+            i += 1;
+        }
+
+        let id = next_id;
+        next_id = convert_tree_into(item.tree, options.clone(), &mut writer, id);
+        entries.push((name, id));
+    }
+
+    writer.catalog(catalog_id).pages(page_tree_id);
+
+    let mut pages = writer.pages(page_tree_id);
+    pages.kids(iter::empty()).count(0);
+
+    let mut resources = pages.resources();
+    let mut xobjects = resources.x_objects();
+    for (name, id) in &entries {
+        xobjects.pair(Name(name.as_bytes()), *id);
+    }
+
+    xobjects.finish();
+    resources.finish();
+    pages.finish();
+
+    write_document_info(&mut writer, next_id, &options);
+    writer.finish()
+}
@@ -0,0 +1,170 @@
+//! Guard against images that expand to huge sizes when decoded.
+
+use std::ops::Range;
+
+#[cfg(feature = "compress")]
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+use roxmltree::Document;
+
+/// The maximum nesting depth of SVG images in data URLs that is checked.
+/// Images with deeper nested SVG images in data URLs are removed.
+const MAX_DEPTH: usize = 8;
+
+/// Rewrite an SVG source string such that SVG images in data URLs that are
+/// compressed with gzip and would decompress to more than `limit` bytes are
+/// removed, along with SVG images that contain such images.
+///
+/// usvg decompresses such images without bounds while parsing, so a small
+/// source string could otherwise allocate gigabytes. Returns `None` if the
+/// source is not valid XML or has no such images.
+pub(crate) fn guard_svg_images(src: &str, limit: usize) -> Option<String> {
+    let edits = oversized_images(src, limit, 0);
+    if edits.is_empty() {
+        return None;
+    }
+
+    let mut out = src.to_string();
+    for range in edits.into_iter().rev() {
+        out.replace_range(range, "");
+    }
+
+    Some(out)
+}
+
+/// The ranges of the images in an SVG source string that are too large at
+/// the given nesting depth.
+fn oversized_images(src: &str, limit: usize, depth: usize) -> Vec<Range<usize>> {
+    if !may_contain_svg_images(src) {
+        return vec![];
+    }
+
+    let doc = match Document::parse(src) {
+        Ok(doc) => doc,
+        Err(_) => return vec![],
+    };
+
+    let mut ranges = vec![];
+    for node in doc.descendants().filter(|node| node.has_tag_name("image")) {
+        let href = node.attributes().iter().find(|attr| attr.name() == "href");
+        let data = match href.and_then(|attr| data_url(attr.value())) {
+            Some(data) => data,
+            None => continue,
+        };
+
+        if exceeds(&data, limit, depth) {
+            log::warn!(
+                "Skipped compressed SVG image that expands to more than {} bytes.",
+                limit,
+            );
+            ranges.push(node.range());
+        }
+    }
+
+    ranges
+}
+
+/// Whether any base64-encoded data URL in an SVG source string might hold an
+/// SVG image, judging by the first bytes of its payload.
+///
+/// This runs before the source is parsed so that the guard, which is enabled
+/// by default, doesn't parse sources whose data URLs only hold raster images.
+pub(crate) fn may_contain_svg_images(src: &str) -> bool {
+    src.match_indices("data:").any(|(start, _)| {
+        let href = &src[start ..];
+        let href = &href[.. href.find(['"', '\'']).unwrap_or(href.len())];
+        let payload = match href.split_once(',') {
+            Some((header, payload)) if header.ends_with(";base64") => payload,
+            _ => return false,
+        };
+
+        // Four base64 characters encode the three bytes of the magic numbers.
+        let prefix: String =
+            payload.chars().filter(|c| !c.is_whitespace()).take(4).collect();
+        let raster: [&[u8]; 3] = [b"\x89PN", b"\xff\xd8\xff", b"GIF"];
+        match base64::decode(prefix) {
+            Ok(magic) => !raster.contains(&magic.as_slice()),
+            Err(_) => true,
+        }
+    })
+}
+
+/// Decode the payload of a base64-encoded data URL.
+fn data_url(href: &str) -> Option<Vec<u8>> {
+    let (header, payload) = href.trim().strip_prefix("data:")?.split_once(',')?;
+    if !header.ends_with(";base64") {
+        return None;
+    }
+
+    let payload: String = payload.chars().filter(|c| !c.is_whitespace()).collect();
+    base64::decode(payload).ok()
+}
+
+/// Whether data is gzip-compressed and decompresses to more than `limit`
+/// bytes or is an SVG image that contains such data, as usvg parses nested SVG
+/// images with the same options.
+fn exceeds(data: &[u8], limit: usize, depth: usize) -> bool {
+    let inflated;
+    let svg = match gzip_body(data) {
+        Some(body) => match inflate(body, limit) {
+            Some(data) => {
+                inflated = data;
+                inflated.as_slice()
+            }
+            None => return true,
+        },
+        None => data,
+    };
+
+    match std::str::from_utf8(svg) {
+        Ok(svg) if svg.contains("data:") => {
+            depth >= MAX_DEPTH || !oversized_images(svg, limit, depth + 1).is_empty()
+        }
+        _ => false,
+    }
+}
+
+/// Decompress a DEFLATE stream unless it is invalid or decompresses to more
+/// than `limit` bytes.
+#[cfg(feature = "compress")]
+fn inflate(body: &[u8], limit: usize) -> Option<Vec<u8>> {
+    decompress_to_vec_with_limit(body, limit).ok()
+}
+
+/// Without the `compress` feature, compressed data can't be measured and all
+/// of it is treated as too large.
+#[cfg(not(feature = "compress"))]
+fn inflate(_: &[u8], _: usize) -> Option<Vec<u8>> {
+    None
+}
+
+/// The DEFLATE stream in a gzip member, skipping the header.
+fn gzip_body(data: &[u8]) -> Option<&[u8]> {
+    const FHCRC: u8 = 1 << 1;
+    const FEXTRA: u8 = 1 << 2;
+    const FNAME: u8 = 1 << 3;
+    const FCOMMENT: u8 = 1 << 4;
+
+    if data.get(.. 3)? != [0x1f, 0x8b, 8] {
+        return None;
+    }
+
+    let flags = *data.get(3)?;
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = u16::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?]);
+        pos += 2 + len as usize;
+    }
+
+    // The file name and comment are terminated by a zero byte.
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            pos += data.get(pos ..)?.iter().position(|&b| b == 0)? + 1;
+        }
+    }
+
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+
+    data.get(pos ..)
+}
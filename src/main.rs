@@ -1,20 +1,135 @@
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use termcolor::{ColorChoice, ColorSpec, StandardStream, WriteColor};
 
+use manifest::Job;
+
+mod manifest;
+
 #[derive(Debug, Parser)]
-#[clap(about, version)]
+#[clap(about, version, args_conflicts_with_subcommands = true)]
+#[clap(subcommand_negates_reqs = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
     /// Path to read SVG file from.
-    input: PathBuf,
+    #[clap(required_unless_present = "manifest")]
+    input: Option<PathBuf>,
     /// Path to write PDF file to.
     output: Option<PathBuf>,
     /// The number of SVG pixels per PDF points.
     #[clap(long, default_value = "72.0")]
     dpi: f64,
+    /// Do not load images referenced by a file path.
+    #[clap(long)]
+    no_external: bool,
+    /// The width of the page in points. The SVG is scaled to fit.
+    #[clap(long, requires = "height")]
+    width: Option<f32>,
+    /// The height of the page in points. The SVG is scaled to fit.
+    #[clap(long, requires = "width")]
+    height: Option<f32>,
+    /// Round the page size up to whole points or whole pixels at the DPI,
+    /// stretching the content along.
+    #[clap(long, possible_values = &["points", "pixels"])]
+    round_page_size: Option<String>,
+    /// Path to a font file to set text in whose font families are not
+    /// available.
+    #[clap(long)]
+    default_font: Option<PathBuf>,
+    /// Path to a CSS style sheet to apply to the SVG file.
+    #[clap(long)]
+    css: Option<PathBuf>,
+    /// Compress large streams on multiple threads.
+    #[clap(long)]
+    parallel_compression: bool,
+    /// Write the content of patterns uncompressed, for printers that
+    /// mishandle compressed pattern streams.
+    #[clap(long)]
+    uncompressed_patterns: bool,
+    /// The DEFLATE level between 0 (no compression) and 10 to compress
+    /// streams with.
+    #[clap(long)]
+    compression_level: Option<u8>,
+    /// Store objects in compressed object streams, which requires PDF 1.5.
+    #[clap(long)]
+    object_streams: bool,
+    /// Convert strokes into filled outlines.
+    #[clap(long)]
+    outline_strokes: bool,
+    /// Cut clipped paths to their clip region instead of clipping them.
+    #[clap(long)]
+    flatten_clip_paths: bool,
+    /// Downsample raster images whose resolution at their displayed size
+    /// exceeds this number of dots per inch.
+    #[clap(long)]
+    max_image_dpi: Option<f64>,
+    /// Recompress raster images as JPEG with this quality between 1 and 100.
+    #[clap(long)]
+    jpeg_quality: Option<u8>,
+    /// Record the input file and the options in the XMP metadata.
+    #[clap(long)]
+    provenance: bool,
+    /// Omit all metadata, such as the producer, from the PDF file.
+    #[clap(long, conflicts_with = "provenance")]
+    strip_metadata: bool,
+    /// Write one PDF file per top-level layer group, named after the layer.
+    #[clap(long)]
+    split_layers: bool,
+    /// Mark skipped or incompletely drawn elements with annotations that say
+    /// why.
+    #[clap(long)]
+    annotate_issues: bool,
+    /// A preferred language for choosing among the children of `<switch>`
+    /// elements. Can be given multiple times, most preferred first.
+    #[clap(long = "language", multiple_occurrences = true)]
+    languages: Vec<String>,
+    /// A feature string or extension URI that children of `<switch>`
+    /// elements may require. Can be given multiple times.
+    #[clap(long = "feature", multiple_occurrences = true)]
+    features: Vec<String>,
+    /// Print the time spent in each phase of the conversion.
+    #[clap(long)]
+    profile: bool,
+    /// Path to a JSON file that lists conversions to run with their paths and
+    /// options. Options on the command line apply to all of them.
+    #[clap(long, conflicts_with_all = &["input", "output"])]
+    manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Inspect the fonts of an SVG file.
+    #[clap(subcommand)]
+    Fonts(FontsCommand),
+    /// Bundle multiple SVG files into a PDF portfolio.
+    Portfolio {
+        /// Paths to read SVG files from.
+        #[clap(required = true)]
+        inputs: Vec<PathBuf>,
+        /// Path to write PDF file to.
+        #[clap(short, long)]
+        output: PathBuf,
+        /// The number of SVG pixels per PDF points.
+        #[clap(long, default_value = "72.0")]
+        dpi: f64,
+        /// Do not load images referenced by a file path.
+        #[clap(long)]
+        no_external: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum FontsCommand {
+    /// List the fonts required to render the text of an SVG file.
+    Required {
+        /// Path to read SVG file from.
+        input: PathBuf,
+    },
 }
 
 fn main() {
@@ -26,27 +141,107 @@ fn main() {
 
 fn run() -> Result<(), String> {
     let args = Args::parse();
+    match &args.command {
+        Some(Command::Fonts(FontsCommand::Required { input })) => {
+            return fonts_required(input);
+        }
+        Some(Command::Portfolio { inputs, output, dpi, no_external }) => {
+            return portfolio(inputs, output, *dpi, *no_external);
+        }
+        None => {}
+    }
 
-    // Determine output path.
-    let name =
-        Path::new(args.input.file_name().ok_or("Input path does not point to a file")?);
-    let output = args.output.unwrap_or_else(|| name.with_extension("pdf"));
+    let mut usvg_opts = usvg_options(args.no_external);
+    if let Some(path) = &args.default_font {
+        set_default_font(&mut usvg_opts, path)?;
+    }
 
-    // Load source file.
-    let svg =
-        std::fs::read_to_string(&args.input).map_err(|_| "Failed to load SVG file")?;
+    let converter =
+        svg2pdf::Converter::with_usvg_options(usvg_opts, svg2pdf::Options::default());
 
-    // Convert string to SVG.
-    let mut options = usvg::Options::default();
-    options.fontdb = fontdb::Database::new();
-    options.fontdb.load_system_fonts();
-    let tree =
-        usvg::Tree::from_str(&svg, &options.to_ref()).map_err(|err| err.to_string())?;
+    if let Some(path) = &args.manifest {
+        for job in manifest::read(path)? {
+            convert(&converter, &args, &job)
+                .map_err(|err| format!("{}: {}", job.input.display(), err))?;
+        }
+
+        return Ok(());
+    }
+
+    let job = Job {
+        input: args.input.clone().ok_or("No input path was given")?,
+        output: args.output.clone(),
+        ..Job::default()
+    };
+
+    convert(&converter, &args, &job)
+}
+
+/// Convert an SVG file with the options from the command line, overridden by
+/// those of the job.
+fn convert(converter: &svg2pdf::Converter, args: &Args, job: &Job) -> Result<(), String> {
+    // Determine output path.
+    let input = &job.input;
+    let name = Path::new(input.file_name().ok_or("Input path does not point to a file")?);
+    let output = job.output.clone().unwrap_or_else(|| name.with_extension("pdf"));
+
+    // Load source file and style sheet.
+    let svg = std::fs::read_to_string(input).map_err(|_| "Failed to load SVG file")?;
+    let style_sheet = job
+        .css
+        .as_ref()
+        .or(args.css.as_ref())
+        .map(std::fs::read_to_string)
+        .transpose()
+        .map_err(|_| "Failed to load style sheet")?;
 
     // Convert SVG to PDF.
-    let mut options = svg2pdf::Options::default();
-    options.dpi = args.dpi;
-    let pdf = svg2pdf::convert_tree(&tree, options);
+    let provenance = job.provenance.unwrap_or(args.provenance);
+    let options = svg2pdf::Options {
+        dpi: job.dpi.unwrap_or(args.dpi),
+        page_size: job.page_size.or(args.width.zip(args.height)),
+        page_rounding: args.round_page_size.as_deref().map(|unit| match unit {
+            "pixels" => svg2pdf::PageRounding::Pixels,
+            _ => svg2pdf::PageRounding::Points,
+        }),
+        style_sheet,
+        compress_patterns: !args.uncompressed_patterns,
+        parallel_compression: args.parallel_compression,
+        compression: args.compression_level.map_or(
+            svg2pdf::CompressionLevel::Default,
+            svg2pdf::CompressionLevel::Level,
+        ),
+        object_streams: args.object_streams,
+        outline_strokes: job.outline_strokes.unwrap_or(args.outline_strokes),
+        flatten_clip_paths: job.flatten_clip_paths.unwrap_or(args.flatten_clip_paths),
+        image_policy: (args.max_image_dpi.is_some() || args.jpeg_quality.is_some())
+            .then_some(svg2pdf::ImagePolicy {
+                max_dpi: args.max_image_dpi,
+                jpeg_quality: args.jpeg_quality,
+            }),
+        provenance: provenance.then(|| svg2pdf::Provenance {
+            source: Some(name.to_string_lossy().into_owned()),
+            source_hash: job.source_hash.clone(),
+        }),
+        strip_metadata: args.strip_metadata,
+        annotate_issues: args.annotate_issues,
+        languages: args.languages.clone(),
+        supported_features: args.features.clone(),
+        ..svg2pdf::Options::default()
+    };
+
+    if args.split_layers {
+        let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+        for (name, svg) in split_layers(&svg)? {
+            let path = output.with_file_name(format!("{stem}-{name}.pdf"));
+            let pdf = convert_svg(converter, &svg, options.clone(), args.profile, &path)?;
+            std::fs::write(path, pdf).map_err(|_| "Failed to write PDF file")?;
+        }
+
+        return Ok(());
+    }
+
+    let pdf = convert_svg(converter, &svg, options, args.profile, &output)?;
 
     // Write output file.
     std::fs::write(output, pdf).map_err(|_| "Failed to write PDF file")?;
@@ -54,6 +249,272 @@ fn run() -> Result<(), String> {
     Ok(())
 }
 
+/// Convert an SVG source string, printing the time spent in each phase if
+/// profiling is enabled.
+fn convert_svg(
+    converter: &svg2pdf::Converter,
+    svg: &str,
+    options: svg2pdf::Options,
+    profile: bool,
+    output: &Path,
+) -> Result<Vec<u8>, String> {
+    if !profile {
+        return converter.convert_with(svg, options).map_err(|err| err.to_string());
+    }
+
+    let (pdf, timings) = converter
+        .convert_profiled(svg, options)
+        .map_err(|err| err.to_string())?;
+    eprint!("{}:\n{}", output.display(), timings);
+    Ok(pdf)
+}
+
+/// Split an SVG file into one source string per top-level layer group, along
+/// with a file name for the layer.
+///
+/// Groups marked as layers by Inkscape take precedence over other top-level
+/// groups. In each source string, all top-level graphics but the layer are
+/// wrapped in a hidden group, so that references into them remain valid.
+fn split_layers(svg: &str) -> Result<Vec<(String, String)>, String> {
+    const INKSCAPE: &str = "http://www.inkscape.org/namespaces/inkscape";
+    const GRAPHICS: &[&str] = &[
+        "a", "circle", "ellipse", "g", "image", "line", "path", "polygon", "polyline",
+        "rect", "svg", "switch", "text", "use",
+    ];
+
+    let doc = roxmltree::Document::parse(svg).map_err(|err| err.to_string())?;
+    let graphics: Vec<_> = doc
+        .root_element()
+        .children()
+        .filter(|node| GRAPHICS.contains(&node.tag_name().name()))
+        .collect();
+
+    let is_layer =
+        |node: &roxmltree::Node| node.attribute((INKSCAPE, "groupmode")) == Some("layer");
+
+    let mut layers: Vec<_> = graphics.iter().filter(|node| is_layer(node)).collect();
+    if layers.is_empty() {
+        layers = graphics.iter().filter(|node| node.has_tag_name("g")).collect();
+    }
+
+    if layers.is_empty() {
+        return Err("The SVG file has no top-level groups to split".into());
+    }
+
+    let mut names: Vec<String> = vec![];
+    let mut sources = vec![];
+    for (i, layer) in layers.iter().enumerate() {
+        let label = layer
+            .attribute((INKSCAPE, "label"))
+            .or_else(|| layer.attribute("id"))
+            .map(|label| {
+                label
+                    .chars()
+                    .map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' })
+                    .collect()
+            })
+            .unwrap_or_else(|| format!("layer{}", i + 1));
+
+        // Layers with the same label get numbered file names.
+        let mut name = label.clone();
+        let mut n = 1;
+        while names.contains(&name) {
+            n += 1;
+            name = format!("{label}-{n}");
+        }
+
+        let mut out = svg.to_string();
+        for node in graphics.iter().rev().filter(|node| *node != *layer) {
+            let range = node.range();
+            out.insert_str(range.end, "</g>");
+            out.insert_str(range.start, "<g display=\"none\">");
+        }
+
+        names.push(name.clone());
+        sources.push((name, out));
+    }
+
+    Ok(sources)
+}
+
+/// Convert SVG files and bundle them into a PDF portfolio.
+fn portfolio(
+    inputs: &[PathBuf],
+    output: &Path,
+    dpi: f64,
+    no_external: bool,
+) -> Result<(), String> {
+    let options = usvg_options(no_external);
+
+    let mut trees = vec![];
+    for input in inputs {
+        let name = input
+            .file_name()
+            .ok_or("Input path does not point to a file")?
+            .to_string_lossy()
+            .into_owned();
+
+        let svg = std::fs::read_to_string(input)
+            .map_err(|_| format!("Failed to load SVG file {}", input.display()))?;
+
+        let tree = usvg::Tree::from_str(&svg, &options.to_ref())
+            .map_err(|err| format!("{}: {}", input.display(), err))?;
+
+        let modified = std::fs::metadata(input)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(date);
+
+        trees.push((name, tree, modified));
+    }
+
+    let files: Vec<svg2pdf::PortfolioFile> = trees
+        .iter()
+        .map(|(name, tree, modified)| svg2pdf::PortfolioFile {
+            name: name.clone(),
+            tree,
+            modified: *modified,
+        })
+        .collect();
+
+    let options = svg2pdf::Options { dpi, ..svg2pdf::Options::default() };
+    let pdf = svg2pdf::convert_portfolio(&files, options);
+
+    std::fs::write(output, pdf).map_err(|_| "Failed to write PDF file")?;
+
+    Ok(())
+}
+
+/// Convert a point in time to a PDF date in UTC.
+fn date(time: SystemTime) -> Option<pdf_writer::Date> {
+    let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let (days, time) = ((secs / 86400) as i64, secs % 86400);
+
+    // Convert days since the epoch to a civil date.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    Some(
+        pdf_writer::Date::new(u16::try_from(year).ok()?)
+            .month(month as u8)
+            .day(day as u8)
+            .hour((time / 3600) as u8)
+            .minute((time / 60 % 60) as u8)
+            .second((time % 60) as u8)
+            .utc_offset_hour(0),
+    )
+}
+
+/// The parsing options for SVG files, with system fonts loaded.
+fn usvg_options(no_external: bool) -> usvg::Options {
+    let mut options = usvg::Options {
+        fontdb: fontdb::Database::new(),
+        ..usvg::Options::default()
+    };
+
+    options.fontdb.load_system_fonts();
+    if no_external {
+        options.image_href_resolver.resolve_string = Box::new(|href, _| {
+            print_warning(&format!("refusing to load external image '{href}'")).ok();
+            None
+        });
+    }
+
+    options
+}
+
+/// Register a font file and make its family the fallback for text whose font
+/// families are not available.
+fn set_default_font(options: &mut usvg::Options, path: &Path) -> Result<(), String> {
+    let count = options.fontdb.faces().len();
+    options
+        .fontdb
+        .load_font_file(path)
+        .map_err(|_| "Failed to load default font")?;
+
+    let face = options
+        .fontdb
+        .faces()
+        .get(count)
+        .ok_or("Default font file contains no fonts")?;
+
+    options.font_family = face.family.clone();
+    Ok(())
+}
+
+/// Print which fonts the text of an SVG file is set in, which font families are
+/// missing and which characters lack glyphs.
+fn fonts_required(input: &Path) -> Result<(), String> {
+    let svg = std::fs::read_to_string(input).map_err(|_| "Failed to load SVG file")?;
+
+    let mut fontdb = fontdb::Database::new();
+    fontdb.load_system_fonts();
+
+    let usages = svg2pdf::fonts_used(&svg, &fontdb).map_err(|err| err.to_string())?;
+    if usages.is_empty() {
+        println!("The file contains no text.");
+        return Ok(());
+    }
+
+    let mut missing: Vec<&str> = vec![];
+    for usage in &usages {
+        for name in &usage.unavailable {
+            if !missing.contains(&name.as_str()) {
+                missing.push(name);
+            }
+        }
+
+        println!(
+            "{} (weight {}, {:?}, {:?})",
+            usage.families.join(", "),
+            usage.weight,
+            usage.style,
+            usage.stretch,
+        );
+
+        match usage.face.and_then(|id| fontdb.face(id)) {
+            Some(face) => println!("  uses {} ({})", face.family, face.post_script_name),
+            None => {
+                println!("  no matching font");
+                continue;
+            }
+        }
+
+        if !usage.missing.is_empty() {
+            println!(
+                "  lacks glyphs for {}",
+                usage.missing.iter().collect::<String>()
+            );
+        }
+    }
+
+    if !missing.is_empty() {
+        println!("Missing font families: {}", missing.join(", "));
+    }
+
+    Ok(())
+}
+
+fn print_warning(msg: &str) -> io::Result<()> {
+    let mut w = StandardStream::stderr(ColorChoice::Always);
+
+    let mut color = ColorSpec::new();
+    color.set_fg(Some(termcolor::Color::Yellow));
+    color.set_bold(true);
+    w.set_color(&color)?;
+    write!(w, "warning")?;
+
+    w.reset()?;
+    writeln!(w, ": {msg}.")
+}
+
 fn print_error(msg: &str) -> io::Result<()> {
     let mut w = StandardStream::stderr(ColorChoice::Always);
 
@@ -66,3 +527,67 @@ fn print_error(msg: &str) -> io::Result<()> {
     w.reset()?;
     writeln!(w, ": {msg}.")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_font() {
+        let mut options = usvg::Options::default();
+        options.fontdb.load_font_file("tests/square.ttf").unwrap();
+        set_default_font(&mut options, Path::new("tests/square.ttf")).unwrap();
+        assert_eq!(options.font_family, "Square");
+        assert_eq!(options.fontdb.len(), 2);
+
+        // Text in a missing family falls back to the default font.
+        let src = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <text x="10" y="90" font-family="Missing" font-size="100">A</text>
+        </svg>"#;
+        let converter =
+            svg2pdf::Converter::with_usvg_options(options, svg2pdf::Options {
+                compress: false,
+                ..svg2pdf::Options::default()
+            });
+        let pdf = converter.convert(src).unwrap();
+        assert!(String::from_utf8_lossy(&pdf).contains("20 10 m\n70 10 l\n70 80 l"));
+
+        let mut options = usvg::Options::default();
+        assert!(set_default_font(&mut options, Path::new("tests/missing.ttf")).is_err());
+        assert!(set_default_font(&mut options, Path::new("tests/group.svg")).is_err());
+        assert_eq!(options.font_family, usvg::Options::default().font_family);
+    }
+
+    #[test]
+    fn layers() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg"
+                xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape">
+            <rect id="background" width="10" height="10"/>
+            <g inkscape:groupmode="layer" inkscape:label="Layer 1"><circle r="1"/></g>
+            <g id="plain"/>
+            <g inkscape:groupmode="layer" inkscape:label="Layer 1"><circle r="2"/></g>
+        </svg>"#;
+
+        let layers = split_layers(svg).unwrap();
+        let names: Vec<_> = layers.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["Layer_1", "Layer_1-2"]);
+
+        // All other top-level graphics are hidden, but kept for references.
+        let (_, first) = &layers[0];
+        assert!(first.contains(r#"<g display="none"><rect id="background""#));
+        assert!(first.contains(r#"<g display="none"><g id="plain"/></g>"#));
+        assert!(first.contains(r#"inkscape:label="Layer 1"><circle r="1"/></g>"#));
+        assert_eq!(first.matches(r#"<g display="none">"#).count(), 3);
+
+        // Without Inkscape layers, all top-level groups count.
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg">
+            <g id="a b"/><rect/><g/>
+        </svg>"#;
+        let names: Vec<_> =
+            split_layers(svg).unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["a_b", "layer2"]);
+
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg"><rect/></svg>"#;
+        assert!(split_layers(svg).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! Read the batch conversions of the CLI from a JSON manifest.
+//!
+//! A manifest is an array of jobs, or an object with a `jobs` array. Each job
+//! is an object with an `input` path and optionally an `output` path, which
+//! defaults to the input path with a `.pdf` extension, and options that
+//! override those given on the command line:
+//!
+//! ```json
+//! [
+//!   { "input": "logo.svg", "dpi": 96 },
+//!   { "input": "map.svg", "output": "out/map.pdf", "width": 595, "height": 842,
+//!     "css": "print.css", "provenance": true, "source_hash": "9f86d0..." }
+//! ]
+//! ```
+//!
+//! Relative paths are resolved against the directory of the manifest.
+
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+/// A conversion described by a manifest or the command line.
+#[derive(Debug, Default)]
+pub struct Job {
+    /// Path to read the SVG file from.
+    pub input: PathBuf,
+    /// Path to write the PDF file to.
+    pub output: Option<PathBuf>,
+    /// The number of SVG pixels per PDF point.
+    pub dpi: Option<f64>,
+    /// The width and height of the page in points.
+    pub page_size: Option<(f32, f32)>,
+    /// Path to a CSS style sheet to apply to the SVG file.
+    pub css: Option<PathBuf>,
+    /// Whether to convert strokes into filled outlines.
+    pub outline_strokes: Option<bool>,
+    /// Whether to cut clipped paths to their clip region.
+    pub flatten_clip_paths: Option<bool>,
+    /// Whether to record the input file and options in the XMP metadata.
+    pub provenance: Option<bool>,
+    /// A digest of the input file to record with the provenance.
+    pub source_hash: Option<String>,
+}
+
+/// Read the jobs of a manifest file.
+pub fn read(path: &Path) -> Result<Vec<Job>, String> {
+    let src = std::fs::read_to_string(path).map_err(|_| "Failed to load manifest")?;
+    let value: Value = serde_json::from_str(&src)
+        .map_err(|err| format!("Failed to parse manifest: {err}"))?;
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let jobs = match value {
+        Value::Array(jobs) => jobs,
+        Value::Object(mut fields) => match fields.remove("jobs") {
+            Some(Value::Array(jobs)) => jobs,
+            Some(_) => return Err("Manifest jobs must be an array".into()),
+            None => return Err("Manifest has no jobs".into()),
+        },
+        _ => return Err("Manifest must be an array or an object".into()),
+    };
+
+    jobs.into_iter()
+        .enumerate()
+        .map(|(i, job)| {
+            job_from_value(job, dir)
+                .map_err(|err| format!("Job {} in manifest: {err}", i + 1))
+        })
+        .collect()
+}
+
+/// Read a job from its JSON object.
+fn job_from_value(value: Value, dir: &Path) -> Result<Job, String> {
+    let fields: Map<String, Value> = match value {
+        Value::Object(fields) => fields,
+        _ => return Err("expected an object".into()),
+    };
+
+    let mut job = Job::default();
+    let mut input = None;
+    let (mut width, mut height) = (None, None);
+
+    for (key, value) in fields {
+        match key.as_str() {
+            "input" => input = Some(dir.join(string(value, &key)?)),
+            "output" => job.output = Some(dir.join(string(value, &key)?)),
+            "dpi" => job.dpi = Some(number(value, &key)?),
+            "width" => width = Some(number(value, &key)? as f32),
+            "height" => height = Some(number(value, &key)? as f32),
+            "css" => job.css = Some(dir.join(string(value, &key)?)),
+            "outline_strokes" => job.outline_strokes = Some(bool(value, &key)?),
+            "flatten_clip_paths" => job.flatten_clip_paths = Some(bool(value, &key)?),
+            "provenance" => job.provenance = Some(bool(value, &key)?),
+            "source_hash" => job.source_hash = Some(string(value, &key)?),
+            _ => return Err(format!("unknown option '{key}'")),
+        }
+    }
+
+    job.input = input.ok_or("no input path was given")?;
+    if job.output.is_none() {
+        job.output = Some(job.input.with_extension("pdf"));
+    }
+
+    job.page_size = match (width, height) {
+        (Some(width), Some(height)) => Some((width, height)),
+        (None, None) => None,
+        _ => return Err("width and height must be given together".into()),
+    };
+
+    Ok(job)
+}
+
+fn string(value: Value, key: &str) -> Result<String, String> {
+    match value {
+        Value::String(string) => Ok(string),
+        _ => Err(format!("'{key}' must be a string")),
+    }
+}
+
+fn number(value: Value, key: &str) -> Result<f64, String> {
+    match value.as_f64() {
+        Some(number) => Ok(number),
+        None => Err(format!("'{key}' must be a number")),
+    }
+}
+
+fn bool(value: Value, key: &str) -> Result<bool, String> {
+    match value {
+        Value::Bool(value) => Ok(value),
+        _ => Err(format!("'{key}' must be true or false")),
+    }
+}
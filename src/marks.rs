@@ -0,0 +1,198 @@
+//! Add bleed and crop and registration marks to pages for print deliverables.
+
+use pdf_writer::types::ColorSpaceOperand;
+use pdf_writer::writers::ColorSpace;
+use pdf_writer::{Content, Filter, Finish, Name, PdfWriter, Rect, Ref};
+
+use crate::Context;
+
+/// The length of crop marks in points.
+const MARK_LENGTH: f32 = 18.0;
+
+/// The line width of all marks in points.
+const LINE_WIDTH: f32 = 0.25;
+
+/// The radius of the circle of registration targets in points.
+const TARGET_RADIUS: f32 = 4.0;
+
+/// The registration color, which is printed on all separations.
+const ALL: Name = Name(b"All");
+
+/// The boxes of a page with bleed and marks.
+pub struct PrintBoxes {
+    /// The page including the marks.
+    pub media: Rect,
+    /// The page including the bleed.
+    pub bleed: Rect,
+    /// The page as it is going to be trimmed.
+    pub trim: Rect,
+}
+
+/// Surround the content of a page of the given size with bleed and marks
+/// outside of it. All lengths in points are divided by the page's user space
+/// `unit`.
+///
+/// The content is moved so that the page including its marks starts at the
+/// origin and clipped to the bleed. The marks are written as a Form XObject
+/// that is registered with the current frame of the context.
+pub fn surround(
+    content: &[u8],
+    size: (f32, f32),
+    bleed: f32,
+    unit: f32,
+    writer: &mut PdfWriter,
+    ctx: &mut Context,
+) -> (Vec<u8>, PrintBoxes) {
+    let margin = margin(bleed, unit);
+    let bleed = bleed / unit;
+    let (width, height) = size;
+
+    let boxes = PrintBoxes {
+        media: Rect::new(0.0, 0.0, width + 2.0 * margin, height + 2.0 * margin),
+        bleed: Rect::new(
+            margin - bleed,
+            margin - bleed,
+            margin + width + bleed,
+            margin + height + bleed,
+        ),
+        trim: Rect::new(margin, margin, margin + width, margin + height),
+    };
+
+    let marks_ref = write_marks(&boxes, unit, writer, ctx);
+    let name = ctx.alloc_xobject("");
+    ctx.pending_xobjects.push((name.clone(), marks_ref));
+
+    let mut prefix = Content::new();
+    prefix.save_state();
+    prefix.rect(
+        boxes.bleed.x1,
+        boxes.bleed.y1,
+        boxes.bleed.x2 - boxes.bleed.x1,
+        boxes.bleed.y2 - boxes.bleed.y1,
+    );
+    prefix.clip_nonzero();
+    prefix.end_path();
+    prefix.transform([1.0, 0.0, 0.0, 1.0, margin, margin]);
+
+    // On tagged pages, the marks are not part of the document's content.
+    let mut suffix = Content::new();
+    suffix.restore_state();
+    if ctx.tags.is_some() {
+        suffix.begin_marked_content(Name(b"Artifact"));
+        suffix.x_object(name.as_name());
+        suffix.end_marked_content();
+    } else {
+        suffix.x_object(name.as_name());
+    }
+
+    // Content streams do not end with a newline, so one is needed to separate
+    // their operators.
+    let mut out = prefix.finish();
+    out.push(b'\n');
+    out.extend_from_slice(content);
+    out.push(b'\n');
+    out.extend(suffix.finish());
+    (out, boxes)
+}
+
+/// The distance between the trim box and the edge of the page with the given
+/// bleed, divided by the page's user space `unit`.
+pub fn margin(bleed: f32, unit: f32) -> f32 {
+    (bleed + MARK_LENGTH) / unit
+}
+
+/// Write a Form XObject with crop marks at the corners of the trim box and
+/// registration targets at the center of its edges. Returns its reference.
+fn write_marks(
+    boxes: &PrintBoxes,
+    unit: f32,
+    writer: &mut PdfWriter,
+    ctx: &mut Context,
+) -> Ref {
+    let trim = boxes.trim;
+    let offset = trim.x1 - boxes.bleed.x1;
+    let length = MARK_LENGTH / unit;
+
+    let mut content = Content::new();
+    content.set_stroke_color_space(ColorSpaceOperand::Named(ALL));
+    content.set_stroke_color([1.0]);
+    content.set_line_width(LINE_WIDTH / unit);
+
+    // Crop marks extend the edges of the trim box, starting at the bleed.
+    for x in [trim.x1, trim.x2] {
+        content.move_to(x, trim.y1 - offset);
+        content.line_to(x, trim.y1 - offset - length);
+        content.move_to(x, trim.y2 + offset);
+        content.line_to(x, trim.y2 + offset + length);
+    }
+
+    for y in [trim.y1, trim.y2] {
+        content.move_to(trim.x1 - offset, y);
+        content.line_to(trim.x1 - offset - length, y);
+        content.move_to(trim.x2 + offset, y);
+        content.line_to(trim.x2 + offset + length, y);
+    }
+
+    content.stroke();
+
+    // Registration targets: a circle with a cross hair.
+    let center_x = (trim.x1 + trim.x2) / 2.0;
+    let center_y = (trim.y1 + trim.y2) / 2.0;
+    let distance = offset + length / 2.0;
+    let radius = TARGET_RADIUS / unit;
+    for (x, y) in [
+        (center_x, trim.y1 - distance),
+        (center_x, trim.y2 + distance),
+        (trim.x1 - distance, center_y),
+        (trim.x2 + distance, center_y),
+    ] {
+        circle(&mut content, x, y, radius);
+        content.move_to(x - 1.5 * radius, y);
+        content.line_to(x + 1.5 * radius, y);
+        content.move_to(x, y - 1.5 * radius);
+        content.line_to(x, y + 1.5 * radius);
+        content.stroke();
+    }
+
+    let content = content.finish();
+    let data = ctx.finish_content(content);
+
+    let form_ref = ctx.alloc_ref();
+    let tint_ref = ctx.alloc_ref();
+    ctx.xobjects += 1;
+
+    let mut form = writer.form_xobject(form_ref, &data);
+    form.bbox(boxes.media);
+    if ctx.compress {
+        form.filter(Filter::FlateDecode);
+    }
+
+    form.resources()
+        .color_spaces()
+        .insert(ALL)
+        .start::<ColorSpace>()
+        .separation(ALL, Name(b"DeviceCMYK"), tint_ref);
+    form.finish();
+
+    writer
+        .exponential_function(tint_ref)
+        .domain([0.0, 1.0])
+        .c0([0.0; 4])
+        .c1([1.0; 4])
+        .n(1.0);
+
+    form_ref
+}
+
+/// Add a circle to the current path.
+fn circle(content: &mut Content, x: f32, y: f32, r: f32) {
+    // The distance of the control points from the start and end points for
+    // a bezier curve that approximates a quarter circle.
+    let k = 0.5523 * r;
+    content.move_to(x + r, y);
+    content.cubic_to(x + r, y + k, x + k, y + r, x, y + r);
+    content.cubic_to(x - k, y + r, x - r, y + k, x - r, y);
+    content.cubic_to(x - r, y - k, x - k, y - r, x, y - r);
+    content.cubic_to(x + k, y - r, x + r, y - k, x + r, y);
+    content.close_path();
+}
@@ -0,0 +1,438 @@
+//! Convert strokes into filled outlines.
+//!
+//! Some RIPs and cutting plotters handle fills more reliably than strokes with
+//! dashes, joins and caps. The outline is computed in the user space of the
+//! path, so that it is exact under any transform, and consists of line
+//! segments that approximate curves and round joins within a tolerance.
+
+use std::f64::consts::PI;
+
+use usvg::{LineCap, LineJoin, PathData, PathSegment, Stroke};
+
+/// The maximum deviation of flattened curves from the exact shape in points.
+pub(crate) const TOLERANCE: f64 = 0.05;
+
+/// A point or vector.
+pub(crate) type Point = (f64, f64);
+
+/// Compute the outline of a stroke along a path, to be filled with the
+/// nonzero rule. The `scale` converts lengths in the user space of the path
+/// to points.
+pub(crate) fn outline(data: &PathData, stroke: &Stroke, scale: f64) -> PathData {
+    let tolerance = TOLERANCE / scale.max(f64::EPSILON);
+    let width = stroke.width.value();
+    let mut out = PathData::default();
+
+    for (points, closed) in flatten(data, tolerance) {
+        let lines = match &stroke.dasharray {
+            Some(pattern) => dash(&points, closed, pattern, stroke.dashoffset as f64),
+            None => vec![(points, closed)],
+        };
+
+        for (points, closed) in lines {
+            let outliner = Outliner { stroke, hw: width / 2.0, tolerance };
+            outliner.outline(&points, closed, &mut out);
+        }
+    }
+
+    out
+}
+
+/// Split a path into the dashes of a stroke, to be stroked without a dash
+/// pattern. Like the outline, the dashes are computed in the user space of the
+/// path, with curves flattened within the tolerance. The `scale` converts
+/// lengths in the user space of the path to points.
+pub(crate) fn dashes(data: &PathData, stroke: &Stroke, scale: f64) -> PathData {
+    let tolerance = TOLERANCE / scale.max(f64::EPSILON);
+    let pattern = stroke.dasharray.as_deref().unwrap_or_default();
+    let mut out = PathData::default();
+
+    for (points, closed) in flatten(data, tolerance) {
+        for (points, closed) in dash(&points, closed, pattern, stroke.dashoffset as f64) {
+            let mut points = points.iter();
+            if let Some(&(x, y)) = points.next() {
+                out.push_move_to(x, y);
+
+                // A dash of zero length still gets its caps.
+                if points.len() == 0 {
+                    out.push_line_to(x, y);
+                }
+            }
+
+            for &(x, y) in points {
+                out.push_line_to(x, y);
+            }
+
+            if closed {
+                out.push_close_path();
+            }
+        }
+    }
+
+    out
+}
+
+/// Split a path into polylines, along with whether they are closed.
+pub(crate) fn flatten(data: &PathData, tolerance: f64) -> Vec<(Vec<Point>, bool)> {
+    let mut lines = vec![];
+    let mut points: Vec<Point> = vec![];
+
+    // Whether the current subpath has segments. A subpath whose segments all
+    // have zero length still gets caps, unlike a lone move.
+    let mut drawn = false;
+    let mut finish = |points: &mut Vec<Point>, drawn: &mut bool, closed| {
+        let points = std::mem::take(points);
+        if std::mem::take(drawn) {
+            let closed = closed && points.len() > 1;
+            lines.push((points, closed));
+        }
+    };
+
+    for &segment in &data.0 {
+        if !matches!(segment, PathSegment::MoveTo { .. }) {
+            drawn = true;
+        }
+
+        match segment {
+            PathSegment::MoveTo { x, y } => {
+                finish(&mut points, &mut drawn, false);
+                points.push((x, y));
+            }
+            PathSegment::LineTo { x, y } => push(&mut points, (x, y)),
+            PathSegment::CurveTo { x1, y1, x2, y2, x, y } => {
+                let p0 = points.last().copied().unwrap_or((x1, y1));
+                let (p1, p2, p3) = ((x1, y1), (x2, y2), (x, y));
+
+                // The deviation of a cubic from its chords is bounded by 3/4
+                // of its second differences divided by the squared number of
+                // chords.
+                let dd = |a: Point, b: Point, c: Point| {
+                    length((a.0 - 2.0 * b.0 + c.0, a.1 - 2.0 * b.1 + c.1))
+                };
+
+                let dd = dd(p0, p1, p2).max(dd(p1, p2, p3));
+                let n = (0.75 * dd / tolerance).sqrt().ceil().clamp(1.0, 1000.0);
+                for i in 1 ..= n as usize {
+                    let t = i as f64 / n;
+                    let mt = 1.0 - t;
+                    let a = mt * mt * mt;
+                    let b = 3.0 * mt * mt * t;
+                    let c = 3.0 * mt * t * t;
+                    let d = t * t * t;
+                    push(
+                        &mut points,
+                        (
+                            a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0,
+                            a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1,
+                        ),
+                    );
+                }
+            }
+            PathSegment::ClosePath => {
+                let start = points.first().copied();
+                if let Some(start) = start {
+                    if points.len() > 1 && points.last() == Some(&start) {
+                        points.pop();
+                    }
+
+                    finish(&mut points, &mut drawn, true);
+
+                    // A segment after closing starts at the same point.
+                    points.push(start);
+                }
+            }
+        }
+    }
+
+    finish(&mut points, &mut drawn, false);
+    lines
+}
+
+/// Add a point to a polyline unless it coincides with the previous one.
+fn push(points: &mut Vec<Point>, p: Point) {
+    if points.last().is_none_or(|&last| length(sub(p, last)) > 1e-9) {
+        points.push(p);
+    }
+}
+
+/// Split a polyline into the dashes of a pattern of alternating dash and gap
+/// lengths.
+fn dash(
+    points: &[Point],
+    closed: bool,
+    pattern: &[f64],
+    offset: f64,
+) -> Vec<(Vec<Point>, bool)> {
+    let total: f64 = pattern.iter().sum();
+    if total <= 0.0 || points.len() < 2 {
+        return vec![(points.to_vec(), closed)];
+    }
+
+    // Find the position in the pattern at the start of the line.
+    let mut index = 0;
+    let mut left = pattern[0];
+    let mut skip = offset.rem_euclid(total);
+    while skip >= left {
+        skip -= left;
+        index = (index + 1) % pattern.len();
+        left = pattern[index];
+    }
+    left -= skip;
+
+    let mut segments: Vec<(Point, Point)> =
+        points.windows(2).map(|w| (w[0], w[1])).collect();
+    if closed {
+        segments.push((points[points.len() - 1], points[0]));
+    }
+
+    let starts_on = index % 2 == 0;
+    let mut dashes: Vec<Vec<Point>> = vec![];
+    let mut current: Option<Vec<Point>> = starts_on.then(|| vec![points[0]]);
+
+    for (a, b) in segments {
+        let len = length(sub(b, a));
+        let mut pos = 0.0;
+        while len - pos > left {
+            pos += left;
+            let p = lerp(a, b, pos / len);
+            match current.take() {
+                Some(mut dash) => {
+                    push(&mut dash, p);
+                    dashes.push(dash);
+                }
+                None => current = Some(vec![p]),
+            }
+
+            index = (index + 1) % pattern.len();
+            left = pattern[index];
+        }
+
+        left -= len - pos;
+        if let Some(dash) = &mut current {
+            push(dash, b);
+        }
+    }
+
+    if let Some(dash) = current {
+        // A dash that runs across the start of a closed line continues with
+        // the first dash.
+        if closed && starts_on && !dashes.is_empty() {
+            let mut first = dashes.remove(0);
+            let mut joined = dash;
+            joined.extend(first.drain(1 ..));
+            dashes.insert(0, joined);
+        } else {
+            dashes.push(dash);
+        }
+    }
+
+    dashes.into_iter().map(|dash| (dash, false)).collect()
+}
+
+/// Writes the outlines of polylines.
+struct Outliner<'a> {
+    stroke: &'a Stroke,
+    /// Half the stroke width.
+    hw: f64,
+    tolerance: f64,
+}
+
+impl Outliner<'_> {
+    /// Write the outline of a polyline to the path.
+    fn outline(&self, points: &[Point], closed: bool, out: &mut PathData) {
+        if points.len() == 1 {
+            self.dot(points[0], out);
+            return;
+        }
+
+        if closed {
+            // The two sides of a closed line are separate rings that wind in
+            // opposite directions.
+            write_ring(&self.side(points, true, 1.0), out);
+            let mut inner = self.side(points, true, -1.0);
+            inner.reverse();
+            write_ring(&inner, out);
+            return;
+        }
+
+        let n = points.len();
+        let mut ring = self.side(points, false, 1.0);
+        let d = unit(sub(points[n - 1], points[n - 2]));
+        self.cap(points[n - 1], d, &mut ring);
+
+        let mut right = self.side(points, false, -1.0);
+        right.reverse();
+        ring.extend(right);
+
+        let d = unit(sub(points[0], points[1]));
+        self.cap(points[0], d, &mut ring);
+        write_ring(&ring, out);
+    }
+
+    /// The points along one side of a polyline, offset by half the stroke
+    /// width to the left for a positive `side` and to the right for a
+    /// negative one.
+    fn side(&self, points: &[Point], closed: bool, side: f64) -> Vec<Point> {
+        let n = points.len();
+        let normal = |i: usize| {
+            let (dx, dy) = unit(sub(points[(i + 1) % n], points[i]));
+            (-dy * self.hw * side, dx * self.hw * side)
+        };
+
+        let mut out = vec![];
+        let segments = if closed { n } else { n - 1 };
+        for (i, &p) in points.iter().enumerate() {
+            let has_prev = closed || i > 0;
+            let has_next = closed || i + 1 < n;
+            let prev = has_prev.then(|| normal((i + segments - 1) % segments));
+            let next = has_next.then(|| normal(i % segments));
+
+            match (prev, next) {
+                (Some(a), Some(b)) => self.join(p, a, b, side, &mut out),
+                (Some(a), None) => out.push(add(p, a)),
+                (None, Some(b)) => out.push(add(p, b)),
+                (None, None) => {}
+            }
+        }
+
+        out
+    }
+
+    /// Connect the offset points of two segments at a vertex, given the
+    /// offset vectors of the segments on this side.
+    fn join(&self, p: Point, a: Point, b: Point, side: f64, out: &mut Vec<Point>) {
+        let cross = a.0 * b.1 - a.1 * b.0;
+        let dot = a.0 * b.0 + a.1 * b.1;
+        let straight = cross.abs() <= 1e-12 * self.hw * self.hw;
+        out.push(add(p, a));
+
+        // The segments continue in the same direction.
+        if straight && dot > 0.0 {
+            return;
+        }
+
+        // On the inner side of the turn, the offset lines overlap. Passing
+        // through the vertex keeps the winding of the outline consistent.
+        if !straight && cross * side > 0.0 {
+            out.push(p);
+            out.push(add(p, b));
+            return;
+        }
+
+        match self.stroke.linejoin {
+            LineJoin::Miter => {
+                // The miter tip lies along the sum of the offsets, scaled by
+                // the inverse cosine of half the angle between them.
+                let cos = dot / (self.hw * self.hw);
+                if cos > -1.0 + 1e-9 {
+                    let m = scale(add(a, b), 1.0 / (1.0 + cos));
+                    if length(m) / self.hw <= self.stroke.miterlimit.value() {
+                        out.push(add(p, m));
+                    }
+                }
+            }
+            LineJoin::Round => {
+                // When the line turns back on itself, both sides go around
+                // its end.
+                let sweep = if straight { -side * PI } else { cross.atan2(dot) };
+                self.arc(p, a, sweep, out);
+            }
+            LineJoin::Bevel => {}
+        }
+
+        out.push(add(p, b));
+    }
+
+    /// Add the cap at the end of a line that points in the direction `d`,
+    /// going from its left to its right side.
+    fn cap(&self, p: Point, d: Point, out: &mut Vec<Point>) {
+        let n = (-d.1 * self.hw, d.0 * self.hw);
+        match self.stroke.linecap {
+            LineCap::Butt => {}
+            LineCap::Round => self.arc(p, n, -PI, out),
+            LineCap::Square => {
+                let e = scale(d, self.hw);
+                out.push(add(add(p, n), e));
+                out.push(add(sub(p, n), e));
+            }
+        }
+    }
+
+    /// Add the inner points of a circular arc around `center` that starts at
+    /// `center + from` and sweeps by the given angle.
+    fn arc(&self, center: Point, from: Point, sweep: f64, out: &mut Vec<Point>) {
+        let ratio = (1.0 - self.tolerance / self.hw).clamp(-1.0, 1.0);
+        let step = (2.0 * ratio.acos()).max(1e-3);
+        let n = (sweep.abs() / step).ceil().max(1.0) as usize;
+        for i in 1 .. n {
+            let angle = sweep * i as f64 / n as f64;
+            let (sin, cos) = angle.sin_cos();
+            out.push(add(
+                center,
+                (from.0 * cos - from.1 * sin, from.0 * sin + from.1 * cos),
+            ));
+        }
+    }
+
+    /// Write the caps of a zero-length subpath.
+    fn dot(&self, p: Point, out: &mut PathData) {
+        let hw = self.hw;
+        let ring = match self.stroke.linecap {
+            LineCap::Butt => return,
+            LineCap::Round => {
+                let mut ring = vec![(p.0 + hw, p.1)];
+                self.arc(p, (hw, 0.0), 2.0 * PI, &mut ring);
+                ring
+            }
+            LineCap::Square => vec![
+                (p.0 - hw, p.1 - hw),
+                (p.0 + hw, p.1 - hw),
+                (p.0 + hw, p.1 + hw),
+                (p.0 - hw, p.1 + hw),
+            ],
+        };
+
+        write_ring(&ring, out);
+    }
+}
+
+/// Write a closed polygon to the path.
+fn write_ring(ring: &[Point], out: &mut PathData) {
+    let mut points = ring.iter();
+    if let Some(&(x, y)) = points.next() {
+        out.push_move_to(x, y);
+        for &(x, y) in points {
+            out.push_line_to(x, y);
+        }
+        out.push_close_path();
+    }
+}
+
+fn add(a: Point, b: Point) -> Point {
+    (a.0 + b.0, a.1 + b.1)
+}
+
+pub(crate) fn sub(a: Point, b: Point) -> Point {
+    (a.0 - b.0, a.1 - b.1)
+}
+
+fn scale(a: Point, s: f64) -> Point {
+    (a.0 * s, a.1 * s)
+}
+
+pub(crate) fn length(a: Point) -> f64 {
+    a.0.hypot(a.1)
+}
+
+fn unit(a: Point) -> Point {
+    let len = length(a);
+    if len > 0.0 {
+        scale(a, 1.0 / len)
+    } else {
+        (1.0, 0.0)
+    }
+}
+
+pub(crate) fn lerp(a: Point, b: Point, t: f64) -> Point {
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+}
@@ -0,0 +1,191 @@
+//! Bundle multiple SVG files into a PDF portfolio.
+
+use pdf_writer::{
+    Content, Date, Filter, Finish, Name, PdfWriter, Rect, Ref, Str, TextStr,
+};
+use usvg::Tree;
+
+use crate::{convert_tree, deflate, write_document_info, Options};
+
+/// An SVG file to be included in a [portfolio](convert_portfolio).
+#[derive(Clone)]
+pub struct PortfolioFile<'a> {
+    /// The name of the file, e.g. `arrow.svg`. The embedded PDF is named after
+    /// it with the extension replaced by `.pdf`.
+    pub name: String,
+    /// The parsed SVG file.
+    pub tree: &'a Tree,
+    /// When the SVG file was last modified, if known.
+    pub modified: Option<Date>,
+}
+
+/// Convert multiple [`usvg` trees](Tree) into a PDF portfolio.
+///
+/// Each file is converted to a standalone PDF with the given options, which is
+/// then embedded into the portfolio. Viewers with support for portfolios list
+/// the embedded PDFs with their name, size and modification date, so that
+/// large icon or figure sets can be delivered and reviewed as a single file.
+/// Other viewers show a cover page and may still offer the files as
+/// attachments.
+///
+/// ## Example
+/// ```rust
+/// use svg2pdf::PortfolioFile;
+///
+/// let opt = usvg::Options::default();
+/// let mut files = vec![];
+/// for name in ["example.svg", "group.svg"] {
+///     let svg = std::fs::read_to_string(format!("tests/{}", name)).unwrap();
+///     files.push((name, usvg::Tree::from_str(&svg, &opt.to_ref()).unwrap()));
+/// }
+///
+/// let files: Vec<_> = files
+///     .iter()
+///     .map(|(name, tree)| PortfolioFile {
+///         name: name.to_string(),
+///         tree,
+///         modified: None,
+///     })
+///     .collect();
+///
+/// let pdf = svg2pdf::convert_portfolio(&files, svg2pdf::Options::default());
+/// # assert!(pdf.starts_with(b"%PDF"));
+/// ```
+pub fn convert_portfolio(files: &[PortfolioFile], options: Options) -> Vec<u8> {
+    let mut writer = PdfWriter::new();
+
+    // Portfolios were introduced with PDF 1.7.
+    writer.set_version(1, 7);
+
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let page_id = Ref::new(3);
+    let content_id = Ref::new(4);
+    let font_id = Ref::new(5);
+    let mut next_id = 6;
+
+    // The embedded files are keyed by name in a name tree, whose keys must be
+    // unique and sorted.
+    let mut entries: Vec<(String, Ref)> = vec![];
+    for file in files {
+        let stem = file.name.rsplit_once('.').map_or(&*file.name, |(stem, _)| stem);
+        let mut name = format!("{}.pdf", stem);
+        let mut i = 2;
+        while entries.iter().any(|(other, _)| *other == name) {
+            name = format!("{} ({}).pdf", stem, i);
+            i += 1;
+        }
+
+        let pdf = convert_tree(file.tree, options.clone());
+        let spec_id = Ref::new(next_id);
+        let file_id = Ref::new(next_id + 1);
+        next_id += 2;
+
+        let compressed = deflate(&pdf, &options);
+        let mut embedded =
+            writer.embedded_file(file_id, compressed.as_deref().unwrap_or(&pdf));
+        embedded.subtype(Name(b"application/pdf"));
+        if compressed.is_some() {
+            embedded.filter(Filter::FlateDecode);
+        }
+
+        let mut params = embedded.params();
+        params.size(pdf.len() as i32);
+        if let Some(date) = file.modified {
+            params.modification_date(date);
+        }
+
+        params.finish();
+        embedded.finish();
+
+        let mut spec = writer.file_spec(spec_id);
+        spec.path(Str(name.as_bytes()));
+        spec.unic_file(TextStr(&name));
+        spec.insert(Name(b"EF"))
+            .dict()
+            .pair(Name(b"F"), file_id)
+            .pair(Name(b"UF"), file_id);
+        spec.finish();
+
+        entries.push((name, spec_id));
+    }
+
+    // Open the first file by default.
+    let first = entries.first().map(|(name, _)| name.clone());
+    entries.sort_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+
+    let mut catalog = writer.catalog(catalog_id);
+    catalog.pages(page_tree_id);
+
+    let mut names = catalog.names();
+    let mut embedded_files = names.embedded_files();
+    let mut items = embedded_files.names();
+    for (name, spec_id) in &entries {
+        items.insert(Str(name.as_bytes()), *spec_id);
+    }
+
+    items.finish();
+    embedded_files.finish();
+    names.finish();
+
+    let mut collection = catalog.insert(Name(b"Collection")).dict();
+    collection.pair(Name(b"Type"), Name(b"Collection"));
+    collection.pair(Name(b"View"), Name(b"D"));
+    if let Some(first) = &first {
+        collection.pair(Name(b"D"), Str(first.as_bytes()));
+    }
+
+    let mut schema = collection.insert(Name(b"Schema")).dict();
+    schema.pair(Name(b"Type"), Name(b"CollectionSchema"));
+    let fields: [(&[u8], &str); 3] =
+        [(b"F", "Name"), (b"Size", "Size"), (b"ModDate", "Modified")];
+    for (i, (subtype, label)) in fields.into_iter().enumerate() {
+        schema
+            .insert(Name(subtype))
+            .dict()
+            .pair(Name(b"Type"), Name(b"CollectionField"))
+            .pair(Name(b"Subtype"), Name(subtype))
+            .pair(Name(b"N"), TextStr(label))
+            .pair(Name(b"O"), i as i32 + 1);
+    }
+
+    schema.finish();
+    collection.finish();
+    catalog.finish();
+
+    // A cover page for viewers that do not support portfolios.
+    writer.pages(page_tree_id).kids([page_id]).count(1);
+
+    let mut page = writer.page(page_id);
+    page.media_box(Rect::new(0.0, 0.0, 595.0, 842.0));
+    page.parent(page_tree_id);
+    page.contents(content_id);
+    page.resources().fonts().pair(Name(b"F1"), font_id);
+    page.finish();
+
+    writer.type1_font(font_id).base_font(Name(b"Helvetica"));
+
+    let message = match files.len() {
+        1 => "This PDF portfolio contains 1 file.".to_string(),
+        n => format!("This PDF portfolio contains {} files.", n),
+    };
+
+    let mut content = Content::new();
+    content.begin_text();
+    content.set_font(Name(b"F1"), 12.0);
+    content.next_line(72.0, 770.0);
+    content.show(Str(message.as_bytes()));
+    content.end_text();
+
+    let content = content.finish();
+    let compressed = deflate(&content, &options);
+    let mut stream = writer.stream(content_id, compressed.as_deref().unwrap_or(&content));
+    if compressed.is_some() {
+        stream.filter(Filter::FlateDecode);
+    }
+
+    stream.finish();
+
+    write_document_info(&mut writer, Ref::new(next_id), &options);
+    writer.finish()
+}
@@ -0,0 +1,64 @@
+//! Measure how long the phases of a conversion take.
+
+use std::fmt::{self, Display, Formatter};
+use std::ops::AddAssign;
+use std::time::Duration;
+
+/// The time spent in each phase of a conversion, as returned by
+/// [`Converter::convert_profiled`](crate::Converter::convert_profiled).
+///
+/// usvg shapes text and converts it into paths while parsing, so shaping is
+/// part of [`parse`](Self::parse). No fonts are embedded, so there is no time
+/// spent subsetting them. If the document is written multiple times to reach a
+/// [target size](crate::Options::target_size), the phases after parsing sum up
+/// all attempts.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Timings {
+    /// Preprocessing and parsing the SVG source, including text shaping.
+    pub parse: Duration,
+    /// Converting the nodes of the tree into content streams and resources.
+    pub render: Duration,
+    /// Decoding and encoding raster images.
+    pub images: Duration,
+    /// Compressing content streams.
+    pub compress: Duration,
+    /// Serializing the document.
+    pub write: Duration,
+}
+
+impl Timings {
+    /// The time spent in all phases together.
+    pub fn total(&self) -> Duration {
+        self.parse + self.render + self.images + self.compress + self.write
+    }
+}
+
+impl AddAssign for Timings {
+    fn add_assign(&mut self, other: Self) {
+        self.parse += other.parse;
+        self.render += other.render;
+        self.images += other.images;
+        self.compress += other.compress;
+        self.write += other.write;
+    }
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let phases = [
+            ("parsing and text shaping", self.parse),
+            ("rendering nodes", self.render),
+            ("encoding images", self.images),
+            ("compressing streams", self.compress),
+            ("serializing", self.write),
+            ("total", self.total()),
+        ];
+
+        for (name, duration) in phases {
+            let millis = duration.as_secs_f64() * 1000.0;
+            writeln!(f, "{:<26}{:>10.2} ms", name, millis)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+//! Record where a PDF came from in its XMP metadata.
+
+use crate::animate::escape;
+use crate::Options;
+
+/// The namespace of the custom XMP schema for provenance information.
+const NAMESPACE: &str = "https://github.com/typst/svg2pdf/ns/provenance/1.0/";
+
+/// Information about the source of a conversion, to be recorded in the XMP
+/// metadata of the output along with the svg2pdf version and the options.
+///
+/// This allows archived documents to be traced back to the parameters they
+/// were converted with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// The name of the source file, e.g. `figure.svg`.
+    pub source: Option<String>,
+    /// A digest of the source file, e.g. a hex-encoded SHA-256 hash. It is
+    /// recorded as given.
+    pub source_hash: Option<String>,
+}
+
+/// Write an XMP packet with the provenance of a conversion.
+pub(crate) fn xmp(provenance: &Provenance, options: &Options) -> String {
+    let options = Options { provenance: None, ..options.clone() };
+
+    let mut properties = vec![
+        ("Version", env!("CARGO_PKG_VERSION").to_string()),
+        ("Options", format!("{:?}", options)),
+    ];
+
+    if let Some(source) = &provenance.source {
+        properties.push(("Source", source.clone()));
+    }
+
+    if let Some(hash) = &provenance.source_hash {
+        properties.push(("SourceHash", hash.clone()));
+    }
+
+    let mut xmp = String::new();
+    xmp.push_str("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+    xmp.push_str("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n");
+    xmp.push_str("<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n");
+    xmp.push_str(&format!(
+        "<rdf:Description rdf:about=\"\" xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\" \
+         xmlns:svg2pdf=\"{}\">\n",
+        NAMESPACE,
+    ));
+
+    xmp.push_str("<pdf:Producer>svg2pdf</pdf:Producer>\n");
+    for (name, value) in properties {
+        xmp.push_str(&format!(
+            "<svg2pdf:{0}>{1}</svg2pdf:{0}>\n",
+            name,
+            escape(&value)
+        ));
+    }
+
+    xmp.push_str("</rdf:Description>\n</rdf:RDF>\n</x:xmpmeta>\n");
+    xmp.push_str("<?xpacket end=\"r\"?>");
+    xmp
+}
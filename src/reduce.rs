@@ -0,0 +1,214 @@
+//! Reduce the size of the output to meet a target size.
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use usvg::{ImageKind, NodeKind, Tree};
+
+/// A measure that trades quality for a smaller output, as taken to meet the
+/// [target size](crate::Options::target_size).
+///
+/// The measures that were taken are returned by
+/// [`convert_tree_sized`](crate::convert_tree_sized). They are displayed as a
+/// sentence fragment like "downsampled images to 50%".
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Reduction {
+    /// Raster images are downsampled to the given fraction of their width and
+    /// height.
+    Downsample(f32),
+    /// Raster images are recompressed as JPEG with the given quality. Alpha
+    /// channels are kept lossless.
+    Jpeg(u8),
+    /// Coordinates are rounded to the given number of decimal places.
+    Precision(u8),
+}
+
+impl Reduction {
+    /// Whether the measure only affects raster images.
+    fn is_image(self) -> bool {
+        matches!(self, Self::Downsample(_) | Self::Jpeg(_))
+    }
+}
+
+impl Display for Reduction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Downsample(scale) => {
+                write!(f, "downsampled images to {}%", scale * 100.0)
+            }
+            Self::Jpeg(quality) => {
+                write!(f, "recompressed images as JPEG with quality {}", quality)
+            }
+            Self::Precision(1) => write!(f, "rounded coordinates to 1 decimal place"),
+            Self::Precision(places) => {
+                write!(f, "rounded coordinates to {} decimal places", places)
+            }
+        }
+    }
+}
+
+/// The measures in the order in which they are tried, from the least to the
+/// most destructive. Each one is applied on top of the previous ones.
+const STEPS: &[Reduction] = &[
+    Reduction::Downsample(0.5),
+    Reduction::Jpeg(75),
+    Reduction::Precision(2),
+    Reduction::Downsample(0.25),
+    Reduction::Jpeg(50),
+    Reduction::Precision(1),
+];
+
+/// How raster images are reduced, independently of the size of the output.
+///
+/// Print shops and upload forms often limit the size of files, but embedded
+/// photos and scans frequently have a much higher resolution than needed at
+/// the size they are displayed with.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ImagePolicy {
+    /// The resolution in dots per inch that raster images should not exceed
+    /// at the size they are displayed with. Images with a higher resolution
+    /// are downsampled to it.
+    pub max_dpi: Option<f64>,
+    /// The quality between 1 and 100 with which raster images are
+    /// recompressed as JPEG. Alpha channels are kept lossless. This requires
+    /// the `jpeg` feature.
+    pub jpeg_quality: Option<u8>,
+}
+
+/// The measures that are currently in effect.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Reductions {
+    /// The fraction of their width and height raster images are downsampled
+    /// to.
+    pub image_scale: f32,
+    /// The quality with which raster images are recompressed as JPEG, if they
+    /// are.
+    pub jpeg_quality: Option<u8>,
+    /// The number of decimal places coordinates are rounded to, if they are.
+    pub precision: Option<u8>,
+}
+
+impl Reductions {
+    /// Put a measure into effect.
+    fn apply(&mut self, reduction: Reduction) {
+        match reduction {
+            Reduction::Downsample(scale) => self.image_scale = scale,
+            Reduction::Jpeg(quality) => self.jpeg_quality = Some(quality),
+            Reduction::Precision(places) => self.precision = Some(places),
+        }
+    }
+}
+
+impl Reductions {
+    /// The measures for a raster image with the given size in pixels that is
+    /// displayed with the given size in points, including those of the image
+    /// policy. The stricter measure wins if both apply.
+    #[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
+    pub fn for_image(
+        self,
+        policy: Option<&ImagePolicy>,
+        pixels: (u32, u32),
+        size: (f64, f64),
+    ) -> Self {
+        let mut reductions = self;
+        let policy = match policy {
+            Some(policy) => policy,
+            None => return reductions,
+        };
+
+        if let Some(max_dpi) = policy.max_dpi {
+            if size.0 > 0.0 && size.1 > 0.0 {
+                let dpi = (pixels.0 as f64 / size.0).max(pixels.1 as f64 / size.1) * 72.0;
+                if dpi > max_dpi {
+                    reductions.image_scale *= (max_dpi / dpi) as f32;
+                }
+            }
+        }
+
+        if let Some(quality) = policy.jpeg_quality {
+            reductions.jpeg_quality =
+                Some(reductions.jpeg_quality.map_or(quality, |other| other.min(quality)));
+        }
+
+        reductions
+    }
+}
+
+impl Default for Reductions {
+    fn default() -> Self {
+        Self {
+            image_scale: 1.0,
+            jpeg_quality: None,
+            precision: None,
+        }
+    }
+}
+
+/// Run a conversion with increasingly aggressive measures until its output is
+/// no larger than `target` bytes, there is nothing left to try or the `cancel`
+/// flag is set. The measures for raster images are only tried if `images` is
+/// true.
+///
+/// Returns the output along with the measures that were taken, which are also
+/// reported through the `log` crate.
+pub(crate) fn reduce<F>(
+    images: bool,
+    target: Option<usize>,
+    cancel: Option<&AtomicBool>,
+    mut convert: F,
+) -> (Vec<u8>, Vec<Reduction>)
+where
+    F: FnMut(&Reductions) -> Vec<u8>,
+{
+    let mut reductions = Reductions::default();
+    let mut applied = vec![];
+    let mut pdf = convert(&reductions);
+    let target = match target {
+        Some(target) => target,
+        None => return (pdf, applied),
+    };
+
+    // Each attempt converts the whole document again, so a cancelled
+    // conversion must not start another one.
+    let cancelled = || cancel.is_some_and(|flag| flag.load(Ordering::Relaxed));
+    for &step in STEPS {
+        if pdf.len() <= target || cancelled() {
+            return (pdf, applied);
+        }
+
+        if step.is_image() && !images {
+            continue;
+        }
+
+        #[cfg(not(feature = "jpeg"))]
+        if matches!(step, Reduction::Jpeg(_)) {
+            continue;
+        }
+
+        reductions.apply(step);
+        pdf = convert(&reductions);
+        applied.push(step);
+        log::info!("To meet the target size, {} ({} bytes).", step, pdf.len());
+    }
+
+    if pdf.len() > target && !cancelled() {
+        log::warn!(
+            "Could not reduce the output to {} bytes, it has {} bytes.",
+            target,
+            pdf.len(),
+        );
+    }
+
+    (pdf, applied)
+}
+
+/// Whether a tree or any SVG image nested in it contains raster images.
+pub(crate) fn has_raster_images(tree: &Tree) -> bool {
+    tree.root().descendants().any(|node| match *node.borrow() {
+        NodeKind::Image(ref image) => match &image.kind {
+            ImageKind::SVG(tree) => has_raster_images(tree),
+            _ => true,
+        },
+        _ => false,
+    })
+}
@@ -1,31 +1,61 @@
 //! Provide rendering capabilities for SVG's primitives.
 
-use miniz_oxide::deflate::compress_to_vec_zlib;
+use std::fmt::{self, Write};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+
 use pdf_writer::types::{
     ColorSpaceOperand, LineCapStyle, LineJoinStyle, PaintType, ProcSet, ShadingType,
     TilingType,
 };
-use pdf_writer::writers::Shading;
-use pdf_writer::{Content, Filter, Finish, Name, PdfWriter, Rect, Ref, Writer};
+use pdf_writer::writers::{Shading, StitchingFunction};
+use pdf_writer::{Content, Filter, Finish, Name, PdfWriter, Ref, Writer};
 use usvg::{
     Align, AspectRatio, FillRule, ImageKind, LineCap, LineJoin, Node, NodeExt, NodeKind,
-    Paint, PathSegment, Pattern, Transform, Units, ViewBox, Visibility,
+    Paint, PathSegment, Pattern, SpreadMethod, Transform, Tree, Units, ViewBox,
+    Visibility,
 };
 
+#[cfg(feature = "jpeg")]
+use image::codecs::jpeg::{JpegDecoder, JpegEncoder};
+#[cfg(feature = "png")]
+use image::codecs::png::PngDecoder;
 #[cfg(any(feature = "png", feature = "jpeg"))]
+use image::ImageDecoder;
+#[cfg(any(feature = "png", feature = "gif"))]
+use image::{Luma, Rgb, Rgba};
+#[cfg(any(feature = "png", feature = "gif"))]
+use pdf_writer::writers::ImageXObject;
+#[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
 use {
-    image::io::Reader as ImageReader,
-    image::{DynamicImage, ImageFormat, Luma, Rgb, Rgba},
-    pdf_writer::writers::ImageXObject,
+    image::imageops::FilterType,
+    image::io::{Limits, Reader as ImageReader},
+    image::{DynamicImage, ImageFormat},
+    pdf_writer::Rect,
 };
 
 use super::{
-    apply_clip_path, apply_mask, content_stream, form_xobject, Context, Options,
-    RgbColor, SRGB,
+    apply_clip_path, apply_mask, content_stream, form_xobject, render_children,
+    render_content, Context, KeyRecorder, Options, RgbColor, SRGB,
 };
-use crate::defer::{PendingGS, PendingGradient};
+use crate::clip::{clip, clip_regions, draw_rings};
+use crate::defer::{PendingGS, PendingGradient, ResourceName};
+use crate::outline::{dashes, outline};
+#[cfg(feature = "jpeg")]
+use crate::reduce::Reductions;
 use crate::scale::CoordToPdf;
-use crate::{convert_tree_into, deflate};
+use crate::write_tree_xobject;
+
+/// The color space family of images with an embedded ICC profile.
+#[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
+const ICC_BASED: Name = Name(b"ICCBased");
+
+/// The maximum number of clip paths of groups that are drawn directly into a
+/// content stream around an element. PDF 1.7 limits the nesting of `q`
+/// operators to 28 and paths, images and masks add a few levels of their own.
+const MAX_INLINE_CLIPS: usize = 16;
 
 /// Write the appropriate instructions for a node into the content stream.
 ///
@@ -50,7 +80,12 @@ impl Render for usvg::Path {
         content: &mut Content,
         ctx: &mut Context,
     ) {
-        if self.visibility != Visibility::Visible {
+        if self.fill.is_none() && self.stroke.is_none() {
+            return;
+        }
+
+        let hidden = self.visibility != Visibility::Visible;
+        if hidden && !ctx.begin_hidden(content) {
             return;
         }
 
@@ -59,57 +94,125 @@ impl Render for usvg::Path {
             .and_then(|b| b.to_rect())
             .unwrap_or_else(|| usvg::Rect::new(0.0, 0.0, 1.0, 1.0).unwrap());
 
-        let (fill_gradient, fill_g_alpha) =
-            get_gradient(self.fill.as_ref().map(|fill| &fill.paint), ctx);
-
-        let (stroke_gradient, stroke_g_alpha) =
-            get_gradient(self.stroke.as_ref().map(|stroke| &stroke.paint), ctx);
-
-        if fill_g_alpha.is_some() || stroke_g_alpha.is_some() {
-            render_path_partial(
-                self,
-                bbox,
-                true,
-                false,
-                fill_gradient,
-                None,
-                fill_g_alpha,
-                None,
-                writer,
-                content,
-                ctx,
-            );
-            render_path_partial(
-                self,
-                bbox,
-                false,
-                true,
-                None,
-                stroke_gradient,
-                None,
-                stroke_g_alpha,
-                writer,
-                content,
-                ctx,
-            );
-        } else {
-            render_path_partial(
-                self,
-                bbox,
-                true,
-                true,
-                fill_gradient,
-                stroke_gradient,
-                fill_g_alpha,
-                stroke_g_alpha,
-                writer,
-                content,
-                ctx,
-            )
+        let (scale, uniform) = ctx.c.length_scale(self.transform);
+        match &self.stroke {
+            Some(stroke) if ctx.outline_strokes => {
+                let outline = usvg::Path {
+                    fill: Some(usvg::Fill {
+                        paint: stroke.paint.clone(),
+                        opacity: stroke.opacity,
+                        rule: FillRule::NonZero,
+                    }),
+                    stroke: None,
+                    data: Rc::new(outline(&self.data, stroke, scale)),
+                    ..self.clone()
+                };
+
+                if self.fill.is_some() {
+                    let fill = usvg::Path { stroke: None, ..self.clone() };
+                    render_path(&fill, bbox, writer, content, ctx);
+                }
+
+                render_path(&outline, bbox, writer, content, ctx);
+            }
+            // The path is written in page coordinates and so is its dash
+            // pattern. If the transformation scales lengths differently
+            // depending on the direction, no pattern matches the dashes along
+            // all segments, so they are split off in user space instead.
+            Some(stroke) if stroke.dasharray.is_some() && !uniform => {
+                let dashed = usvg::Path {
+                    fill: None,
+                    stroke: Some(usvg::Stroke { dasharray: None, ..stroke.clone() }),
+                    data: Rc::new(dashes(&self.data, stroke, scale)),
+                    ..self.clone()
+                };
+
+                if self.fill.is_some() {
+                    let fill = usvg::Path { stroke: None, ..self.clone() };
+                    render_path(&fill, bbox, writer, content, ctx);
+                }
+
+                render_path(&dashed, bbox, writer, content, ctx);
+            }
+            _ => render_path(self, bbox, writer, content, ctx),
+        }
+
+        if hidden {
+            content.end_marked_content();
         }
     }
 }
 
+/// Write the fill and stroke of a path with the given bounding box.
+fn render_path(
+    path: &usvg::Path,
+    bbox: usvg::Rect,
+    writer: &mut PdfWriter,
+    content: &mut Content,
+    ctx: &mut Context,
+) {
+    if !ctx.clip_regions.is_empty() {
+        let rings = clip(&path.data, path.transform, &ctx.c, &ctx.clip_regions);
+        if rings.is_empty() {
+            return;
+        }
+
+        ctx.clipped_path = Some(rings);
+    }
+
+    let (fill_gradient, fill_g_alpha) =
+        get_gradient(path.fill.as_ref().map(|fill| &fill.paint), ctx);
+
+    let (stroke_gradient, stroke_g_alpha) =
+        get_gradient(path.stroke.as_ref().map(|stroke| &stroke.paint), ctx);
+
+    if fill_g_alpha.is_some() || stroke_g_alpha.is_some() {
+        render_path_partial(
+            path,
+            bbox,
+            true,
+            false,
+            fill_gradient,
+            None,
+            fill_g_alpha,
+            None,
+            writer,
+            content,
+            ctx,
+        );
+        render_path_partial(
+            path,
+            bbox,
+            false,
+            true,
+            None,
+            stroke_gradient,
+            None,
+            stroke_g_alpha,
+            writer,
+            content,
+            ctx,
+        );
+    } else {
+        render_path_partial(
+            path,
+            bbox,
+            true,
+            true,
+            fill_gradient,
+            stroke_gradient,
+            fill_g_alpha,
+            stroke_g_alpha,
+            writer,
+            content,
+            ctx,
+        )
+    }
+
+    ctx.clipped_path = None;
+}
+
+#[allow(clippy::too_many_arguments)]
 fn render_path_partial(
     path: &usvg::Path,
     bbox: usvg::Rect,
@@ -123,6 +226,14 @@ fn render_path_partial(
     content: &mut Content,
     ctx: &mut Context,
 ) {
+    // The area covered by the path determines how often spread gradients
+    // are repeated.
+    let area = path
+        .data
+        .bbox_with_transform(path.transform, path.stroke.as_ref())
+        .and_then(|bbox| bbox.to_rect())
+        .map_or_else(|| ctx.c.pdf_rect(bbox), |rect| ctx.c.pdf_rect(rect));
+
     // In order to apply non-uniform transparency, e.g. in a gradient, we
     // have to create a Soft Mask in an external graphics state dictionary.
     //
@@ -134,21 +245,23 @@ fn render_path_partial(
             alpha_func,
             fill_gradient.as_ref().unwrap(),
             bbox,
+            area,
             writer,
             ctx,
         );
 
-        Some(start_wrap(smask_form_ref, content, ctx))
+        Some(start_wrap(smask_form_ref, &path.id, content, ctx))
     } else if let Some(alpha_func) = stroke_g_alpha {
         let smask_form_ref = prep_shading(
             alpha_func,
             stroke_gradient.as_ref().unwrap(),
             bbox,
+            area,
             writer,
             ctx,
         );
 
-        Some(start_wrap(smask_form_ref, content, ctx))
+        Some(start_wrap(smask_form_ref, &path.id, content, ctx))
     } else {
         content.save_state();
         None
@@ -162,18 +275,30 @@ fn render_path_partial(
         content
     };
 
-    content.set_fill_color_space(ColorSpaceOperand::Named(SRGB));
-    content.set_stroke_color_space(ColorSpaceOperand::Named(SRGB));
+    // A rectangle filled with a gradient is painted with the shading operator
+    // within it instead of through a pattern if there is no stroke to draw.
+    let shading_area = (fill
+        && fill_gradient.is_some()
+        && !(stroke && path.stroke.is_some())
+        && ctx.clipped_path.is_none())
+    .then(|| rect_area(path, &ctx.c))
+    .flatten();
+
+    if shading_area.is_none() {
+        content.set_fill_color_space(ColorSpaceOperand::Named(SRGB));
+        content.set_stroke_color_space(ColorSpaceOperand::Named(SRGB));
+    }
 
-    let stroke_opacity = path.stroke.as_ref().map(|s| s.opacity.value() as f32);
-    let fill_opacity = path.fill.as_ref().map(|f| f.opacity.value() as f32);
+    let stroke_opacity =
+        path.stroke.as_ref().map(|s| s.opacity.value() as f32 * ctx.opacity);
+    let fill_opacity = path.fill.as_ref().map(|f| f.opacity.value() as f32 * ctx.opacity);
 
     // Write a graphics state for stroke and fill opacity.
     if stroke_opacity.unwrap_or(1.0) != 1.0 || fill_opacity.unwrap_or(1.0) != 1.0 {
-        let num = ctx.alloc_gs();
-        content.set_parameters(Name(format!("gs{}", num).as_bytes()));
+        let gs = ctx.alloc_gs();
+        content.set_parameters(gs.as_name());
         ctx.pending_graphics
-            .push(PendingGS::opacity(stroke_opacity, fill_opacity, num));
+            .push(PendingGS::opacity(stroke_opacity, fill_opacity, gs));
     }
 
     if stroke {
@@ -196,10 +321,12 @@ fn render_path_partial(
 
             content.set_miter_limit(stroke.miterlimit.value() as f32);
 
+            // Dash lengths are measured along the path in its user space.
             if let Some(dasharray) = &stroke.dasharray {
+                let (scale, _) = ctx.c.length_scale(path.transform);
                 content.set_dash_pattern(
-                    dasharray.iter().map(|&x| x as f32),
-                    stroke.dashoffset,
+                    dasharray.iter().map(|&x| (x * scale) as f32),
+                    (stroke.dashoffset as f64 * scale) as f32,
                 );
             }
 
@@ -210,75 +337,105 @@ fn render_path_partial(
                 Paint::Link(id) => {
                     let item = ctx.tree.defs_by_id(id).unwrap();
                     content.set_stroke_color_space(ColorSpaceOperand::Pattern);
-                    let num = ctx.alloc_pattern();
-                    let name = format!("p{}", num);
+                    let name = ctx.alloc_pattern(id);
 
                     match *item.borrow() {
                         NodeKind::RadialGradient(_) | NodeKind::LinearGradient(_) => {
                             let pattern = stroke_gradient.unwrap();
 
                             ctx.pending_gradients.push(PendingGradient::from_gradient(
-                                pattern, bbox, num, &ctx.c,
+                                pattern,
+                                bbox,
+                                area,
+                                name.clone(),
+                                &ctx.c,
                             ));
                         }
                         NodeKind::Pattern(ref pattern) => {
-                            prep_pattern(pattern, &item, num, bbox, writer, ctx);
+                            prep_pattern(pattern, &item, &name, path, writer, ctx);
                         }
                         _ => unreachable!(),
                     }
 
-                    content.set_stroke_pattern(None, Name(name.as_bytes()));
+                    content.set_stroke_pattern(None, name.as_name());
                 }
             }
         }
     }
 
+    let mut shading = None;
     if fill {
         match path.fill.as_ref().map(|fill| &fill.paint) {
             Some(Paint::Color(c)) => {
                 content.set_fill_color(RgbColor::from(*c).to_array());
             }
+            Some(Paint::Link(_)) if shading_area.is_some() => {
+                let name = ctx.alloc_shading();
+                let mut pending = PendingGradient::from_gradient(
+                    fill_gradient.unwrap(),
+                    bbox,
+                    area,
+                    name.clone(),
+                    &ctx.c,
+                );
+                pending.direct = true;
+                ctx.pending_gradients.push(pending);
+                shading = Some(name);
+            }
             Some(Paint::Link(id)) => {
                 let item = ctx.tree.defs_by_id(id).unwrap();
                 content.set_fill_color_space(ColorSpaceOperand::Pattern);
 
-                let num = ctx.alloc_pattern();
-                let name = format!("p{}", num);
+                let name = ctx.alloc_pattern(id);
 
                 match *item.borrow() {
                     NodeKind::RadialGradient(_) | NodeKind::LinearGradient(_) => {
                         let pattern = fill_gradient.unwrap();
 
                         ctx.pending_gradients.push(PendingGradient::from_gradient(
-                            pattern, bbox, num, &ctx.c,
+                            pattern,
+                            bbox,
+                            area,
+                            name.clone(),
+                            &ctx.c,
                         ));
                     }
                     NodeKind::Pattern(ref pattern) => {
-                        prep_pattern(pattern, &item, num, bbox, writer, ctx);
+                        prep_pattern(pattern, &item, &name, path, writer, ctx);
                     }
                     _ => unreachable!(),
                 }
 
-                content.set_fill_pattern(None, Name(name.as_bytes()));
+                content.set_fill_pattern(None, name.as_name());
             }
             None => {}
         }
     }
 
-    draw_path(&path.data.0, path.transform, content, &ctx.c);
+    if let (Some(name), Some(rect)) = (&shading, shading_area) {
+        content.rect(rect.x1, rect.y1, rect.x2 - rect.x1, rect.y2 - rect.y1);
+        content.clip_nonzero();
+        content.end_path();
+        content.shading(name.as_name());
+    } else {
+        match &ctx.clipped_path {
+            Some(rings) => draw_rings(rings, content),
+            None => draw_path(&path.data.0, path.transform, content, &ctx.c),
+        }
 
-    match (
-        path.fill.as_ref().map(|f| f.rule),
-        fill,
-        path.stroke.is_some() && stroke,
-    ) {
-        (Some(FillRule::NonZero), true, true) => content.fill_nonzero_and_stroke(),
-        (Some(FillRule::EvenOdd), true, true) => content.fill_even_odd_and_stroke(),
-        (Some(FillRule::NonZero), true, false) => content.fill_nonzero(),
-        (Some(FillRule::EvenOdd), true, false) => content.fill_even_odd(),
-        (None, _, true) | (_, false, true) => content.stroke(),
-        (None, _, false) | (_, false, false) => content.end_path(),
-    };
+        match (
+            path.fill.as_ref().map(|f| f.rule),
+            fill,
+            path.stroke.is_some() && stroke,
+        ) {
+            (Some(FillRule::NonZero), true, true) => content.fill_nonzero_and_stroke(),
+            (Some(FillRule::EvenOdd), true, true) => content.fill_even_odd_and_stroke(),
+            (Some(FillRule::NonZero), true, false) => content.fill_nonzero(),
+            (Some(FillRule::EvenOdd), true, false) => content.fill_even_odd(),
+            (None, _, true) | (_, false, true) => content.stroke(),
+            (None, _, false) | (_, false, false) => content.end_path(),
+        };
+    }
 
     // We only backed up the graphics state if there was no alpha
     // transparency so we only restore it in that case.
@@ -289,32 +446,65 @@ fn render_path_partial(
     let pdf_bbox = ctx.c.pdf_rect(bbox);
 
     // Write the Form XObject if there was a gradient with alpha values.
-    if let Some((xobj_content, path_no)) = xobj_content {
+    if let Some((xobj_content, path_name)) = xobj_content {
         let path_ref = ctx.alloc_ref();
-        let data = if ctx.compress {
-            deflate(&xobj_content.finish())
-        } else {
-            xobj_content.finish()
-        };
+        let data = ctx.finish_content(xobj_content.finish());
+        ctx.xobjects += 1;
 
         let mut form =
             form_xobject(writer, path_ref, &data, pdf_bbox, ctx.compress, true);
         let mut resources = form.resources();
         ctx.pop(&mut resources);
-        ctx.pending_xobjects.push((path_no, path_ref));
+        ctx.pending_xobjects.push((path_name, path_ref));
     }
 }
 
-/// Convert usvg's transforms to PDF matrices.
-fn transform_to_matrix(transform: Transform) -> [f32; 6] {
-    [
-        transform.a as f32,
-        transform.b as f32,
-        transform.c as f32,
-        transform.d as f32,
-        transform.e as f32,
-        transform.f as f32,
-    ]
+/// The area of a path that forms a rectangle with edges parallel to the axes
+/// of the page.
+fn rect_area(path: &usvg::Path, c: &CoordToPdf) -> Option<pdf_writer::Rect> {
+    let segments = &path.data.0;
+    let mut points = vec![];
+    for (i, segment) in segments.iter().enumerate() {
+        match *segment {
+            PathSegment::MoveTo { x, y } if i == 0 => {
+                points.push(c.point(path.transform.apply(x, y)))
+            }
+            PathSegment::LineTo { x, y } if i > 0 => {
+                points.push(c.point(path.transform.apply(x, y)))
+            }
+            PathSegment::ClosePath if i + 1 == segments.len() => {}
+            _ => return None,
+        }
+    }
+
+    if points.len() == 5 && points[0] == points[4] {
+        points.pop();
+    }
+
+    if points.len() != 4 {
+        return None;
+    }
+
+    // Each edge must be either horizontal or vertical and have a length.
+    for i in 0 .. 4 {
+        let (a, b) = (points[i], points[(i + 1) % 4]);
+        if (a.0 == b.0) == (a.1 == b.1) {
+            return None;
+        }
+    }
+
+    let (x1, y1) = points[0];
+    let (x2, y2) = points[2];
+    if x1 == x2 || y1 == y2 {
+        return None;
+    }
+
+    Some(pdf_writer::Rect::new(
+        x1.min(x2),
+        y1.min(y2),
+        x1.max(x2),
+        y1.max(y2),
+    ))
 }
 
 /// Retrieve the pattern and alpha values for a paint.
@@ -344,6 +534,7 @@ fn prep_shading(
     alpha_func: Ref,
     gradient: &Gradient,
     bbox: usvg::Rect,
+    area: pdf_writer::Rect,
     writer: &mut PdfWriter,
     ctx: &mut Context,
 ) -> Ref {
@@ -355,9 +546,8 @@ fn prep_shading(
     // We draw the gradient with the shading operator instead of
     // registering a pattern, so we allocate a shading number for the
     // `Resources` dictionary.
-    let shading_num = ctx.alloc_shading();
-    let shading_name = format!("sh{}", shading_num);
-    shading_content.shading(Name(shading_name.as_bytes()));
+    let shading_name = ctx.alloc_shading();
+    shading_content.shading(shading_name.as_name());
     let shading_content = shading_content.finish();
 
     // Reference for the indirect Shading dictionary.
@@ -366,19 +556,20 @@ fn prep_shading(
 
     shading.shading_type(gradient.shading_type);
     shading.color_space().d65_gray();
-    shading.function(alpha_func);
-    shading.coords(gradient.transformed_coords(&ctx.c, bbox).into_iter().take(
-        if gradient.shading_type == ShadingType::Axial {
-            4
-        } else {
-            6
-        },
-    ));
-    shading.extend([true, true]);
+    let coords = gradient.transformed_coords(&ctx.c, bbox);
+    let spread = gradient.spread(coords, area);
+    write_shading(
+        &mut shading,
+        gradient.shading_type,
+        alpha_func,
+        coords,
+        spread,
+    );
     shading.finish();
 
     // Write the Form XObject for with the luminance-encoded alpha
     // values for the Soft Mask.
+    ctx.xobjects += 1;
     let mut smask_form = form_xobject(
         writer,
         smask_form_ref,
@@ -390,7 +581,7 @@ fn prep_shading(
 
     let mut resources = smask_form.resources();
     resources.proc_sets([ProcSet::Pdf, ProcSet::ImageGrayscale]);
-    resources.shadings().pair(Name(shading_name.as_bytes()), shading_ref);
+    resources.shadings().pair(shading_name.as_name(), shading_ref);
 
     smask_form_ref
 }
@@ -399,39 +590,48 @@ fn prep_shading(
 /// applicability.
 fn start_wrap(
     smask_ref: Ref,
+    id: &str,
     content: &mut Content,
     ctx: &mut Context,
-) -> (Content, u32) {
-    // Number of the inner transparency group
-    let path_ref = ctx.alloc_xobject();
+) -> (Content, ResourceName) {
+    // Name of the inner transparency group
+    let path_name = ctx.alloc_xobject(id);
 
     // Write the reference to the transparency group containing the path
     // to the original content stream. For all following operations, we
     // will populate a content stream for this group.
-    content.x_object(Name(format!("xo{}", path_ref).as_bytes()));
+    content.x_object(path_name.as_name());
 
     // Apply the Graphics State with the Soft Mask first thing in the
     // new content stream.
-    let gs_num = ctx.alloc_gs();
-    let gs_name = format!("gs{}", gs_num);
     ctx.push();
-    ctx.pending_graphics.push(PendingGS::soft_mask(smask_ref, gs_num));
+    let gs = ctx.alloc_gs();
 
     let mut path_content = Content::new();
-    path_content.set_parameters(Name(gs_name.as_bytes()));
+    path_content.set_parameters(gs.as_name());
+    ctx.pending_graphics.push(PendingGS::soft_mask(smask_ref, gs));
 
-    (path_content, path_ref)
+    (path_content, path_name)
 }
 
 /// Write a pattern to the file for use for filling or stroking.
+///
+/// The pattern is positioned in the coordinate system of the path, including
+/// its transform, and its bounding box units refer to the untransformed path.
 fn prep_pattern(
     pattern: &Pattern,
     node: &Node,
-    num: u32,
-    bbox: usvg::Rect,
+    name: &ResourceName,
+    path: &usvg::Path,
     writer: &mut PdfWriter,
     ctx: &mut Context,
 ) {
+    let bbox = path
+        .data
+        .bbox()
+        .and_then(|b| b.to_rect())
+        .unwrap_or_else(|| usvg::Rect::new(0.0, 0.0, 1.0, 1.0).unwrap());
+
     let rect = match pattern.units {
         Units::UserSpaceOnUse => pattern.rect,
         Units::ObjectBoundingBox => usvg::Rect::new(
@@ -443,8 +643,16 @@ fn prep_pattern(
         .unwrap(),
     };
 
-    let matrix = transform_to_matrix(pattern.transform);
-    let pdf_rect = ctx.c.pdf_rect(rect);
+    // The cell is written in PDF coordinates without the pre-transformation
+    // of the converter, and the pattern matrix maps it into the coordinate
+    // system of the path. Because PDF applies the matrix in the default
+    // coordinate system, it must include the conversion to PDF coordinates.
+    let mut transform = path.transform;
+    transform.append(&pattern.transform);
+    let matrix = ctx.c.transformed_matrix(transform).map(|x| x as f32);
+    let mut cell = ctx.c;
+    cell.transform([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+    let pdf_rect = cell.pdf_rect(rect);
 
     let mut inner_matrix = if let Some(viewbox) = pattern.view_box {
         CoordToPdf::new((rect.width(), rect.height()), ctx.c.dpi(), viewbox, None)
@@ -472,10 +680,36 @@ fn prep_pattern(
 
     let old = ctx.c.transform(inner_matrix);
 
+    // The content of the pattern is not clipped along with the path and the
+    // opacity of the groups around the path applies to the pattern as a whole.
+    let regions = std::mem::take(&mut ctx.clip_regions);
+    let clipped = ctx.clipped_path.take();
+    let opacity = std::mem::replace(&mut ctx.opacity, 1.0);
+    let compress = ctx.compress;
+    ctx.compress &= ctx.compress_patterns;
     let pattern_stream = content_stream(node, writer, ctx);
+    let compressed = ctx.compress;
+    ctx.compress = compress;
+    ctx.clip_regions = regions;
+    ctx.clipped_path = clipped;
+    ctx.opacity = opacity;
     ctx.c.transform(old);
 
+    // Paths with the same pattern and bounding box share the pattern object.
+    let rect_bits =
+        [pdf_rect.x1, pdf_rect.y1, pdf_rect.x2, pdf_rect.y2].map(f32::to_bits);
+    let key = ctx.form_key(
+        &pattern_stream,
+        ("pattern", rect_bits, matrix.map(f32::to_bits)),
+    );
+    if let Some(&pattern_ref) = ctx.forms.get(&key) {
+        ctx.discard();
+        ctx.pending_patterns.push((name.clone(), pattern_ref));
+        return;
+    }
+
     let pattern_ref = ctx.alloc_ref();
+    ctx.forms.insert(key, pattern_ref);
     let mut pdf_pattern = writer.tiling_pattern(pattern_ref, &pattern_stream);
     pdf_pattern
         .tiling_type(TilingType::ConstantSpacing)
@@ -486,7 +720,7 @@ fn prep_pattern(
         .x_step(pdf_rect.x2 - pdf_rect.x1)
         .y_step(pdf_rect.y2 - pdf_rect.y1);
 
-    if ctx.compress {
+    if compressed {
         pdf_pattern.filter(Filter::FlateDecode);
     }
 
@@ -495,7 +729,7 @@ fn prep_pattern(
     resources.finish();
 
     pdf_pattern.matrix(matrix);
-    ctx.pending_patterns.push((num, pattern_ref))
+    ctx.pending_patterns.push((name.clone(), pattern_ref))
 }
 
 impl Render for usvg::Group {
@@ -506,235 +740,616 @@ impl Render for usvg::Group {
         content: &mut Content,
         ctx: &mut Context,
     ) {
-        ctx.push();
-
-        let group_ref = ctx.alloc_ref();
-        let child_content = content_stream(&node, writer, ctx);
-
-        let bbox = node
-            .calculate_bbox()
-            .and_then(|b| b.to_rect())
-            .unwrap_or_else(|| usvg::Rect::new(0.0, 0.0, 1.0, 1.0).unwrap());
+        let regions = ctx
+            .flatten_clip_paths
+            .then(|| clip_regions(node, self, ctx))
+            .flatten();
+
+        let depth = ctx.clip_regions.len();
+        let flattened = regions.map(|regions| ctx.clip_regions.extend(regions)).is_some();
+        if !self.filter.is_empty() {
+            ctx.note_issue(
+                node,
+                "Drew the element without its filter, which is not supported.".into(),
+            );
+        }
 
-        let pdf_bbox = ctx.c.pdf_rect(bbox);
-        let old = ctx.c.transform([
+        let matrix = [
             self.transform.a,
             self.transform.b,
             self.transform.c,
             self.transform.d,
             self.transform.e,
             self.transform.f,
-        ]);
+        ];
+
+        // A group that wraps a single element and does not need to be
+        // composited on its own can be drawn directly into the surrounding
+        // content stream. The same goes for a group with a constant opacity
+        // if it can be applied to each of its children instead.
+        let opacity = self.opacity.value() as f32;
+        let distribute = opacity != 1.0
+            && self.mask.is_none()
+            && (ctx.distribute_opacity || paints_disjoint(node));
+        let transparent = opacity != 1.0 || self.mask.is_some();
+
+        // Each clip path of such a group nests the graphics state once more
+        // and viewers only support a limited depth, so deeply nested clipped
+        // groups are written as Form XObjects again.
+        let clipped = !flattened && self.clip_path.is_some();
+        let nestable = !clipped || ctx.inline_clips < MAX_INLINE_CLIPS;
+        if ctx.initial_mask.is_none()
+            && nestable
+            && (distribute || !transparent && node.children().count() == 1)
+        {
+            if clipped {
+                content.save_state();
+                let old = ctx.c.transform(matrix);
+                apply_clip_path(self.clip_path.as_ref(), content, ctx);
+                ctx.c.transform(old);
+                ctx.inline_clips += 1;
+            }
 
-        // Every group is an isolated transparency group, it needs to be painted
-        // onto its own canvas.
-        let mut form = form_xobject(
-            writer,
-            group_ref,
-            &child_content,
-            pdf_bbox,
-            ctx.compress,
-            true,
-        );
+            let outer = ctx.opacity;
+            ctx.opacity *= opacity;
+            render_children(node, writer, content, ctx);
+            ctx.opacity = outer;
+            ctx.clip_regions.truncate(depth);
 
-        let mut resources = form.resources();
-        ctx.pop(&mut resources);
+            if clipped {
+                ctx.inline_clips -= 1;
+                content.restore_state();
+            }
+            return;
+        }
 
-        let num = ctx.alloc_xobject();
-        let name = format!("xo{}", num);
+        // The opacity of the surrounding groups applies to the group as a
+        // whole.
+        ctx.push();
+        let outer = std::mem::replace(&mut ctx.opacity, 1.0);
+        let child_content = render_content(node, writer, ctx);
+        ctx.opacity = outer;
+        ctx.clip_regions.truncate(depth);
+
+        // Groups that don't draw anything are dropped entirely.
+        if is_empty_content(&child_content) {
+            ctx.discard();
+            return;
+        }
+
+        let child_content = ctx.finish_content(child_content);
+        let bbox = node
+            .calculate_bbox()
+            .and_then(|b| b.to_rect())
+            .unwrap_or_else(|| usvg::Rect::new(0.0, 0.0, 1.0, 1.0).unwrap());
+
+        let pdf_bbox = ctx.c.pdf_rect(bbox);
+        let old = ctx.c.transform(matrix);
+
+        // Groups with the same content, e.g. icons that are reused many times,
+        // share their Form XObject.
+        let bbox_bits =
+            [pdf_bbox.x1, pdf_bbox.y1, pdf_bbox.x2, pdf_bbox.y2].map(f32::to_bits);
+        let key = ctx.form_key(&child_content, ("group", bbox_bits));
+        let group_ref = match ctx.forms.get(&key) {
+            Some(&group_ref) => {
+                ctx.discard();
+                group_ref
+            }
+            None => {
+                let group_ref = ctx.alloc_ref();
+                ctx.forms.insert(key, group_ref);
+                ctx.xobjects += 1;
+
+                // Every group is an isolated transparency group, it needs to be
+                // painted onto its own canvas.
+                let mut form = form_xobject(
+                    writer,
+                    group_ref,
+                    &child_content,
+                    pdf_bbox,
+                    ctx.compress,
+                    true,
+                );
+
+                let mut resources = form.resources();
+                ctx.pop(&mut resources);
+                group_ref
+            }
+        };
+
+        let name = ctx.alloc_xobject(&self.id);
         content.save_state();
 
-        apply_clip_path(self.clip_path.as_ref(), content, ctx);
+        if !flattened {
+            apply_clip_path(self.clip_path.as_ref(), content, ctx);
+        }
         ctx.c.transform(old);
 
         if let Some(reference) = apply_mask(self.mask.as_ref(), bbox, pdf_bbox, ctx) {
-            let num = ctx.alloc_gs();
-            content.set_parameters(Name(format!("gs{}", num).as_bytes()));
-            ctx.pending_graphics.push(PendingGS::soft_mask(reference, num));
+            let gs = ctx.alloc_gs();
+            content.set_parameters(gs.as_name());
+            ctx.pending_graphics.push(PendingGS::soft_mask(reference, gs));
         }
 
-        if self.opacity.value() != 1.0 {
-            let num = ctx.alloc_gs();
-            content.set_parameters(Name(format!("gs{}", num).as_bytes()));
+        if opacity * ctx.opacity != 1.0 {
+            let gs = ctx.alloc_gs();
+            content.set_parameters(gs.as_name());
             ctx.pending_graphics
-                .push(PendingGS::fill_opacity(self.opacity.value() as f32, num));
+                .push(PendingGS::fill_opacity(opacity * ctx.opacity, gs));
         }
 
-        content.x_object(Name(name.as_bytes()));
+        content.x_object(name.as_name());
         content.restore_state();
-        ctx.pending_xobjects.push((num, group_ref));
+        ctx.pending_xobjects.push((name, group_ref));
     }
 }
 
+/// Whether the children of a group each paint a single layer and don't
+/// overlap, so that an opacity can be applied to each of them instead of to
+/// the group as a whole.
+fn paints_disjoint(node: &Node) -> bool {
+    let mut bboxes: Vec<usvg::PathBbox> = vec![];
+    for child in node.children() {
+        let single = match *child.borrow() {
+            NodeKind::Path(ref path) => path.fill.is_some() != path.stroke.is_some(),
+            NodeKind::Image(_) => true,
+            _ => false,
+        };
+
+        let bbox = match child.calculate_bbox() {
+            Some(bbox) if single => bbox,
+            _ => return false,
+        };
+
+        let overlaps = |other: &usvg::PathBbox| {
+            bbox.left() < other.right()
+                && other.left() < bbox.right()
+                && bbox.top() < other.bottom()
+                && other.top() < bbox.bottom()
+        };
+
+        if bboxes.iter().any(overlaps) {
+            return false;
+        }
+
+        bboxes.push(bbox);
+    }
+
+    true
+}
+
+/// Whether an uncompressed content stream consists only of state saves and
+/// restores, i.e. doesn't draw anything.
+fn is_empty_content(content: &[u8]) -> bool {
+    content
+        .split(u8::is_ascii_whitespace)
+        .all(|op| matches!(op, b"" | b"q" | b"Q"))
+}
+
 impl Render for usvg::Image {
     fn render(
         &self,
-        _: &Node,
+        node: &Node,
         writer: &mut PdfWriter,
         content: &mut Content,
         ctx: &mut Context,
     ) {
         {
-            if self.visibility != Visibility::Visible {
+            let hidden = self.visibility != Visibility::Visible;
+            if hidden && ctx.hidden_layer.is_none() {
                 return;
             }
 
-            let image_ref = ctx.alloc_ref();
+            // The size in points the image is displayed with, which
+            // determines its resolution for the image policy.
+            let transform = self.transform;
+            let scale =
+                (transform.a * transform.d - transform.b * transform.c).abs().sqrt()
+                    * ctx.c.scale();
+            let display = (
+                self.view_box.rect.width() * scale,
+                self.view_box.rect.height() * scale,
+            );
+
+            // Images that are used multiple times, e.g. repeated logos or map
+            // tiles, only need to be written once, also across the pages of a
+            // document. Nested SVGs additionally need to have the same size, and
+            // so do raster images if they are reduced according to their
+            // resolution.
+            let image_key = match &self.kind {
+                ImageKind::SVG(tree) => {
+                    let mut key = KeyRecorder::default();
+                    hash_tree(tree, &mut key);
+                    let rect = self.view_box.rect;
+                    rect.width().to_bits().hash(&mut key);
+                    rect.height().to_bits().hash(&mut key);
+                    write!(HashWriter(&mut key), "{:?}", self.view_box.aspect).ok();
+                    ImageKey::Svg(key.0.into_boxed_slice())
+                }
+                ImageKind::JPEG(buf) | ImageKind::PNG(buf) | ImageKind::GIF(buf) => {
+                    let display = ctx
+                        .image_policy
+                        .is_some_and(|p| p.max_dpi.is_some())
+                        .then(|| [display.0.to_bits(), display.1.to_bits()]);
+                    ImageKey::Raster(buf.clone(), display)
+                }
+            };
 
-            #[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
-            let set_image_props = |
-                image: &mut ImageXObject,
-                raster_size: &mut Option<(u32, u32)>,
-                decoded: &DynamicImage,
-                grey: bool,
-            | {
+            let cached = ctx.images.get(&image_key).copied();
+            let image_ref = cached.map_or_else(|| ctx.alloc_ref(), |(id, _)| id);
+
+            #[cfg(any(feature = "png", feature = "gif"))]
+            let set_image_props = |image: &mut ImageXObject,
+                                   raster_size: &mut Option<(u32, u32)>,
+                                   decoded: &DynamicImage,
+                                   grey: bool,
+                                   bilevel: bool,
+                                   icc: Option<Ref>| {
                 let color = decoded.color();
                 *raster_size = Some((decoded.width(), decoded.height()));
                 image.width(decoded.width() as i32);
                 image.height(decoded.height() as i32);
-                image.bits_per_component(
-                    (color.bits_per_pixel() / color.channel_count() as u16) as i32,
-                );
+                image.bits_per_component(if bilevel {
+                    1
+                } else {
+                    (color.bits_per_pixel() / color.channel_count() as u16) as i32
+                });
 
-                let space = image.color_space();
-                if !grey && color.has_color() {
-                    space.device_rgb();
+                if let Some(icc) = icc {
+                    image.insert(Name(b"ColorSpace")).array().item(ICC_BASED).item(icc);
+                } else if !grey && !bilevel && color.has_color() {
+                    image.color_space().device_rgb();
                 } else {
-                    space.device_gray();
+                    image.color_space().device_gray();
                 }
             };
 
             #[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
-            let mut raster_size: Option<(u32, u32)> = None;
+            let mut raster_size = cached.and_then(|(_, size)| size);
+            #[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
+            let max_pixels = ctx.max_image_pixels;
+            #[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
+            let reductions_for = {
+                let (reductions, policy) = (ctx.reductions, ctx.image_policy);
+                move |width, height| {
+                    reductions.for_image(policy.as_ref(), (width, height), display)
+                }
+            };
             let rect = self.view_box.rect;
 
             #[cfg(any(feature = "png", feature = "gif"))]
-            let mut apply_transparent = |decoded: DynamicImage| {
-                let color = decoded.color();
-
-                let bits = color.bits_per_pixel();
-                let channels = color.channel_count() as u16;
-                let image_bytes: Vec<u8> = match (channels, bits / channels > 8) {
-                    (1, false) => {
-                        decoded.to_luma8().pixels().flat_map(|&Luma(c)| c).collect()
-                    }
-                    (1, true) => decoded
-                        .to_luma16()
-                        .pixels()
-                        .flat_map(|&Luma(x)| x)
-                        .flat_map(|x| x.to_be_bytes())
-                        .collect(),
-                    (3 | 4, false) => {
-                        decoded.to_rgb8().pixels().flat_map(|&Rgb(c)| c).collect()
-                    }
-                    (3 | 4, true) => decoded
-                        .to_rgb16()
-                        .pixels()
-                        .flat_map(|&Rgb(c)| c)
-                        .flat_map(|x| x.to_be_bytes())
-                        .collect(),
-                    _ => panic!("unknown number of channels={channels}"),
-                };
-                let compressed = compress_to_vec_zlib(&image_bytes, 8);
-
-                let mut image = writer.image_xobject(image_ref, &compressed);
-                set_image_props(&mut image, &mut raster_size, &decoded, false);
-                image.filter(Filter::FlateDecode);
+            let mut apply_transparent =
+                |decoded: DynamicImage, profile: Option<Vec<u8>>| {
+                    let reductions = reductions_for(decoded.width(), decoded.height());
+                    let decoded =
+                        downsample(&decoded, reductions.image_scale).unwrap_or(decoded);
+
+                    #[cfg(feature = "jpeg")]
+                    let (decoded, jpeg) = match reductions.jpeg_quality {
+                        Some(quality) => {
+                            let decoded = to_8bit(decoded);
+                            let jpeg = encode_jpeg(&decoded, quality);
+                            (decoded, jpeg)
+                        }
+                        None => (decoded, None),
+                    };
 
-                // The alpha channel has to be written separately, as a Soft
-                // Mask.
-                if color.has_alpha() {
-                    let mask_id = ctx.alloc_ref();
-                    image.pair(Name(b"SMask"), mask_id);
-                    image.finish();
+                    #[cfg(not(feature = "jpeg"))]
+                    let jpeg: Option<Vec<u8>> = None;
 
+                    let color = decoded.color();
                     let bits = color.bits_per_pixel();
                     let channels = color.channel_count() as u16;
-                    let alpha_bytes: Vec<u8> = if bits / channels > 8 {
-                        decoded
-                            .to_rgba16()
+                    let image_bytes: Vec<u8> = match (channels, bits / channels > 8) {
+                        (1, false) => {
+                            decoded.to_luma8().pixels().flat_map(|&Luma(c)| c).collect()
+                        }
+                        (1, true) => decoded
+                            .to_luma16()
+                            .pixels()
+                            .flat_map(|&Luma(x)| x)
+                            .flat_map(|x| x.to_be_bytes())
+                            .collect(),
+                        (3 | 4, false) => {
+                            decoded.to_rgb8().pixels().flat_map(|&Rgb(c)| c).collect()
+                        }
+                        (3 | 4, true) => decoded
+                            .to_rgb16()
                             .pixels()
-                            .flat_map(|&Rgba([.., a])| a.to_be_bytes())
-                            .collect()
-                    } else {
-                        decoded.to_rgba8().pixels().map(|&Rgba([.., a])| a).collect()
+                            .flat_map(|&Rgb(c)| c)
+                            .flat_map(|x| x.to_be_bytes())
+                            .collect(),
+                        _ => panic!("unknown number of channels={channels}"),
                     };
 
-                    let compressed = compress_to_vec_zlib(&alpha_bytes, 8);
-                    let mut mask = writer.image_xobject(mask_id, &compressed);
-                    let mut void = None;
+                    // Black-and-white images only need one bit per pixel, and
+                    // would only be blurred by JPEG compression.
+                    let pixel = if channels == 1 { 1 } else { 3 } * (bits / channels / 8);
+                    let (image_bytes, bilevel) =
+                        match pack_bilevel(&image_bytes, pixel as usize, decoded.width())
+                        {
+                            Some(packed) => (packed, true),
+                            None => (image_bytes, false),
+                        };
+                    let jpeg = jpeg.filter(|_| !bilevel);
+
+                    let channels = if color.has_color() && !bilevel { 3 } else { 1 };
+                    let icc = profile.and_then(|profile| {
+                        write_icc_profile(&profile, channels, writer, ctx)
+                    });
+
+                    ctx.stream_bytes += image_bytes.len();
+                    ctx.xobjects += 1;
+                    let (compressed, filter) =
+                        match ctx.deflate(&image_bytes, ctx.compression.image()) {
+                            Some(compressed) => (compressed, Some(Filter::FlateDecode)),
+                            None => (image_bytes, None),
+                        };
+
+                    let (data, filter) = match jpeg {
+                        Some(jpeg) if jpeg.len() < compressed.len() => {
+                            (jpeg, Some(Filter::DctDecode))
+                        }
+                        _ => (compressed, filter),
+                    };
 
-                    set_image_props(&mut mask, &mut void, &decoded, true);
-                    mask.filter(Filter::FlateDecode);
-                }
-            };
+                    let mut image = writer.image_xobject(image_ref, &data);
+                    set_image_props(
+                        &mut image,
+                        &mut raster_size,
+                        &decoded,
+                        false,
+                        bilevel,
+                        icc,
+                    );
+                    if let Some(filter) = filter {
+                        image.filter(filter);
+                    }
 
-            match &self.kind {
-                #[cfg(feature = "jpeg")]
-                ImageKind::JPEG(buf) => {
-                    let cursor = std::io::Cursor::new(buf.as_ref());
-                    let decoded = if let Ok(decoded) =
-                        ImageReader::with_format(cursor, ImageFormat::Jpeg).decode()
-                    {
-                        decoded
-                    } else {
-                        return;
-                    };
+                    // The alpha channel has to be written separately, as a Soft
+                    // Mask.
+                    if color.has_alpha() {
+                        let mask_id = ctx.alloc_ref();
+                        image.pair(Name(b"SMask"), mask_id);
+                        image.finish();
 
-                    let mut image = writer.image_xobject(image_ref, buf);
-                    set_image_props(&mut image, &mut raster_size, &decoded, false);
-                    image.filter(Filter::DctDecode);
-                }
-                #[cfg(feature = "png")]
-                ImageKind::PNG(buf) => {
-                    let cursor = std::io::Cursor::new(buf.as_ref());
-                    apply_transparent(
-                        if let Ok(decoded) =
-                            ImageReader::with_format(cursor, ImageFormat::Png).decode()
-                        {
+                        let bits = color.bits_per_pixel();
+                        let channels = color.channel_count() as u16;
+                        let alpha_bytes: Vec<u8> = if bits / channels > 8 {
                             decoded
+                                .to_rgba16()
+                                .pixels()
+                                .flat_map(|&Rgba([.., a])| a.to_be_bytes())
+                                .collect()
                         } else {
-                            return;
-                        },
-                    );
-                }
-                #[cfg(feature = "gif")]
-                ImageKind::GIF(buf) => {
-                    let cursor = std::io::Cursor::new(buf.as_ref());
-                    apply_transparent(
-                        if let Ok(decoded) =
-                            ImageReader::with_format(cursor, ImageFormat::Gif).decode()
-                        {
-                            decoded
+                            decoded.to_rgba8().pixels().map(|&Rgba([.., a])| a).collect()
+                        };
+
+                        let pixel = (bits / channels / 8) as usize;
+                        let (alpha_bytes, bilevel) =
+                            match pack_bilevel(&alpha_bytes, pixel, decoded.width()) {
+                                Some(packed) => (packed, true),
+                                None => (alpha_bytes, false),
+                            };
+
+                        ctx.stream_bytes += alpha_bytes.len();
+                        ctx.xobjects += 1;
+                        let compressed =
+                            ctx.deflate(&alpha_bytes, ctx.compression.image());
+                        let mut mask = writer.image_xobject(
+                            mask_id,
+                            compressed.as_deref().unwrap_or(&alpha_bytes),
+                        );
+                        let mut void = None;
+
+                        set_image_props(
+                            &mut mask, &mut void, &decoded, true, bilevel, None,
+                        );
+                        if compressed.is_some() {
+                            mask.filter(Filter::FlateDecode);
+                        }
+                    }
+                };
+
+            // Images that can't be embedded are skipped with a warning.
+            let start = Instant::now();
+            let skipped: Option<String> = 'load: {
+                match &self.kind {
+                    _ if cached.is_some() => {}
+                    #[cfg(feature = "jpeg")]
+                    ImageKind::JPEG(buf) => {
+                        // The original data is embedded as it is unless the image
+                        // should be reduced or can't be read by PDF viewers, in
+                        // which case it is decoded and re-encoded.
+                        let original = jpeg_header(buf);
+                        let reductions = match original {
+                            Some(header) => reductions_for(header.width, header.height),
+                            None => ctx.reductions,
+                        };
+                        let reduce = reductions.image_scale < 1.0
+                            || reductions.jpeg_quality.is_some();
+                        let mut recompressed = None;
+                        if reduce || original.is_none() {
+                            let decoded = match decode(buf, ImageFormat::Jpeg, max_pixels)
+                            {
+                                Ok(decoded) => decoded,
+                                Err(message) => break 'load Some(message),
+                            };
+
+                            let (decoded, reduced) =
+                                reduce_jpeg(decoded, buf, &reductions);
+                            recompressed = reduced.or_else(|| {
+                                original
+                                    .is_none()
+                                    .then(|| encode_jpeg(&decoded, 90))
+                                    .flatten()
+                            });
+                        }
+
+                        let data = recompressed.as_deref().unwrap_or(buf);
+                        let header = if let Some(header) = jpeg_header(data) {
+                            header
                         } else {
-                            return;
-                        },
-                    );
-                }
-                ImageKind::SVG(tree) => {
-                    // An SVG image means that the file gets embedded in a
-                    // completely isolated fashion, thus we convert its tree
-                    // recursively here.
-                    let opt = Options {
-                        viewport: Some((rect.width(), rect.height())),
-                        aspect: Some(self.view_box.aspect),
-                        dpi: ctx.c.dpi(),
-                        compress: ctx.compress,
-                    };
+                            break 'load Some(
+                                "Skipped JPEG image with an unsupported encoding.".into(),
+                            );
+                        };
+
+                        let (width, height) = (header.width, header.height);
+                        if let Some(message) = too_many_pixels(width, height, max_pixels)
+                        {
+                            break 'load Some(message);
+                        }
+
+                        let channels = header.components;
+                        ctx.stream_bytes += width as usize * height as usize * channels;
+                        ctx.xobjects += 1;
+                        raster_size = Some((width, height));
+
+                        let icc =
+                            icc_profile(buf, ImageFormat::Jpeg).and_then(|profile| {
+                                write_icc_profile(&profile, channels as i32, writer, ctx)
+                            });
+
+                        let mut image = writer.image_xobject(image_ref, data);
+                        image.width(width as i32);
+                        image.height(height as i32);
+                        image.bits_per_component(8);
+                        match (icc, channels) {
+                            (Some(icc), _) => {
+                                image
+                                    .insert(Name(b"ColorSpace"))
+                                    .array()
+                                    .item(ICC_BASED)
+                                    .item(icc);
+                            }
+                            (None, 4) => image.color_space().device_cmyk(),
+                            (None, 3) => image.color_space().device_rgb(),
+                            (None, _) => image.color_space().device_gray(),
+                        }
+
+                        // Adobe applications write inverted CMYK values.
+                        if channels == 4 && header.adobe {
+                            image.decode([1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+                        }
 
-                    ctx.next_id = convert_tree_into(tree, opt, writer, image_ref).get();
+                        image.filter(Filter::DctDecode);
+                    }
+                    #[cfg(feature = "png")]
+                    ImageKind::PNG(buf) => {
+                        apply_transparent(
+                            match decode(buf, ImageFormat::Png, max_pixels) {
+                                Ok(decoded) => decoded,
+                                Err(message) => break 'load Some(message),
+                            },
+                            icc_profile(buf, ImageFormat::Png),
+                        );
+                    }
+                    #[cfg(feature = "gif")]
+                    ImageKind::GIF(buf) => {
+                        apply_transparent(
+                            match decode(buf, ImageFormat::Gif, max_pixels) {
+                                Ok(decoded) => decoded,
+                                Err(message) => break 'load Some(message),
+                            },
+                            None,
+                        );
+                    }
+                    ImageKind::SVG(tree) => {
+                        // An SVG image means that the file gets embedded in a
+                        // completely isolated fashion, thus we convert its tree
+                        // recursively here.
+                        let opt = Options {
+                            viewport: Some((rect.width(), rect.height())),
+                            aspect: Some(self.view_box.aspect),
+                            dpi: ctx.c.dpi(),
+                            compress: ctx.compress,
+                            compress_patterns: ctx.compress_patterns,
+                            parallel_compression: ctx.parallel_compression,
+                            compression: ctx.compression,
+                            outline_strokes: ctx.outline_strokes,
+                            flatten_clip_paths: ctx.flatten_clip_paths,
+                            max_image_pixels: ctx.max_image_pixels,
+                            image_policy: ctx.image_policy,
+                            debug_names: ctx.debug_names.is_some(),
+                            ..Options::default()
+                        };
+
+                        let reductions = ctx.reductions;
+                        let allocator =
+                            ctx.allocator.as_mut().map(|allocator| &mut **allocator as _);
+                        let (next_id, xobjects) = write_tree_xobject(
+                            tree,
+                            &opt,
+                            &reductions,
+                            writer,
+                            image_ref,
+                            true,
+                            allocator,
+                        );
+                        ctx.next_id = next_id.get();
+                        ctx.xobjects += xobjects;
+                    }
+                    #[cfg(not(feature = "jpeg"))]
+                    ImageKind::JPEG(_) => {
+                        break 'load Some(
+                            "Skipped JPEG image because the `jpeg` feature is disabled."
+                                .into(),
+                        );
+                    }
+                    #[cfg(not(feature = "png"))]
+                    ImageKind::PNG(_) => {
+                        break 'load Some(
+                            "Skipped PNG image because the `png` feature is disabled."
+                                .into(),
+                        );
+                    }
+                    #[cfg(not(feature = "gif"))]
+                    ImageKind::GIF(_) => {
+                        break 'load Some(
+                            "Skipped GIF image because the `gif` feature is disabled."
+                                .into(),
+                        );
+                    }
                 }
-                #[cfg(any(
-                    not(feature = "jpeg"),
-                    not(feature = "png"),
-                    not(feature = "gif")
-                ))]
-                _ => {}
+
+                None
+            };
+
+            if let Some(message) = skipped {
+                log::warn!("{}", message);
+                ctx.note_issue(node, message);
+                return;
             }
 
+            // Nested SVGs count as rendering.
+            if !matches!(self.kind, ImageKind::SVG(_)) {
+                ctx.timings.images += start.elapsed();
+            }
+
+            #[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
+            let size = raster_size;
+            #[cfg(not(any(feature = "png", feature = "jpeg", feature = "gif")))]
+            let size = None;
+            ctx.images.insert(image_key, (image_ref, size));
+
             // Common operations for raster image formats.
             #[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
             let image_ref = if let Some((width, height)) = raster_size {
+                // Photos may be stored sideways along with an EXIF tag that
+                // says how to rotate them for display.
+                let orientation = match &self.kind {
+                    ImageKind::JPEG(buf) => exif_orientation(buf),
+                    _ => 1,
+                };
+
+                let (width, height) = if orientation >= 5 {
+                    (height, width)
+                } else {
+                    (width, height)
+                };
+
                 let mut content = Content::new();
                 let xobj_name = Name(b"EmbRaster");
                 let converter = CoordToPdf::new(
@@ -757,11 +1372,15 @@ impl Render for usvg::Image {
                     converter.offset_x() as f32,
                     converter.offset_y() as f32,
                 ]);
+                if orientation != 1 {
+                    content.transform(orientation_matrix(orientation));
+                }
                 content.x_object(xobj_name);
                 content.restore_state();
 
                 let content = content.finish();
                 let external_ref = ctx.alloc_ref();
+                ctx.xobjects += 1;
 
                 let mut xobject = writer.form_xobject(external_ref, &content);
                 let mut resources = xobject.resources();
@@ -777,7 +1396,7 @@ impl Render for usvg::Image {
                 ));
 
                 let scaling = 72.0 / ctx.c.dpi();
-                let mut transform = self.transform.clone();
+                let mut transform = self.transform;
                 transform.scale(scaling, scaling);
                 xobject.matrix([
                     transform.a as f32,
@@ -793,17 +1412,409 @@ impl Render for usvg::Image {
                 image_ref
             };
 
-            let num = ctx.alloc_xobject();
-            ctx.pending_xobjects.push((num, image_ref));
-            let name = format!("xo{}", num);
+            let name = ctx.alloc_xobject(&self.id);
+            ctx.pending_xobjects.push((name.clone(), image_ref));
+
+            if hidden {
+                ctx.begin_hidden(content);
+            }
 
             let (x, y) = ctx.c.point((rect.x(), rect.y() + rect.height()));
+            content.save_state();
             content.transform([1.0, 0.0, 0.0, 1.0, x, y]);
-            content.x_object(Name(name.as_bytes()));
+
+            if ctx.opacity != 1.0 {
+                let gs = ctx.alloc_gs();
+                content.set_parameters(gs.as_name());
+                ctx.pending_graphics.push(PendingGS::fill_opacity(ctx.opacity, gs));
+            }
+
+            content.x_object(name.as_name());
+            content.restore_state();
+
+            if hidden {
+                content.end_marked_content();
+            }
         }
     }
 }
 
+/// Identifies an image XObject that was already written.
+#[derive(PartialEq, Eq, Hash)]
+pub(crate) enum ImageKey {
+    /// The data of a raster image, along with its displayed size if images are
+    /// reduced according to their resolution. Comparing the shared data is
+    /// cheap if it comes from the same source.
+    Raster(Arc<Vec<u8>>, Option<[u64; 2]>),
+    /// The recorded tree of a nested SVG image, along with its size and
+    /// aspect ratio.
+    Svg(Box<[u8]>),
+}
+
+/// Feed the nodes of a tree into a hasher, including the data of embedded
+/// images.
+fn hash_tree(tree: &Tree, hasher: &mut KeyRecorder) {
+    for node in tree.root().descendants() {
+        node.ancestors().count().hash(hasher);
+        let kind = node.borrow();
+        write!(HashWriter(hasher), "{:?}", *kind).ok();
+
+        // The debug representation of images omits their data.
+        if let NodeKind::Image(ref image) = *kind {
+            match &image.kind {
+                ImageKind::JPEG(buf) | ImageKind::PNG(buf) | ImageKind::GIF(buf) => {
+                    buf.hash(hasher)
+                }
+                ImageKind::SVG(tree) => hash_tree(tree, hasher),
+            }
+        }
+    }
+}
+
+/// Writes formatted text into a hasher.
+struct HashWriter<'a>(&'a mut KeyRecorder);
+
+impl Write for HashWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Decode a raster image unless it has more than `max_pixels` pixels. Returns
+/// the warning to skip the image with otherwise.
+#[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
+fn decode(
+    buf: &[u8],
+    format: ImageFormat,
+    max_pixels: Option<u64>,
+) -> Result<DynamicImage, String> {
+    let failed = || {
+        let name = format!("{:?}", format).to_uppercase();
+        format!("Skipped {} image that could not be decoded.", name)
+    };
+    let reader = || ImageReader::with_format(std::io::Cursor::new(buf), format);
+    let mut limits = Limits::default();
+    if let Some(max_pixels) = max_pixels {
+        let (width, height) = reader().into_dimensions().map_err(|_| failed())?;
+        if let Some(message) = too_many_pixels(width, height, Some(max_pixels)) {
+            return Err(message);
+        }
+
+        // Leave room for 16-bit channels with alpha.
+        let pixels = width as u64 * height as u64;
+        limits.max_alloc = Some(limits.max_alloc.unwrap_or(0).max(pixels * 8));
+    }
+
+    let mut reader = reader();
+    reader.limits(limits);
+    reader.decode().map_err(|_| failed())
+}
+
+/// The warning to skip an image with if it has more than the maximum number of
+/// pixels.
+#[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
+fn too_many_pixels(width: u32, height: u32, max_pixels: Option<u64>) -> Option<String> {
+    match max_pixels {
+        Some(max_pixels) if width as u64 * height as u64 > max_pixels => Some(format!(
+            "Skipped {}x{} image because it has more than {} pixels.",
+            width, height, max_pixels,
+        )),
+        _ => None,
+    }
+}
+
+/// Pack the pixels of an image into one bit each if all of their samples are
+/// either zero or the maximum value, with each row starting at a new byte.
+/// Returns `None` if any pixel has another value.
+#[cfg(any(feature = "png", feature = "gif"))]
+pub(crate) fn pack_bilevel(bytes: &[u8], pixel: usize, width: u32) -> Option<Vec<u8>> {
+    let width = width as usize;
+    if width == 0 || pixel == 0 {
+        return None;
+    }
+
+    let stride = width.div_ceil(8);
+    let rows = (bytes.len() / pixel).div_ceil(width);
+    let mut packed = vec![0; stride * rows];
+    for (i, sample) in bytes.chunks_exact(pixel).enumerate() {
+        if sample.iter().all(|&b| b == u8::MAX) {
+            let (y, x) = (i / width, i % width);
+            packed[y * stride + x / 8] |= 0x80 >> (x % 8);
+        } else if sample.iter().any(|&b| b != 0) {
+            return None;
+        }
+    }
+
+    Some(packed)
+}
+
+/// Downsample a decoded image to the given fraction of its width and height.
+/// Returns `None` if the image keeps its size.
+#[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
+fn downsample(decoded: &DynamicImage, scale: f32) -> Option<DynamicImage> {
+    if scale >= 1.0 {
+        return None;
+    }
+
+    let width = ((decoded.width() as f32 * scale).round() as u32).max(1);
+    let height = ((decoded.height() as f32 * scale).round() as u32).max(1);
+    Some(decoded.resize_exact(width, height, FilterType::Triangle))
+}
+
+/// Convert an image with 16 bits per channel to 8 bits per channel, keeping
+/// its color and alpha channels.
+#[cfg(all(feature = "jpeg", any(feature = "png", feature = "gif")))]
+fn to_8bit(decoded: DynamicImage) -> DynamicImage {
+    let color = decoded.color();
+    if color.bits_per_pixel() / color.channel_count() as u16 <= 8 {
+        return decoded;
+    }
+
+    match (color.has_color(), color.has_alpha()) {
+        (true, true) => DynamicImage::ImageRgba8(decoded.to_rgba8()),
+        (true, false) => DynamicImage::ImageRgb8(decoded.to_rgb8()),
+        (false, true) => DynamicImage::ImageLumaA8(decoded.to_luma_alpha8()),
+        (false, false) => DynamicImage::ImageLuma8(decoded.to_luma8()),
+    }
+}
+
+/// Encode the color channels of an image as JPEG with the given quality.
+#[cfg(feature = "jpeg")]
+fn encode_jpeg(decoded: &DynamicImage, quality: u8) -> Option<Vec<u8>> {
+    let mut buf = vec![];
+    let mut encoder = JpegEncoder::new_with_quality(&mut buf, quality);
+    let result = if decoded.color().has_color() {
+        encoder.encode_image(&decoded.to_rgb8())
+    } else {
+        encoder.encode_image(&decoded.to_luma8())
+    };
+
+    result.ok().map(|_| buf)
+}
+
+/// Downsample and recompress a JPEG image according to the measures to reduce
+/// the output size. Returns the image to embed along with its new data if it
+/// was re-encoded.
+#[cfg(feature = "jpeg")]
+fn reduce_jpeg(
+    decoded: DynamicImage,
+    buf: &[u8],
+    reductions: &Reductions,
+) -> (DynamicImage, Option<Vec<u8>>) {
+    if reductions.image_scale >= 1.0 && reductions.jpeg_quality.is_none() {
+        return (decoded, None);
+    }
+
+    let quality = reductions.jpeg_quality.unwrap_or(90);
+    let scaled =
+        downsample(&decoded, reductions.image_scale).unwrap_or_else(|| decoded.clone());
+
+    match encode_jpeg(&scaled, quality) {
+        Some(data) if data.len() < buf.len() => (scaled, Some(data)),
+        _ => (decoded, None),
+    }
+}
+
+/// Read the ICC profile embedded in a PNG or JPEG image.
+#[cfg(any(feature = "png", feature = "jpeg"))]
+fn icc_profile(buf: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    let cursor = std::io::Cursor::new(buf);
+    match format {
+        #[cfg(feature = "png")]
+        ImageFormat::Png => PngDecoder::new(cursor).ok()?.icc_profile(),
+        #[cfg(feature = "jpeg")]
+        ImageFormat::Jpeg => JpegDecoder::new(cursor).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// Write an ICC profile for images with the given number of color channels.
+/// Images with the same profile share it. Returns `None` if the profile is
+/// for a different number of channels.
+#[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
+fn write_icc_profile(
+    profile: &[u8],
+    channels: i32,
+    writer: &mut PdfWriter,
+    ctx: &mut Context,
+) -> Option<Ref> {
+    // The color space signature of the profile header.
+    let alternate = match (profile.get(16 .. 20)?, channels) {
+        (b"GRAY", 1) => Name(b"DeviceGray"),
+        (b"RGB ", 3) => Name(b"DeviceRGB"),
+        (b"CMYK", 4) => Name(b"DeviceCMYK"),
+        _ => return None,
+    };
+
+    let mut key = KeyRecorder::default();
+    ("icc", profile).hash(&mut key);
+    let key = key.0.into_boxed_slice();
+    if let Some(&icc_ref) = ctx.forms.get(&key) {
+        return Some(icc_ref);
+    }
+
+    let icc_ref = ctx.alloc_ref();
+    ctx.forms.insert(key, icc_ref);
+
+    let compressed = ctx.deflate(profile, ctx.compression.image());
+    let mut stream = writer.stream(icc_ref, compressed.as_deref().unwrap_or(profile));
+    stream.pair(Name(b"N"), channels);
+    stream.pair(Name(b"Alternate"), alternate);
+    if compressed.is_some() {
+        stream.filter(Filter::FlateDecode);
+    }
+
+    Some(icc_ref)
+}
+
+/// The header of a JPEG image that PDF viewers can decode.
+#[cfg(feature = "jpeg")]
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct JpegHeader {
+    /// The width in pixels.
+    width: u32,
+    /// The height in pixels.
+    height: u32,
+    /// The number of color components, 1, 3 or 4.
+    components: usize,
+    /// Whether the image has an Adobe segment, in which case CMYK values are
+    /// stored inverted.
+    adobe: bool,
+}
+
+/// Read the header of a JPEG image. Returns `None` if the image is malformed
+/// or uses an encoding that the `DCTDecode` filter doesn't support, i.e.
+/// anything but baseline and progressive Huffman coding with 8 bits per
+/// component.
+#[cfg(feature = "jpeg")]
+pub(crate) fn jpeg_header(data: &[u8]) -> Option<JpegHeader> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut adobe = false;
+    let mut pos = 2;
+    while data.get(pos) == Some(&0xFF) {
+        let marker = *data.get(pos + 1)?;
+        let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        let segment = data.get(pos + 4 .. pos + 2 + len)?;
+        match marker {
+            0xEE if segment.starts_with(b"Adobe") => adobe = true,
+            // The start of frame segment of a baseline, extended or
+            // progressive image.
+            0xC0 ..= 0xC2 => {
+                let read = |i: usize| u16::from_be_bytes([segment[i], segment[i + 1]]);
+                let components = *segment.get(5)? as usize;
+                let header = JpegHeader {
+                    width: read(3) as u32,
+                    height: read(1) as u32,
+                    components,
+                    adobe,
+                };
+
+                let valid = segment[0] == 8
+                    && header.width > 0
+                    && header.height > 0
+                    && matches!(components, 1 | 3 | 4);
+                return valid.then_some(header);
+            }
+            // Other start of frame segments, except for the DHT, JPG and DAC
+            // markers in their range, and the start of the scan.
+            0xC3 ..= 0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => return None,
+            0xDA => return None,
+            _ => {}
+        }
+
+        pos += 2 + len;
+    }
+
+    None
+}
+
+/// Read the EXIF orientation of a JPEG image, a number between 1 and 8.
+/// Returns 1 (no transformation) if the image has no valid orientation tag.
+#[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
+pub(crate) fn exif_orientation(data: &[u8]) -> u16 {
+    let read_u16 = |buf: &[u8], pos: usize, le: bool| {
+        let bytes = buf.get(pos .. pos.checked_add(2)?)?.try_into().ok()?;
+        Some(if le {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+
+    let read_u32 = |buf: &[u8], pos: usize, le: bool| {
+        let bytes = buf.get(pos .. pos.checked_add(4)?)?.try_into().ok()?;
+        Some(if le {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+
+    // Find the TIFF structure of the APP1 segment among the segments before
+    // the image data starts.
+    let find_tiff = || {
+        if !data.starts_with(&[0xFF, 0xD8]) {
+            return None;
+        }
+
+        let mut pos = 2;
+        while data.get(pos) == Some(&0xFF) {
+            let marker = *data.get(pos + 1)?;
+            let len = read_u16(data, pos + 2, false)? as usize;
+            let segment = data.get(pos + 4 .. pos + 2 + len)?;
+            if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+                return Some(&segment[6 ..]);
+            } else if marker == 0xDA {
+                return None;
+            }
+
+            pos += 2 + len;
+        }
+
+        None
+    };
+
+    let orientation = || {
+        let tiff = find_tiff()?;
+        let le = match tiff.get(.. 2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+
+        let ifd = read_u32(tiff, 4, le)? as usize;
+        let count = read_u16(tiff, ifd, le)? as usize;
+        (0 .. count).find_map(|i| {
+            let entry = ifd.checked_add(2 + 12 * i)?;
+            (read_u16(tiff, entry, le)? == 0x0112)
+                .then(|| read_u16(tiff, entry + 8, le))
+                .flatten()
+        })
+    };
+
+    orientation().filter(|o| (1 ..= 8).contains(o)).unwrap_or(1)
+}
+
+/// The transformation of the unit square that displays an image stored with
+/// the given EXIF orientation upright.
+#[cfg(any(feature = "png", feature = "jpeg", feature = "gif"))]
+pub(crate) fn orientation_matrix(orientation: u16) -> [f32; 6] {
+    match orientation {
+        2 => [-1.0, 0.0, 0.0, 1.0, 1.0, 0.0],
+        3 => [-1.0, 0.0, 0.0, -1.0, 1.0, 1.0],
+        4 => [1.0, 0.0, 0.0, -1.0, 0.0, 1.0],
+        5 => [0.0, -1.0, -1.0, 0.0, 1.0, 1.0],
+        6 => [0.0, -1.0, 1.0, 0.0, 0.0, 1.0],
+        7 => [0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+        8 => [0.0, 1.0, -1.0, 0.0, 1.0, 0.0],
+        _ => [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+    }
+}
+
 /// Draw a path into a content stream. Does close the path but not perform any
 /// drawing operators.
 pub fn draw_path(
@@ -848,8 +1859,27 @@ pub(crate) struct Gradient {
     /// Whether to transform the coords to the bounding box of the element or
     /// keep them in the page coordinate system.
     pub(crate) transform_coords: bool,
+    /// How the gradient continues beyond its vector.
+    pub(crate) spread_method: SpreadMethod,
 }
 
+/// How often a gradient with a `reflect` or `repeat` spread method is drawn
+/// before and after its vector to cover an area. Each period maps the whole
+/// gradient vector to the interval between two consecutive integers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub(crate) struct Spread {
+    /// Whether every other period is mirrored.
+    reflect: bool,
+    /// The start of the first period, where zero is the start of the vector.
+    start: i32,
+    /// The end of the last period, where one is the end of the vector.
+    end: i32,
+}
+
+/// The maximum number of periods of a repeated gradient, beyond which it is
+/// padded.
+const MAX_PERIODS: i32 = 1024;
+
 impl Gradient {
     fn from_node(node: Node) -> Option<Self> {
         match *node.borrow() {
@@ -858,12 +1888,14 @@ impl Gradient {
                 shading_type: ShadingType::Axial,
                 coords: [lg.x1, lg.y1, lg.x2, lg.y2, 0.0, 0.0],
                 transform_coords: lg.base.units == usvg::Units::ObjectBoundingBox,
+                spread_method: lg.base.spread_method,
             }),
             NodeKind::RadialGradient(ref rg) => Some(Self {
                 id: rg.id.clone(),
                 shading_type: ShadingType::Radial,
                 coords: [rg.fx, rg.fy, rg.cx, rg.cy, 0.0, rg.r.value()],
                 transform_coords: rg.base.units == usvg::Units::ObjectBoundingBox,
+                spread_method: rg.base.spread_method,
             }),
             _ => None,
         }
@@ -920,4 +1952,108 @@ impl Gradient {
             ]
         }
     }
+
+    /// How often the gradient with the transformed `coords` has to be drawn
+    /// to cover an `area` of the page. Returns `None` if it is padded.
+    pub(crate) fn spread(
+        &self,
+        coords: [f32; 6],
+        area: pdf_writer::Rect,
+    ) -> Option<Spread> {
+        let reflect = match self.spread_method {
+            SpreadMethod::Pad => return None,
+            SpreadMethod::Reflect => true,
+            SpreadMethod::Repeat => false,
+        };
+
+        let [a, b, c, d, e, f] = coords.map(|v| v as f64);
+        let corners = [
+            (area.x1 as f64, area.y1 as f64),
+            (area.x2 as f64, area.y1 as f64),
+            (area.x1 as f64, area.y2 as f64),
+            (area.x2 as f64, area.y2 as f64),
+        ];
+
+        let (min, max) = if self.shading_type == ShadingType::Axial {
+            // Project the corners onto the vector.
+            let (dx, dy) = (c - a, d - b);
+            let length = dx * dx + dy * dy;
+            if length < 1e-9 {
+                return None;
+            }
+
+            corners
+                .iter()
+                .map(|&(x, y)| ((x - a) * dx + (y - b) * dy) / length)
+                .fold((0.0f64, 1.0f64), |(min, max), t| (min.min(t), max.max(t)))
+        } else {
+            // The circle of each period grows by at least the distance by
+            // which its radius exceeds that of its center from the focal
+            // point, as the focal point lies within the end circle.
+            let growth = f - ((d - a).powi(2) + (e - b).powi(2)).sqrt();
+            if growth < 1e-9 {
+                return None;
+            }
+
+            let furthest = corners
+                .iter()
+                .map(|&(x, y)| ((x - a).powi(2) + (y - b).powi(2)).sqrt())
+                .fold(0.0, f64::max);
+
+            (0.0, (furthest / growth).max(1.0))
+        };
+
+        // Circles with a negative radius are not drawn, so radial gradients
+        // start at their focal point.
+        let start = (min.floor() as i32).max(-MAX_PERIODS);
+        let end = (max.ceil() as i32).min(start + MAX_PERIODS).max(1);
+        Some(Spread { reflect, start, end })
+    }
+}
+
+/// Write the function, coordinates and extension of an axial or radial
+/// shading. A spread gradient stitches its function together as often as it
+/// is repeated.
+pub(crate) fn write_shading(
+    shading: &mut Shading,
+    shading_type: ShadingType,
+    function: Ref,
+    coords: [f32; 6],
+    spread: Option<Spread>,
+) {
+    let len = if shading_type == ShadingType::Axial { 4 } else { 6 };
+    shading.extend([true, true]);
+
+    let Spread { reflect, start, end } = match spread {
+        Some(spread) => spread,
+        None => {
+            shading.function(function);
+            shading.coords(coords.into_iter().take(len));
+            return;
+        }
+    };
+
+    // Stretch the vector over all periods. The points and radii of both
+    // shading types lie at the same positions of the start and end.
+    let half = len / 2;
+    let lerp = |t: i32, i: usize| coords[i] + t as f32 * (coords[half + i] - coords[i]);
+    let first = (0 .. half).map(|i| lerp(start, i));
+    let last = (0 .. half).map(|i| lerp(end, i));
+    shading.coords(first.chain(last));
+    shading
+        .insert(Name(b"Domain"))
+        .array()
+        .items([start as f32, end as f32]);
+
+    let mut stitching = shading.insert(Name(b"Function")).start::<StitchingFunction>();
+    stitching.domain([start as f32, end as f32]);
+    stitching.functions((start .. end).map(|_| function));
+    stitching.bounds((start + 1 .. end).map(|t| t as f32));
+    stitching.encode((start .. end).flat_map(|t| {
+        if reflect && t.rem_euclid(2) == 1 {
+            [1.0, 0.0]
+        } else {
+            [0.0, 1.0]
+        }
+    }));
 }
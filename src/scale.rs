@@ -1,6 +1,6 @@
 //! Provide transformations between PDF and SVG coordinate systems.
 
-use usvg::{Align, AspectRatio, ViewBox};
+use usvg::{Align, AspectRatio, Transform, ViewBox};
 
 /// Convert point data between two coordinate systems.
 #[derive(Debug, Copy, Clone)]
@@ -12,12 +12,14 @@ pub struct CoordToPdf {
     height_y: f64,
     dpi: f64,
     matrix: [f64; 6],
+    precision: Option<u8>,
 }
 
 impl CoordToPdf {
     /// Create a new coordinate transform from the ViewBox of the SVG file to
     /// some viewport. A certain scaling mode can be forced by setting
-    /// `aspect_ratio`.
+    /// `aspect_ratio`, which only gives way to the one of the SVG if it uses
+    /// the `defer` keyword.
     pub fn new(
         viewport: (f64, f64),
         dpi: f64,
@@ -32,11 +34,7 @@ impl CoordToPdf {
         let original_ratio = viewbox.rect.width() / viewbox.rect.height();
         let viewport_ratio = viewport.0 / viewport.1;
 
-        let aspect = if let Some(aspect) = aspect_ratio {
-            if aspect.defer { viewbox.aspect } else { aspect }
-        } else {
-            viewbox.aspect
-        };
+        let aspect = resolve_aspect(aspect_ratio, viewbox.aspect);
 
         if aspect.slice == (original_ratio < viewport_ratio) {
             // Scale to fit width.
@@ -95,22 +93,71 @@ impl CoordToPdf {
             height_y: viewport.1,
             dpi,
             matrix: [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+            precision: None,
         }
     }
 
     /// Convert from SVG source coordinates to PDF coordinates.
     pub fn point(&self, point: (f64, f64)) -> (f32, f32) {
+        let (x, y) = self.precise_point(point);
+        (x as f32, y as f32)
+    }
+
+    /// Convert from SVG source coordinates to PDF coordinates without
+    /// narrowing the result to the precision of written operators, for
+    /// further computations.
+    pub fn precise_point(&self, point: (f64, f64)) -> (f64, f64) {
         let (x, y) = self.apply(point);
         (
-            self.px_to_pt(x * self.factor_x + self.offset_x),
-            self.px_to_pt(self.height_y - (y * self.factor_y + self.offset_y)),
+            self.round(self.pt(x * self.factor_x + self.offset_x)),
+            self.round(self.pt(self.height_y - (y * self.factor_y + self.offset_y))),
         )
     }
 
+    /// Move the viewport by the given offset in pixels within a larger page
+    /// with the given height.
+    pub fn place(&mut self, dx: f64, dy: f64, height: f64) {
+        self.offset_x += dx;
+        self.offset_y += dy;
+        self.height_y = height;
+    }
+
+    /// Scale the viewport along with its content by different factors
+    /// horizontally and vertically.
+    pub fn stretch(&mut self, sx: f64, sy: f64) {
+        self.factor_x *= sx;
+        self.offset_x *= sx;
+        self.factor_y *= sy;
+        self.offset_y *= sy;
+        self.height_y *= sy;
+    }
+
+    /// Round point coordinates to the given number of decimal places to
+    /// shorten the written operators or keep them precise if it is `None`.
+    pub fn set_precision(&mut self, precision: Option<u8>) {
+        self.precision = precision;
+    }
+
+    /// Round a coordinate according to the precision.
+    fn round(&self, value: f64) -> f64 {
+        match self.precision {
+            Some(places) => {
+                let factor = 10f64.powi(places as i32);
+                (value * factor).round() / factor
+            }
+            None => value,
+        }
+    }
+
     /// Convert from pixels to PDF points, disregarding any offsets or
     /// axis-specific scales.
     pub fn px_to_pt(&self, px: f64) -> f32 {
-        (px * 72.0 / self.dpi) as f32
+        self.pt(px) as f32
+    }
+
+    /// Convert from pixels to PDF points at full precision.
+    fn pt(&self, px: f64) -> f64 {
+        px * 72.0 / self.dpi
     }
 
     /// Get the offset from the X axis.
@@ -138,6 +185,73 @@ impl CoordToPdf {
         self.dpi
     }
 
+    /// Get the factor by which lengths in SVG source coordinates are scaled to
+    /// points on average, including the pre-transformation.
+    pub fn scale(&self) -> f64 {
+        let [a, b, c, d, _, _] = self.matrix;
+        let det = (a * d - b * c).abs() * self.factor_x * self.factor_y;
+        det.sqrt() * 72.0 / self.dpi
+    }
+
+    /// Get the factor by which lengths in a coordinate system that is
+    /// transformed by `transform` in SVG source coordinates are scaled to
+    /// points on average, along with whether they are scaled alike in all
+    /// directions.
+    pub fn length_scale(&self, transform: Transform) -> (f64, bool) {
+        let k = 72.0 / self.dpi;
+        let [a, b, c, d, _, _] = self.matrix;
+        let map = |x: f64, y: f64| {
+            let (x, y) = (
+                transform.a * x + transform.c * y,
+                transform.b * x + transform.d * y,
+            );
+            (
+                (a * x + b * y) * self.factor_x * k,
+                (c * x + d * y) * self.factor_y * k,
+            )
+        };
+
+        // The transformation is conformal if it maps the unit vectors to
+        // orthogonal vectors of the same length.
+        let (u, v) = (map(1.0, 0.0), map(0.0, 1.0));
+        let (uu, vv, uv) = (
+            u.0 * u.0 + u.1 * u.1,
+            v.0 * v.0 + v.1 * v.1,
+            u.0 * v.0 + u.1 * v.1,
+        );
+
+        let tolerance = 1e-6 * (uu + vv);
+        let uniform = (uu - vv).abs() <= tolerance && uv.abs() <= tolerance;
+        ((u.0 * v.1 - u.1 * v.0).abs().sqrt(), uniform)
+    }
+
+    /// Get the matrix that maps PDF coordinates written by this converter
+    /// without its pre-transformation into a coordinate system that is
+    /// transformed by `transform` in SVG source coordinates, followed by the
+    /// pre-transformation. PDF patterns need this because they are placed in
+    /// the default coordinate system.
+    pub fn transformed_matrix(&self, transform: Transform) -> [f64; 6] {
+        let k = 72.0 / self.dpi;
+        let (fx, fy, ox, oy) =
+            (self.factor_x, self.factor_y, self.offset_x, self.offset_y);
+        let [a, b, c, d, e, f] = self.matrix;
+
+        let mut ts =
+            Transform::new(fx * k, 0.0, 0.0, -fy * k, ox * k, (self.height_y - oy) * k);
+        ts.append(&Transform::new(a, c, b, d, e, f));
+        ts.append(&transform);
+        ts.append(&Transform::new(
+            1.0 / (fx * k),
+            0.0,
+            0.0,
+            -1.0 / (fy * k),
+            -ox / fx,
+            (self.height_y - oy) / fy,
+        ));
+
+        [ts.a, ts.b, ts.c, ts.d, ts.e, ts.f]
+    }
+
     /// Get the transformation matrix for this converter but without accounting
     /// for either DPI or that the PDF coordinate system is flipped. This is
     /// useful for converting between two SVG coordinate systems.
@@ -174,3 +288,28 @@ impl CoordToPdf {
         old
     }
 }
+
+/// The scaling mode of an SVG in a viewport whose referencing context, e.g. an
+/// `<image>` element or [`Options::aspect`](crate::Options::aspect), may
+/// override the SVG's own mode.
+///
+/// An override with the `defer` keyword only applies if the SVG has no mode of
+/// its own. usvg fills in the initial `xMidYMid meet` if the attribute is
+/// missing, so an SVG with exactly this mode is treated as having none.
+pub fn resolve_aspect(outer: Option<AspectRatio>, own: AspectRatio) -> AspectRatio {
+    match outer {
+        Some(outer) if outer.defer => {
+            let initial = AspectRatio {
+                defer: own.defer,
+                ..AspectRatio::default()
+            };
+            if own == initial {
+                AspectRatio { defer: false, ..outer }
+            } else {
+                own
+            }
+        }
+        Some(outer) => outer,
+        None => own,
+    }
+}
@@ -0,0 +1,26 @@
+//! Apply a user-provided style sheet to an SVG.
+
+use roxmltree::Document;
+
+/// Add a style sheet at the end of the root element of an SVG source string so
+/// that its rules take precedence over those of the document's own style sheets
+/// with the same specificity.
+///
+/// Returns `None` if the source is malformed or its root element has no
+/// content that could be styled.
+pub(crate) fn inject(src: &str, css: &str) -> Option<String> {
+    let doc = Document::parse(src).ok()?;
+    let range = doc.root_element().range();
+    let end = range.start + src[range.clone()].rfind("</")?;
+
+    // The style sheet is wrapped in a CDATA section so that it needs no
+    // escaping, except for the sequence that ends such a section.
+    let css = css.replace("]]>", "]]]]><![CDATA[>");
+    let mut out = String::with_capacity(src.len() + css.len() + 40);
+    out.push_str(&src[.. end]);
+    out.push_str("<style type=\"text/css\"><![CDATA[");
+    out.push_str(&css);
+    out.push_str("]]></style>");
+    out.push_str(&src[end ..]);
+    Some(out)
+}
@@ -0,0 +1,157 @@
+//! Resolve `<switch>` elements according to the preferences of the options.
+//!
+//! usvg renders the first child of a `<switch>` whose conditions pass, where
+//! any of the user's languages counts regardless of their order and only the
+//! features it implements itself are supported. To honor the order of the
+//! preferred languages and additional features and extensions, the variant is
+//! chosen here instead: the other children are removed from the source string
+//! before it is parsed and the conditions of the chosen one are dropped.
+
+use std::ops::Range;
+
+use roxmltree::{Document, Node};
+
+/// The SVG 1.1 feature strings that usvg supports.
+const FEATURES: &[&str] = &[
+    "SVGDOM-static",
+    "SVG-static",
+    "CoreAttribute",
+    "Structure",
+    "BasicStructure",
+    "ContainerAttribute",
+    "ConditionalProcessing",
+    "Image",
+    "Style",
+    "Shape",
+    "Text",
+    "BasicText",
+    "PaintAttribute",
+    "BasicPaintAttribute",
+    "OpacityAttribute",
+    "GraphicsAttribute",
+    "BasicGraphicsAttribute",
+    "Marker",
+    "Gradient",
+    "Pattern",
+    "Clip",
+    "BasicClip",
+    "Mask",
+    "Filter",
+    "BasicFilter",
+    "XlinkAttribute",
+];
+
+/// The prefix of SVG 1.1 feature strings.
+const FEATURE_PREFIX: &str = "http://www.w3.org/TR/SVG11/feature#";
+
+/// Rewrite an SVG source string such that each `<switch>` element only keeps
+/// the child that matches the most preferred of `languages`, where features
+/// and extensions in `supported` count as supported in addition to those of
+/// usvg. Children without a `systemLanguage` attribute only win if no child
+/// matches a preferred language. Returns `None` if the source is not valid XML
+/// or has no `<switch>` elements.
+pub(crate) fn resolve(
+    src: &str,
+    languages: &[String],
+    supported: &[String],
+) -> Option<String> {
+    if !src.contains("switch") {
+        return None;
+    }
+
+    let doc = Document::parse(src).ok()?;
+    let mut edits: Vec<(Range<usize>, bool)> = vec![];
+
+    for switch in doc.descendants().filter(|node| node.has_tag_name("switch")) {
+        let children: Vec<_> =
+            switch.children().filter(|node| node.is_element()).collect();
+        let chosen = children
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &child)| Some((rank(child, languages, supported)?, i)))
+            .min()
+            .map(|(_, i)| i);
+
+        for (i, child) in children.iter().enumerate() {
+            if Some(i) != chosen {
+                edits.push((child.range(), true));
+                continue;
+            }
+
+            for attr in child.attributes() {
+                if matches!(
+                    attr.name(),
+                    "systemLanguage" | "requiredFeatures" | "requiredExtensions"
+                ) {
+                    edits.push((attr.range(), false));
+                }
+            }
+        }
+    }
+
+    if edits.is_empty() {
+        return None;
+    }
+
+    // Edits within removed children are covered by their removal.
+    edits.sort_by_key(|(range, _)| (range.start, std::cmp::Reverse(range.end)));
+    let mut kept: Vec<Range<usize>> = vec![];
+    for (range, removal) in edits {
+        if kept.last().is_some_and(|last| range.start < last.end) {
+            continue;
+        }
+        if removal || !kept.iter().any(|last| last.contains(&range.start)) {
+            kept.push(range);
+        }
+    }
+
+    let mut out = src.to_string();
+    for range in kept.into_iter().rev() {
+        out.replace_range(range, "");
+    }
+
+    Some(out)
+}
+
+/// How well a child of a `<switch>` matches: the index of the most preferred
+/// language it is in, or the number of languages if it has no
+/// `systemLanguage`. Returns `None` if its conditions fail.
+fn rank(child: Node, languages: &[String], supported: &[String]) -> Option<usize> {
+    let is_supported = |token: &str| supported.iter().any(|s| s == token);
+
+    if let Some(extensions) = child.attribute("requiredExtensions") {
+        if !extensions.split_whitespace().all(is_supported) {
+            return None;
+        }
+    }
+
+    if let Some(features) = child.attribute("requiredFeatures") {
+        let builtin = |token: &str| {
+            token
+                .strip_prefix(FEATURE_PREFIX)
+                .is_some_and(|feature| FEATURES.contains(&feature))
+        };
+        if !features
+            .split_whitespace()
+            .all(|token| builtin(token) || is_supported(token))
+        {
+            return None;
+        }
+    }
+
+    let langs = match child.attribute("systemLanguage") {
+        Some(langs) => langs,
+        None => return Some(languages.len()),
+    };
+
+    // A language also matches the tags that it is a prefix of, like `en` does
+    // for `en-US`.
+    languages.iter().position(|preferred| {
+        langs.split(',').map(str::trim).any(|lang| {
+            lang == preferred
+                || lang
+                    .strip_prefix(preferred.as_str())
+                    .is_some_and(|rest| rest.starts_with('-'))
+        })
+    })
+}
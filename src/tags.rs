@@ -0,0 +1,380 @@
+//! Tag the content of pages with a structure tree for accessibility.
+//!
+//! usvg discards `<title>` and `<desc>` elements and `aria-label` attributes,
+//! so like the `data-*` attributes, the alternative text of elements is
+//! collected from the source string before it is parsed and looked up by
+//! element id during the conversion. Graphics elements below a labelled
+//! element are given an id if they have none.
+//!
+//! Each labelled element becomes a `Figure` and each text element a `Span` in
+//! the structure tree, nested like in the SVG. The top-level elements of a page
+//! are marked as the content of the structure element of their closest
+//! labelled ancestor, and everything else as an artifact. usvg only keeps the
+//! id of a text element if it becomes a single path, so text with differently
+//! styled spans is an artifact as well.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use pdf_writer::types::StructRole;
+use pdf_writer::writers::StructTreeRoot;
+use pdf_writer::{Content, Finish, Name, PdfWriter, Ref, TextStr};
+use roxmltree::{Document, Node as XmlNode};
+use usvg::{Node, NodeExt, NodeKind};
+
+use crate::animate::name_end;
+use crate::data::GRAPHICS_ELEMENTS;
+use crate::Context;
+
+/// The alternative text of the elements of a tree.
+#[derive(Debug, Default)]
+pub(crate) struct Labels {
+    /// The closest labelled element of each graphics element, keyed by id.
+    ids: HashMap<String, usize>,
+    /// The labelled elements in document order.
+    labels: Vec<Label>,
+}
+
+/// An element that becomes a structure element.
+#[derive(Debug)]
+struct Label {
+    /// Whether the element is a figure or text.
+    role: Role,
+    /// The alternative text from `aria-label`, `<title>` and `<desc>`.
+    alt: Option<String>,
+    /// The characters of a text element.
+    actual_text: Option<String>,
+    /// The closest labelled ancestor.
+    parent: Option<usize>,
+}
+
+/// The role of a structure element.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Role {
+    Figure,
+    Span,
+}
+
+impl Role {
+    fn name(self) -> Name<'static> {
+        match self {
+            Self::Figure => Name(b"Figure"),
+            Self::Span => Name(b"Span"),
+        }
+    }
+
+    fn struct_role(self) -> StructRole {
+        match self {
+            Self::Figure => StructRole::Figure,
+            Self::Span => StructRole::Span,
+        }
+    }
+}
+
+/// Collect the alternative text of the elements of an SVG source string.
+///
+/// Returns the source string with ids added to graphics elements that belong
+/// to a labelled element but have no id, if there are any, along with the
+/// labels.
+pub(crate) fn collect(src: &str) -> (Option<String>, Labels) {
+    let mut labels = Labels::default();
+    let doc = match Document::parse(src) {
+        Ok(doc) => doc,
+        Err(_) => return (None, labels),
+    };
+
+    // The labelled elements by the start of their source range.
+    let mut indices = HashMap::new();
+    let mut edits: Vec<(Range<usize>, String)> = vec![];
+    let mut next = 1;
+
+    for node in doc.descendants().filter(|node| node.is_element()) {
+        let parent = node
+            .ancestors()
+            .skip(1)
+            .find_map(|ancestor| indices.get(&ancestor.range().start).copied());
+
+        let text = node.has_tag_name("text");
+        let alt = alt_text(node);
+        if text || alt.is_some() {
+            indices.insert(node.range().start, labels.labels.len());
+            labels.labels.push(Label {
+                role: if text { Role::Span } else { Role::Figure },
+                alt,
+                actual_text: text.then(|| text_content(node)),
+                parent,
+            });
+        }
+
+        if !GRAPHICS_ELEMENTS.contains(&node.tag_name().name()) {
+            continue;
+        }
+
+        let label = match indices.get(&node.range().start).copied().or(parent) {
+            Some(label) => label,
+            None => continue,
+        };
+
+        let id = match node.attribute("id") {
+            Some(id) => id.to_string(),
+            None => {
+                let id = loop {
+                    let id = format!("svg2pdf-tag-{}", next);
+                    next += 1;
+                    if !doc.descendants().any(|node| node.attribute("id") == Some(&id)) {
+                        break id;
+                    }
+                };
+
+                let pos = name_end(src, node.range().start);
+                edits.push((pos .. pos, format!(" id=\"{}\"", id)));
+                id
+            }
+        };
+
+        labels.ids.insert(id, label);
+    }
+
+    if edits.is_empty() {
+        return (None, labels);
+    }
+
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+
+    let mut out = src.to_string();
+    for (range, text) in edits {
+        out.replace_range(range, &text);
+    }
+
+    (Some(out), labels)
+}
+
+/// The alternative text of an element: its `aria-label` or `<title>`, followed
+/// by its `<desc>` on a new line.
+fn alt_text(node: XmlNode) -> Option<String> {
+    let child = |name| {
+        node.children()
+            .find(|child| child.has_tag_name(name))
+            .map(text_content)
+            .filter(|text| !text.is_empty())
+    };
+
+    let label = node
+        .attribute("aria-label")
+        .map(|label| label.trim().to_string())
+        .filter(|label| !label.is_empty())
+        .or_else(|| child("title"));
+
+    match (label, child("desc")) {
+        (Some(label), Some(desc)) => Some(format!("{label}\n{desc}")),
+        (label, desc) => label.or(desc),
+    }
+}
+
+/// The text within an element, with whitespace collapsed and without the text
+/// of its `<title>` and `<desc>` elements.
+fn text_content(node: XmlNode) -> String {
+    let text: String = node
+        .descendants()
+        .filter(|node| node.is_text())
+        .filter(|text| {
+            !text.ancestors().any(|ancestor| {
+                ancestor != node
+                    && (ancestor.has_tag_name("title") || ancestor.has_tag_name("desc"))
+            })
+        })
+        .filter_map(|text| text.text())
+        .collect();
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl Labels {
+    /// The labelled element that a node belongs to. A group that contains
+    /// the content of multiple labelled elements belongs to their closest
+    /// common ancestor, or to the first of them if there is none.
+    fn find(&self, node: &Node) -> Option<usize> {
+        let mut found = None;
+        for descendant in node.descendants() {
+            if let Some(&label) = self.ids.get(&*descendant.id()) {
+                found = Some(match found {
+                    Some(common) => self.common(common, label).unwrap_or(common),
+                    None => label,
+                });
+            }
+        }
+
+        found
+    }
+
+    /// The closest common ancestor of two labelled elements, including
+    /// themselves.
+    fn common(&self, a: usize, b: usize) -> Option<usize> {
+        let mut ancestors = vec![];
+        let mut current = Some(a);
+        while let Some(label) = current {
+            ancestors.push(label);
+            current = self.labels[label].parent;
+        }
+
+        let mut current = Some(b);
+        while let Some(label) = current {
+            if ancestors.contains(&label) {
+                return Some(label);
+            }
+            current = self.labels[label].parent;
+        }
+
+        None
+    }
+}
+
+/// Start the marked-content sequence of a top-level element of a tagged page,
+/// either as the content of its labelled element or as an artifact. Returns
+/// whether a sequence was started.
+pub(crate) fn begin(node: &Node, content: &mut Content, ctx: &mut Context) -> bool {
+    if !matches!(
+        *node.borrow(),
+        NodeKind::Path(_) | NodeKind::Group(_) | NodeKind::Image(_)
+    ) {
+        return false;
+    }
+
+    let labels = ctx.source.map(|source| &source.labels);
+    let marks = match ctx.tags.as_mut() {
+        Some(marks) => marks,
+        None => return false,
+    };
+
+    match labels.and_then(|labels| Some((labels, labels.find(node)?))) {
+        Some((labels, label)) => {
+            content
+                .begin_marked_content_with_properties(labels.labels[label].role.name())
+                .properties_direct()
+                .identify(marks.len() as i32);
+            marks.push(label);
+        }
+        None => {
+            content.begin_marked_content(Name(b"Artifact"));
+        }
+    }
+
+    true
+}
+
+/// The structure tree of a document, written page by page.
+pub(crate) struct StructTree {
+    /// The structure element for the whole document.
+    document: Ref,
+    /// The structure elements below the document element.
+    kids: Vec<Ref>,
+    /// For each page, an array with the structure element of each of its
+    /// marked-content ids.
+    parents: Vec<Ref>,
+}
+
+/// A kid of a structure element.
+enum Kid {
+    Content(i32),
+    Element(usize),
+}
+
+impl StructTree {
+    /// Create a structure tree whose document element has the given id.
+    pub fn new(document: Ref) -> Self {
+        Self { document, kids: vec![], parents: vec![] }
+    }
+
+    /// Write the structure elements for the marked content of a page, where
+    /// `marks` holds the labelled element of each marked-content id. Returns
+    /// the key of the page in the parent tree.
+    pub fn write_page(
+        &mut self,
+        writer: &mut PdfWriter,
+        page: Ref,
+        labels: &Labels,
+        marks: &[usize],
+        ctx: &mut Context,
+    ) -> i32 {
+        // Each labelled element on the page lists its marked content and
+        // nested elements in order of appearance.
+        let mut refs = HashMap::new();
+        let mut order = vec![];
+        let mut kids: HashMap<usize, Vec<Kid>> = HashMap::new();
+        let mut top = vec![];
+        for (mcid, &label) in marks.iter().enumerate() {
+            kids.entry(label).or_default().push(Kid::Content(mcid as i32));
+
+            let mut child = label;
+            while !refs.contains_key(&child) {
+                refs.insert(child, ctx.alloc_ref());
+                order.push(child);
+                match labels.labels[child].parent {
+                    Some(parent) => {
+                        kids.entry(parent).or_default().push(Kid::Element(child));
+                        child = parent;
+                    }
+                    None => {
+                        top.push(child);
+                        break;
+                    }
+                }
+            }
+        }
+
+        for &label in &order {
+            let id = refs[&label];
+            let Label { role, alt, actual_text, parent } = &labels.labels[label];
+            let mut element = writer.struct_element(id);
+            element.kind(role.struct_role());
+            element.parent(parent.map_or(self.document, |parent| refs[&parent]));
+            element.page(page);
+            if let Some(alt) = alt {
+                element.alt(TextStr(alt));
+            }
+            if let Some(actual_text) = actual_text {
+                element.actual_text(TextStr(actual_text));
+            }
+
+            let mut children = element.children();
+            for kid in kids.get(&label).into_iter().flatten() {
+                match *kid {
+                    Kid::Content(mcid) => children.marked_content_id(mcid),
+                    Kid::Element(child) => children.struct_element(refs[&child]),
+                };
+            }
+        }
+
+        self.kids.extend(top.iter().map(|label| refs[label]));
+
+        let parents = ctx.alloc_ref();
+        writer
+            .indirect(parents)
+            .array()
+            .items(marks.iter().map(|label| refs[label]));
+        self.parents.push(parents);
+        self.parents.len() as i32 - 1
+    }
+
+    /// Write the structure tree root and the document element.
+    pub fn finish(self, writer: &mut PdfWriter, root: Ref) {
+        let mut tree = writer.indirect(root).start::<StructTreeRoot>();
+        tree.child(self.document);
+        let mut parent_tree = tree.parent_tree();
+        let mut nums = parent_tree.nums();
+        for (key, &parents) in self.parents.iter().enumerate() {
+            nums.insert(key as i32, parents);
+        }
+        nums.finish();
+        parent_tree.finish();
+        tree.parent_tree_next_key(self.parents.len() as i32);
+        tree.finish();
+
+        writer
+            .struct_element(self.document)
+            .kind(StructRole::Document)
+            .parent(root)
+            .children()
+            .items(self.kids.iter().copied());
+    }
+}
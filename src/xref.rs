@@ -0,0 +1,141 @@
+//! Pack the objects of a finished PDF file into object streams.
+//!
+//! PDF 1.5 allows to store objects that are not streams themselves in object
+//! streams, which are compressed as a whole, and to replace the
+//! cross-reference table with a compressed cross-reference stream. The writer
+//! only produces the classic format, so its output is split into objects along
+//! the cross-reference table and written anew. Streams are copied as they are.
+
+use std::io::Write;
+
+/// The maximum number of objects in an object stream, so that readers don't
+/// have to decompress a huge stream to get at a single object.
+const OBJECTS_PER_STREAM: usize = 100;
+
+/// Pack the objects of a PDF file written by `pdf-writer` into object streams
+/// with a cross-reference stream, compressing both with `deflate`, which
+/// returns `None` if they should stay uncompressed. Returns `None` if the file
+/// can't be read.
+pub(crate) fn pack(
+    pdf: &[u8],
+    deflate: impl Fn(&[u8]) -> Option<Vec<u8>>,
+) -> Option<Vec<u8>> {
+    let start = pdf.windows(9).rposition(|w| w == b"startxref")?;
+    let tail = std::str::from_utf8(&pdf[start + 9 ..]).ok()?;
+    let xref_offset: usize = tail.trim().trim_end_matches("%%EOF").trim().parse().ok()?;
+
+    // The table consists of a single section with entries of 20 bytes.
+    let table = pdf.get(xref_offset .. start)?.strip_prefix(b"xref\n0 ")?;
+    let newline = table.iter().position(|&b| b == b'\n')?;
+    let size: usize = std::str::from_utf8(&table[.. newline]).ok()?.parse().ok()?;
+    let entries = table.get(newline + 1 .. newline + 1 + size * 20)?;
+    let trailer = std::str::from_utf8(&table[newline + 1 + size * 20 ..]).ok()?;
+
+    let mut offsets = vec![];
+    for (id, entry) in entries.chunks_exact(20).enumerate() {
+        if entry[17] == b'n' {
+            let offset: usize = std::str::from_utf8(&entry[.. 10]).ok()?.parse().ok()?;
+            offsets.push((offset, id));
+        }
+    }
+
+    offsets.sort_unstable();
+    let first = offsets.first()?.0;
+
+    let mut out = Vec::with_capacity(pdf.len());
+    out.extend(pdf.get(.. first)?);
+
+    // Each entry consists of the type, the offset or the object stream, and
+    // the generation or the index within the object stream.
+    let mut xref = vec![(0, 0, 0); size];
+    xref[0].2 = u16::MAX as usize;
+
+    let mut packed = vec![];
+    for (i, &(offset, id)) in offsets.iter().enumerate() {
+        let end = offsets.get(i + 1).map_or(xref_offset, |&(next, _)| next);
+        let object = pdf.get(offset .. end)?;
+        let body = object
+            .strip_prefix(format!("{id} 0 obj\n").as_bytes())?
+            .strip_suffix(b"\nendobj\n\n")?;
+
+        if body.ends_with(b"\nendstream") {
+            xref[id] = (1, out.len(), 0);
+            out.extend(object);
+        } else {
+            packed.push((id, body));
+        }
+    }
+
+    for chunk in packed.chunks(OBJECTS_PER_STREAM) {
+        let stream_id = xref.len();
+        let mut index = String::new();
+        let mut data = vec![];
+        for (i, &(id, body)) in chunk.iter().enumerate() {
+            xref[id] = (2, stream_id, i);
+            if !index.is_empty() {
+                index.push(' ');
+            }
+            index += &format!("{id} {}", data.len());
+            data.extend(body);
+            data.push(b'\n');
+        }
+
+        index.push('\n');
+        let mut content = index.into_bytes();
+        let first = content.len();
+        content.extend(data);
+
+        xref.push((1, out.len(), 0));
+        let extra = format!("  /Type /ObjStm\n  /N {}\n  /First {first}\n", chunk.len());
+        write_stream(&mut out, stream_id, &extra, &content, &deflate);
+    }
+
+    // The cross-reference stream lists itself as well.
+    let xref_id = xref.len();
+    let xref_offset = out.len();
+    xref.push((1, xref_offset, 0));
+
+    let max = xref.iter().map(|&(_, field, _)| field).max().unwrap_or(0);
+    let width = ((usize::BITS - max.leading_zeros()) as usize).div_ceil(8).max(1);
+    let mut data = Vec::with_capacity(xref.len() * (width + 3));
+    for &(kind, field, generation) in &xref {
+        data.push(kind);
+        data.extend(&field.to_be_bytes()[std::mem::size_of::<usize>() - width ..]);
+        data.extend((generation as u16).to_be_bytes());
+    }
+
+    let mut extra = format!(
+        "  /Type /XRef\n  /Size {}\n  /W [1 {width} 2]\n",
+        xref.len()
+    );
+    for line in trailer.lines() {
+        let line = line.trim_start();
+        if line.starts_with("/Root ") || line.starts_with("/Info ") {
+            extra += &format!("  {line}\n");
+        }
+    }
+
+    write_stream(&mut out, xref_id, &extra, &data, &deflate);
+    write!(out, "startxref\n{xref_offset}\n%%EOF").unwrap();
+    Some(out)
+}
+
+/// Write an indirect stream object whose dictionary holds the given lines in
+/// addition to its length and filter.
+fn write_stream(
+    out: &mut Vec<u8>,
+    id: usize,
+    extra: &str,
+    data: &[u8],
+    deflate: impl Fn(&[u8]) -> Option<Vec<u8>>,
+) {
+    let compressed = deflate(data);
+    let data = compressed.as_deref().unwrap_or(data);
+    write!(out, "{id} 0 obj\n<<\n{extra}  /Length {}\n", data.len()).unwrap();
+    if compressed.is_some() {
+        out.extend(b"  /Filter /FlateDecode\n");
+    }
+    out.extend(b">>\nstream\n");
+    out.extend(data);
+    out.extend(b"\nendstream\nendobj\n\n");
+}